@@ -4,14 +4,16 @@ use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
 
-use args::{CliArgs, Commands};
+use args::{CliArgs, Commands, DeterminismArgs, RetrievalArgs};
 use clap::Parser;
 
 use zinnia_runtime::anyhow::{Context, Result};
 use zinnia_runtime::fmt_errors::format_js_error;
 use zinnia_runtime::{
     any_and_jserrorbox_downcast_ref, colors, lassie, lassie_config, resolve_path, run_js_module,
-    AnyError, BootstrapOptions, ConsoleReporter, CoreError,
+    ActivityLimits, AnyError, BootstrapOptions, CarDirectoryBackend, ConsoleReporter, CoreError,
+    DisabledBackend, GatewayBackend, LassieBackend, NetworkFixture, ProxyConfig, RetrievalBackend,
+    RetrievalConfig, TelemetryOptions, TlsConfig,
 };
 
 #[tokio::main(flavor = "current_thread")]
@@ -30,8 +32,46 @@ async fn main() {
 async fn main_impl() -> Result<()> {
     let cli_args = CliArgs::parse_from(std::env::args());
     match cli_args.command {
-        Commands::Run { file } => {
-            run_module(file).await?;
+        Commands::Run {
+            file,
+            otel_endpoint,
+            otel_console,
+            max_activities_per_minute,
+            retrieval,
+            tls,
+            proxy,
+            fixture,
+            determinism,
+        } => {
+            let telemetry = otel_endpoint.map(|endpoint| TelemetryOptions {
+                export_console: otel_console,
+                ..TelemetryOptions::new(endpoint, file.clone())
+            });
+            let tls = tls.tls_config();
+            tls.validate().context("Invalid TLS configuration")?;
+            let proxy = proxy.proxy_config();
+            if let Some(proxy) = &proxy {
+                proxy.validate().context("Invalid proxy configuration")?;
+            }
+            let network_fixture = fixture.network_fixture();
+            let mut reporter = ConsoleReporter::new(Duration::from_millis(500));
+            if let Some(max_activities) = max_activities_per_minute {
+                reporter = reporter.with_activity_limits(ActivityLimits {
+                    max_activities,
+                    interval: Duration::from_secs(60),
+                });
+            }
+            run_module(
+                file,
+                reporter,
+                telemetry,
+                retrieval,
+                tls,
+                proxy,
+                network_fixture,
+                determinism,
+            )
+            .await?;
 
             Ok(())
         }
@@ -42,45 +82,58 @@ async fn main_impl() -> Result<()> {
 struct RunOutput {
     module_output: (),
     // for testing
-    lassie_daemon: Arc<lassie::Daemon>,
+    lassie_daemon: Option<Arc<lassie::Daemon>>,
 }
 
-async fn run_module(file: String) -> Result<RunOutput> {
+#[allow(clippy::too_many_arguments)]
+async fn run_module(
+    file: String,
+    reporter: ConsoleReporter,
+    telemetry: Option<TelemetryOptions>,
+    retrieval_args: RetrievalArgs,
+    tls: TlsConfig,
+    proxy: Option<ProxyConfig>,
+    network_fixture: Option<NetworkFixture>,
+    determinism: DeterminismArgs,
+) -> Result<RunOutput> {
     let main_module = resolve_path(
         &file,
         &std::env::current_dir().context("unable to get current working directory")?,
     )?;
 
-    let lassie_daemon = Arc::new(
-        lassie::Daemon::start(lassie::DaemonConfig {
-            // This configuration applies to `zinnia` CLI only. The `zinniad` daemon running
-            // inside Station uses a different temp_dir config based on the env var
-            // `CACHE_ROOT` provided by the Station.
-            //
-            // By default, Lassie stores its temporary files in the system temp directory.
-            // That's good enough for now. We can improve this later based on user feedback,
-            // for example:
-            // - we can honour CACHE_ROOT
-            // - we can default to something like
-            //   `~/.cache/zinnia/lassie` on Unix,
-            //   `%APPLOCALDATA%\zinnia\lassie' on Windows.
-            //
-            // Important: if we tell Lassie to use a specific temp dir that's not
-            // automatically cleaned by the operating system, we will need to clean any
-            // leftover files ourselves. See the GH issue for deleting leftover files
-            // when `zinniad` starts: https://github.com/filecoin-station/zinnia/issues/245
-            temp_dir: None,
-            ..lassie_config()
-        })
-        .context("cannot initialize the IPFS retrieval client Lassie")?,
-    );
+    let retrieval = retrieval_args.retrieval_config();
+    retrieval
+        .validate()
+        .context("Invalid IPFS retrieval configuration")?;
+
+    let mut lassie_daemon = None;
+    let retrieval_backend: Arc<dyn RetrievalBackend> =
+        if matches!(network_fixture, Some(NetworkFixture::Replay(_))) {
+            // IPFS retrievals are replayed from the fixture too
+            Arc::new(DisabledBackend)
+        } else if let Some(url) = retrieval_args.ipfs_gateway {
+            Arc::new(GatewayBackend::new(url))
+        } else if let Some(dir) = retrieval_args.ipfs_car_dir {
+            Arc::new(CarDirectoryBackend::new(dir))
+        } else {
+            let daemon = Arc::new(start_lassie(&retrieval)?);
+            lassie_daemon = Some(Arc::clone(&daemon));
+            Arc::new(LassieBackend::new(daemon))
+        };
 
     let runtime_config = BootstrapOptions {
         zinnia_version: env!("CARGO_PKG_VERSION"),
+        telemetry,
+        retrieval,
+        retrieval_backend,
+        tls,
+        proxy,
+        network_fixture,
+        rng_seed: determinism.seed,
+        virtual_clock_start: determinism.virtual_clock_start(),
         ..BootstrapOptions::new(
             format!("zinnia/{}", env!("CARGO_PKG_VERSION")),
-            Rc::new(ConsoleReporter::new(Duration::from_millis(500))),
-            Arc::clone(&lassie_daemon),
+            Rc::new(reporter),
             None,
         )
     };
@@ -94,6 +147,30 @@ async fn run_module(file: String) -> Result<RunOutput> {
     })
 }
 
+fn start_lassie(retrieval: &RetrievalConfig) -> Result<lassie::Daemon> {
+    lassie::Daemon::start(lassie::DaemonConfig {
+        // This configuration applies to `zinnia` CLI only. The `zinniad` daemon running
+        // inside Station uses a different temp_dir config based on the env var
+        // `CACHE_ROOT` provided by the Station.
+        //
+        // By default, Lassie stores its temporary files in the system temp directory.
+        // That's good enough for now. We can improve this later based on user feedback,
+        // for example:
+        // - we can honour CACHE_ROOT
+        // - we can default to something like
+        //   `~/.cache/zinnia/lassie` on Unix,
+        //   `%APPLOCALDATA%\zinnia\lassie' on Windows.
+        //
+        // Important: if we tell Lassie to use a specific temp dir that's not
+        // automatically cleaned by the operating system, we will need to clean any
+        // leftover files ourselves. See the GH issue for deleting leftover files
+        // when `zinniad` starts: https://github.com/filecoin-station/zinnia/issues/245
+        temp_dir: None,
+        ..lassie_config(retrieval)
+    })
+    .context("cannot initialize the IPFS retrieval client Lassie")
+}
+
 // Inspired by exit_for_error from Deno's `cli/main.rs`
 // https://github.com/denoland/deno/blob/main/cli/main.rs
 
@@ -133,10 +210,19 @@ mod tests {
             .write_str("/* no-op */")
             .expect("cannot write to dummy.js");
 
-        let RunOutput { lassie_daemon, .. } =
-            run_module(mod_js.path().to_string_lossy().to_string())
-                .await
-                .expect("cannot run dummy.js");
+        let RunOutput { lassie_daemon, .. } = run_module(
+            mod_js.path().to_string_lossy().to_string(),
+            ConsoleReporter::new(Duration::from_millis(500)),
+            None,
+            RetrievalArgs::default(),
+            TlsConfig::default(),
+            None,
+            None,
+            DeterminismArgs::default(),
+        )
+        .await
+        .expect("cannot run dummy.js");
+        let lassie_daemon = lassie_daemon.expect("Lassie should be the default backend");
 
         assert!(
             lassie_daemon.access_token().is_some(),
@@ -0,0 +1,745 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, FromArgMatches};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use zinnia_runtime::anyhow::{anyhow, Context, Result};
+use zinnia_runtime::{ClientCertConfig, ProxyConfig, RetrievalConfig, TlsConfig};
+
+use crate::args::{CliArgs, MAX_JOB_HISTORY_DAYS, MAX_JOB_HISTORY_HOURS};
+
+// How often to check whether the config file was modified
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The content of the configuration file in the TOML format. All fields are optional, values
+/// provided via CLI flags and environment variables take precedence.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub wallet_address: Option<String>,
+    pub station_id: Option<String>,
+    pub state_root: Option<String>,
+    pub cache_root: Option<String>,
+    pub shutdown_timeout_secs: Option<u64>,
+
+    #[serde(default)]
+    pub reporter: ReporterConfig,
+
+    #[serde(default)]
+    pub lassie: LassieConfig,
+
+    #[serde(default)]
+    pub tls: TlsFileConfig,
+
+    #[serde(default)]
+    pub proxy: ProxyFileConfig,
+
+    /// Modules to run, defined as `[[module]]` tables. We support at most one module for now.
+    #[serde(default, rename = "module")]
+    pub modules: Vec<ModuleEntry>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReporterConfig {
+    pub max_activities_per_minute: Option<u32>,
+    pub log_dir: Option<String>,
+    pub log_max_size_mb: Option<u64>,
+    pub log_max_age_hours: Option<u64>,
+    pub log_max_files: Option<usize>,
+    pub log_compress: Option<bool>,
+    pub job_history_hours: Option<u32>,
+    pub job_history_days: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LassieConfig {
+    pub max_blocks: Option<u64>,
+    pub provider_timeout_secs: Option<u64>,
+    pub global_timeout_secs: Option<u64>,
+    pub max_concurrent_retrievals: Option<u32>,
+    pub protocols: Option<Vec<String>>,
+    pub providers: Option<Vec<String>>,
+    pub denied_providers: Option<Vec<String>>,
+    pub temp_max_size_mb: Option<u64>,
+    pub temp_max_age_mins: Option<u64>,
+    pub min_free_disk_space_mb: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFileConfig {
+    pub ca_cert_files: Option<Vec<PathBuf>>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub unsafely_ignore_certificate_errors: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyFileConfig {
+    pub url: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleEntry {
+    /// Path to the main JS file, relative to the directory containing the config file
+    pub path: String,
+    /// Module name used in events & metrics, defaults to the path without the `.js` extension
+    pub name: Option<String>,
+    /// Overrides the global `reporter.max_activities_per_minute` setting
+    pub max_activities_per_minute: Option<u32>,
+    /// Hostnames the module can connect to, e.g. `example.com` or `*.example.com`
+    pub allow_net: Option<Vec<String>>,
+}
+
+/// Everything we need to know to run a module. When any of these values change, we must restart
+/// the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleConfig {
+    pub name: String,
+    /// Path to the main JS file
+    pub file: PathBuf,
+    pub max_activities_per_minute: u32,
+    pub allow_net: Option<Vec<String>>,
+}
+
+impl ModuleConfig {
+    /// Configuration of a module specified as a CLI argument.
+    pub fn from_cli_arg(file: &str, args: &CliArgs) -> Self {
+        // TODO: configurable module name and version
+        // https://github.com/filecoin-station/zinnia/issues/147
+        Self {
+            name: file.trim_end_matches(".js").into(),
+            file: file.into(),
+            max_activities_per_minute: args.max_activities_per_minute,
+            allow_net: None,
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Cannot parse config file {}", path.display()))
+    }
+
+    // Settings we cannot change while zinniad is running
+    fn static_settings(&self) -> ConfigFile {
+        ConfigFile {
+            wallet_address: None,
+            modules: vec![],
+            reporter: ReporterConfig {
+                max_activities_per_minute: None,
+                ..self.reporter.clone()
+            },
+            ..self.clone()
+        }
+    }
+}
+
+/// The configuration resolved from CLI flags, environment variables and the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub args: CliArgs,
+    pub file: Option<ConfigFile>,
+    pub modules: Vec<ModuleConfig>,
+    /// CLI flags & env vars, we need them to resolve the configuration after the file changes
+    pub matches: ArgMatches,
+}
+
+impl Config {
+    /// Resolve the configuration. Values provided via CLI flags or environment variables take
+    /// precedence over the config file, the config file takes precedence over default values.
+    /// Modules provided as CLI arguments replace modules listed in the config file.
+    pub fn resolve(matches: &ArgMatches) -> Result<Self> {
+        let mut args = CliArgs::from_arg_matches(matches)?;
+        let file = match &args.config {
+            None => None,
+            Some(path) => Some(ConfigFile::load(Path::new(path))?),
+        };
+        if let Some(file) = &file {
+            apply_config_file(&mut args, matches, file);
+        }
+
+        let modules = match &file {
+            Some(file) if args.files.is_empty() => {
+                let config_dir = args
+                    .config
+                    .as_deref()
+                    .and_then(|path| Path::new(path).parent())
+                    .unwrap_or_else(|| Path::new(""));
+                file.modules
+                    .iter()
+                    .map(|entry| ModuleConfig {
+                        name: entry
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| entry.path.trim_end_matches(".js").to_string()),
+                        file: config_dir.join(&entry.path),
+                        max_activities_per_minute: entry
+                            .max_activities_per_minute
+                            .unwrap_or(args.max_activities_per_minute),
+                        allow_net: entry.allow_net.clone(),
+                    })
+                    .collect()
+            }
+            _ => args
+                .files
+                .iter()
+                .map(|file| ModuleConfig::from_cli_arg(file, &args))
+                .collect(),
+        };
+
+        // Reject the config file at startup and when reloading it, instead of running only some
+        // of the modules
+        if modules.len() > 1 {
+            return Err(anyhow!(
+                "We do not yet support running more than one module, found {} modules.",
+                modules.len()
+            ));
+        }
+
+        if args.wallet_address.is_none() {
+            return Err(anyhow!(
+                "Missing wallet address. Use --wallet-address, FIL_WALLET_ADDRESS or `wallet_address` in the config file."
+            ));
+        }
+        if args.station_id.is_none() {
+            return Err(anyhow!(
+                "Missing station ID. Use --station-id, STATION_ID or `station_id` in the config file."
+            ));
+        }
+
+        // CLI flags are checked by clap, but values from the config file are not
+        if !(1..=MAX_JOB_HISTORY_HOURS).contains(&args.job_history_hours) {
+            return Err(anyhow!(
+                "Invalid job history configuration: `job_history_hours` must be between 1 and {MAX_JOB_HISTORY_HOURS}."
+            ));
+        }
+        if !(1..=MAX_JOB_HISTORY_DAYS).contains(&args.job_history_days) {
+            return Err(anyhow!(
+                "Invalid job history configuration: `job_history_days` must be between 1 and {MAX_JOB_HISTORY_DAYS}."
+            ));
+        }
+
+        retrieval_config(&args)
+            .validate()
+            .context("Invalid IPFS retrieval configuration")?;
+        tls_config(&args)
+            .and_then(|tls| tls.validate())
+            .context("Invalid TLS configuration")?;
+        if let Some(proxy) = proxy_config(&args) {
+            proxy.validate().context("Invalid proxy configuration")?;
+        }
+
+        Ok(Self {
+            args,
+            file,
+            modules,
+            matches: matches.clone(),
+        })
+    }
+
+    pub fn wallet_address(&self) -> &str {
+        self.args.wallet_address.as_deref().unwrap_or_default()
+    }
+
+    pub fn station_id(&self) -> &str {
+        self.args.station_id.as_deref().unwrap_or_default()
+    }
+
+    pub fn retrieval(&self) -> RetrievalConfig {
+        retrieval_config(&self.args)
+    }
+
+    pub fn tls(&self) -> TlsConfig {
+        // `resolve` checked the configuration
+        tls_config(&self.args).unwrap_or_default()
+    }
+
+    pub fn proxy(&self) -> Option<ProxyConfig> {
+        proxy_config(&self.args)
+    }
+}
+
+fn proxy_config(args: &CliArgs) -> Option<ProxyConfig> {
+    ProxyConfig::with_env_defaults(args.proxy.clone(), args.no_proxy.clone())
+}
+
+fn tls_config(args: &CliArgs) -> Result<TlsConfig> {
+    let client_cert = match (&args.client_cert, &args.client_key) {
+        (None, None) => None,
+        (Some(cert_chain_file), Some(private_key_file)) => Some(ClientCertConfig {
+            cert_chain_file: cert_chain_file.clone(),
+            private_key_file: private_key_file.clone(),
+        }),
+        _ => {
+            return Err(anyhow!(
+                "The client certificate and the private key must be configured together."
+            ))
+        }
+    };
+    Ok(TlsConfig {
+        ca_cert_files: args.ca_cert.clone(),
+        client_cert,
+        unsafely_ignore_certificate_errors: args.unsafely_ignore_certificate_errors.clone(),
+    })
+}
+
+fn retrieval_config(args: &CliArgs) -> RetrievalConfig {
+    RetrievalConfig {
+        provider_timeout: Some(Duration::from_secs(args.lassie_provider_timeout_secs)),
+        global_timeout: Some(Duration::from_secs(args.lassie_global_timeout_secs)),
+        max_blocks: args.lassie_max_blocks,
+        max_concurrent_retrievals: args.max_concurrent_retrievals,
+        protocols: args.lassie_protocols.clone(),
+        providers: args.lassie_providers.clone(),
+        denied_providers: args.lassie_denied_providers.clone(),
+    }
+}
+
+fn apply_config_file(args: &mut CliArgs, matches: &ArgMatches, file: &ConfigFile) {
+    let explicit = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    macro_rules! apply {
+        ($field:expr, $id:literal, $value:expr) => {
+            if !explicit($id) {
+                if let Some(value) = &$value {
+                    $field = value.clone().into();
+                }
+            }
+        };
+    }
+
+    apply!(args.wallet_address, "FIL ADDRESS", file.wallet_address);
+    apply!(args.station_id, "STATION ID", file.station_id);
+    apply!(args.state_root, "LOCAL STATE DIR PATH", file.state_root);
+    apply!(args.cache_root, "CACHE DIR PATH", file.cache_root);
+    apply!(
+        args.shutdown_timeout_secs,
+        "SHUTDOWN TIMEOUT",
+        file.shutdown_timeout_secs
+    );
+
+    let reporter = &file.reporter;
+    apply!(
+        args.max_activities_per_minute,
+        "MAX ACTIVITIES",
+        reporter.max_activities_per_minute
+    );
+    apply!(args.log_dir, "LOG DIR PATH", reporter.log_dir);
+    apply!(
+        args.log_max_size_mb,
+        "LOG MAX SIZE",
+        reporter.log_max_size_mb
+    );
+    apply!(
+        args.log_max_age_hours,
+        "LOG MAX AGE",
+        reporter.log_max_age_hours
+    );
+    apply!(args.log_max_files, "LOG MAX FILES", reporter.log_max_files);
+    apply!(args.log_compress, "LOG COMPRESS", reporter.log_compress);
+    apply!(
+        args.job_history_hours,
+        "JOB HISTORY HOURS",
+        reporter.job_history_hours
+    );
+    apply!(
+        args.job_history_days,
+        "JOB HISTORY DAYS",
+        reporter.job_history_days
+    );
+
+    let tls = &file.tls;
+    apply!(args.ca_cert, "CA CERT", tls.ca_cert_files);
+    apply!(args.client_cert, "CLIENT CERT", tls.client_cert);
+    apply!(args.client_key, "CLIENT KEY", tls.client_key);
+    apply!(
+        args.unsafely_ignore_certificate_errors,
+        "UNSAFELY IGNORE CERTIFICATE ERRORS",
+        tls.unsafely_ignore_certificate_errors
+    );
+
+    let proxy = &file.proxy;
+    apply!(args.proxy, "PROXY URL", proxy.url);
+    apply!(args.no_proxy, "NO PROXY", proxy.no_proxy);
+
+    let lassie = &file.lassie;
+    apply!(
+        args.lassie_provider_timeout_secs,
+        "LASSIE PROVIDER TIMEOUT",
+        lassie.provider_timeout_secs
+    );
+    apply!(
+        args.lassie_global_timeout_secs,
+        "LASSIE GLOBAL TIMEOUT",
+        lassie.global_timeout_secs
+    );
+    apply!(
+        args.lassie_max_blocks,
+        "LASSIE MAX BLOCKS",
+        lassie.max_blocks
+    );
+    apply!(
+        args.max_concurrent_retrievals,
+        "MAX CONCURRENT RETRIEVALS",
+        lassie.max_concurrent_retrievals
+    );
+    apply!(args.lassie_protocols, "LASSIE PROTOCOLS", lassie.protocols);
+    apply!(args.lassie_providers, "LASSIE PROVIDERS", lassie.providers);
+    apply!(
+        args.lassie_denied_providers,
+        "LASSIE DENIED PROVIDERS",
+        lassie.denied_providers
+    );
+    apply!(
+        args.lassie_temp_max_size_mb,
+        "LASSIE TEMP MAX SIZE",
+        lassie.temp_max_size_mb
+    );
+    apply!(
+        args.lassie_temp_max_age_mins,
+        "LASSIE TEMP MAX AGE",
+        lassie.temp_max_age_mins
+    );
+    apply!(
+        args.min_free_disk_space_mb,
+        "MIN FREE DISK SPACE",
+        lassie.min_free_disk_space_mb
+    );
+}
+
+/// A change of the configuration that can be applied without restarting zinniad.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigUpdate {
+    /// The new wallet address, `None` when the address did not change.
+    pub wallet_address: Option<String>,
+    /// The new list of modules to run.
+    pub modules: Vec<ModuleConfig>,
+}
+
+/// Check the config file for changes in the background. When the file changes, we report the
+/// settings that can be applied while zinniad is running. Changes of other settings require
+/// restarting zinniad, we log a warning in such case.
+pub fn watch_config_file(initial: Config) -> mpsc::Receiver<ConfigUpdate> {
+    let (tx, rx) = mpsc::channel(1);
+    let path = PathBuf::from(initial.args.config.clone().unwrap_or_default());
+
+    tokio::spawn(async move {
+        let mut current = initial;
+        let mut last_modified = modified_time(&path);
+        let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            let modified = modified_time(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            log::info!("Reloading config file {}", path.display());
+            let config = match Config::resolve(&current.matches) {
+                Ok(config) => config,
+                Err(err) => {
+                    log::error!(
+                        "Cannot reload the configuration, keeping the previous one: {err:#}"
+                    );
+                    continue;
+                }
+            };
+
+            let static_settings =
+                |config: &Config| config.file.as_ref().map(ConfigFile::static_settings);
+            if static_settings(&config) != static_settings(&current) {
+                log::warn!(
+                    "Some of the changes in {} will be applied after zinniad is restarted",
+                    path.display()
+                );
+            }
+
+            let update = ConfigUpdate {
+                wallet_address: Some(config.wallet_address().to_string())
+                    .filter(|address| address != current.wallet_address()),
+                modules: config.modules.clone(),
+            };
+            current = config;
+
+            if tx.send(update).await.is_err() {
+                // zinniad is shutting down
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    const CONFIG: &str = r#"
+wallet_address = "f1config"
+station_id = "config-station"
+state_root = "/var/lib/zinniad"
+
+[reporter]
+max_activities_per_minute = 10
+log_compress = false
+
+[lassie]
+max_blocks = 100
+protocols = ["http", "bitswap"]
+denied_providers = ["/dns4/example.com/https"]
+temp_max_size_mb = 512
+
+[[module]]
+path = "spark/main.js"
+name = "spark"
+max_activities_per_minute = 5
+allow_net = ["*.filspark.com"]
+"#;
+
+    fn resolve(dir: &Path, content: &str, args: &[&str]) -> Result<Config> {
+        let config_file = dir.join("zinniad.toml");
+        std::fs::write(&config_file, content)?;
+        let config_arg = format!("--config={}", config_file.display());
+        let matches =
+            CliArgs::command().try_get_matches_from(["zinniad", &config_arg].iter().chain(args))?;
+        Config::resolve(&matches)
+    }
+
+    #[test]
+    fn loads_config_file() -> Result<()> {
+        let dir = tempdir()?;
+        let config = resolve(dir.path(), CONFIG, &[])?;
+
+        assert_eq!(config.wallet_address(), "f1config");
+        assert_eq!(config.station_id(), "config-station");
+        assert_eq!(config.args.state_root, "/var/lib/zinniad");
+        assert_eq!(config.args.max_activities_per_minute, 10);
+        assert!(!config.args.log_compress);
+        assert_eq!(
+            config.modules,
+            vec![ModuleConfig {
+                name: "spark".into(),
+                file: dir.path().join("spark/main.js"),
+                max_activities_per_minute: 5,
+                allow_net: Some(vec!["*.filspark.com".into()]),
+            }]
+        );
+
+        let retrieval = config.retrieval();
+        assert_eq!(retrieval.max_blocks, Some(100));
+        assert_eq!(retrieval.protocols, vec!["http", "bitswap"]);
+        assert_eq!(retrieval.denied_providers, vec!["/dns4/example.com/https"]);
+        assert_eq!(config.args.lassie_temp_max_size_mb, 512);
+        Ok(())
+    }
+
+    #[test]
+    fn cli_flags_take_precedence() -> Result<()> {
+        let dir = tempdir()?;
+        let config = resolve(
+            dir.path(),
+            CONFIG,
+            &[
+                "--wallet-address=f1cli",
+                "--max-activities-per-minute=20",
+                "voyager.js",
+            ],
+        )?;
+
+        assert_eq!(config.wallet_address(), "f1cli");
+        assert_eq!(config.station_id(), "config-station");
+        assert_eq!(config.args.max_activities_per_minute, 20);
+        assert_eq!(
+            config.modules,
+            vec![ModuleConfig {
+                name: "voyager".into(),
+                file: "voyager.js".into(),
+                max_activities_per_minute: 20,
+                allow_net: None,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn derives_module_name_from_path() -> Result<()> {
+        let dir = tempdir()?;
+        let content = CONFIG
+            .replace("name = \"spark\"\n", "")
+            .replace("max_activities_per_minute = 5\n", "");
+        let config = resolve(dir.path(), &content, &[])?;
+        assert_eq!(config.modules[0].name, "spark/main");
+        assert_eq!(config.modules[0].max_activities_per_minute, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_more_than_one_module() -> Result<()> {
+        let dir = tempdir()?;
+        let content = format!("{CONFIG}\n[[module]]\npath = \"saturn.js\"\n");
+        let err = resolve(dir.path(), &content, &[]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("more than one module, found 2 modules"),
+            "{err:#}"
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["saturn.js", "voyager.js"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("more than one module"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_config_file() -> Result<()> {
+        let dir = tempdir()?;
+        let err = resolve(dir.path(), "unknown_setting = 1", &[]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("unknown field `unknown_setting`"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_retrieval_config() -> Result<()> {
+        let dir = tempdir()?;
+        let content = format!("{CONFIG}\n").replace(r#"["http", "bitswap"]"#, r#"["ftp"]"#);
+        let err = resolve(dir.path(), &content, &[]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid retrieval protocol \"ftp\""),
+            "{err:#}"
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["--max-concurrent-retrievals=0"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("maximum number of concurrent retrievals"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_job_history_config() -> Result<()> {
+        let dir = tempdir()?;
+        let content = CONFIG.replace("[reporter]\n", "[reporter]\njob_history_days = 0\n");
+        let err = resolve(dir.path(), &content, &[]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("`job_history_days` must be between 1 and 3650"),
+            "{err:#}"
+        );
+
+        let content = CONFIG.replace("[reporter]\n", "[reporter]\njob_history_hours = 4000000\n");
+        let err = resolve(dir.path(), &content, &[]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("`job_history_hours` must be between 1 and 87600"),
+            "{err:#}"
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["--job-history-days=100000"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("100000 is not in 1..=3650"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_proxy_config() -> Result<()> {
+        let dir = tempdir()?;
+        let content = format!(
+            "{CONFIG}\n[proxy]\nurl = \"http://proxy.example.com:3128\"\nno_proxy = [\"example.com\"]\n"
+        );
+        let config = resolve(dir.path(), &content, &[])?;
+        assert_eq!(
+            config.proxy(),
+            Some(ProxyConfig {
+                url: "http://proxy.example.com:3128".into(),
+                no_proxy: vec!["example.com".into()],
+            })
+        );
+
+        let config = resolve(dir.path(), &content, &["--proxy=socks5h://127.0.0.1:1080"])?;
+        assert_eq!(
+            config.proxy().map(|proxy| proxy.url),
+            Some("socks5h://127.0.0.1:1080".into())
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["--proxy=ftp://proxy.example.com"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Unsupported proxy scheme"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_tls_config() -> Result<()> {
+        let dir = tempdir()?;
+        let content =
+            format!("{CONFIG}\n[tls]\nunsafely_ignore_certificate_errors = [\"localhost\"]\n");
+        let config = resolve(dir.path(), &content, &[])?;
+        assert_eq!(
+            config.tls().unsafely_ignore_certificate_errors,
+            Some(vec!["localhost".to_string()])
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["--client-cert=client.pem"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("must be configured together"),
+            "{err:#}"
+        );
+
+        let err = resolve(dir.path(), CONFIG, &["--ca-cert=missing.pem"]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Cannot read certificate file"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn detects_changes_requiring_restart() {
+        let file: ConfigFile = toml::from_str(CONFIG).unwrap();
+        let changed = ConfigFile {
+            wallet_address: Some("f1other".into()),
+            modules: vec![],
+            ..file.clone()
+        };
+        assert_eq!(file.static_settings(), changed.static_settings());
+
+        let changed = ConfigFile {
+            state_root: Some("/tmp".into()),
+            ..file.clone()
+        };
+        assert_ne!(file.static_settings(), changed.static_settings());
+    }
+}
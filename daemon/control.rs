@@ -0,0 +1,684 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::json;
+#[cfg(any(unix, test))]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::mpsc;
+use zinnia_runtime::anyhow::{anyhow, Result};
+
+use crate::config::{ConfigUpdate, ModuleConfig};
+use crate::job_history::JobHistory;
+use crate::metrics::Metrics;
+use crate::signals::ShutdownSignal;
+use crate::station_reporter::{log_heartbeat, log_job_history, print_event};
+
+// Error codes defined by the JSON-RPC 2.0 specification
+pub const PARSE_ERROR: i32 = -32700;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+
+// How many requests can wait in the queue before we stop reading new ones
+const REQUEST_QUEUE_SIZE: usize = 16;
+
+/// A command sent by Station via the control channel, e.g.
+/// `{"id": 1, "method": "pause", "params": {}}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ControlRequest {
+    #[serde(default)]
+    pub id: serde_json::Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+impl ControlRequest {
+    /// Report the successful result of this request to Station.
+    pub fn respond(&self, result: serde_json::Value) {
+        let event = json!({
+            "type": "rpc:response",
+            "id": self.id,
+            "result": result,
+        });
+        print_event(&event);
+    }
+
+    /// Report that this request failed.
+    pub fn respond_error(&self, code: i32, message: &str) {
+        respond_error(&self.id, code, message);
+    }
+}
+
+fn respond_error(id: &serde_json::Value, code: i32, message: &str) {
+    let event = json!({
+        "type": "rpc:response",
+        "id": id,
+        "error": { "code": code, "message": message },
+    });
+    print_event(&event);
+}
+
+/// Start reading control requests in the background.
+///
+/// `source` is either `stdin` or a path where to create a Unix domain socket. The requests are
+/// newline-delimited JSON objects, responses are reported as `rpc:response` events on stdout.
+pub fn start_control_channel(source: &str) -> Result<mpsc::Receiver<ControlRequest>> {
+    let (tx, rx) = mpsc::channel(REQUEST_QUEUE_SIZE);
+    if source == "stdin" {
+        log::info!("Reading control requests from stdin");
+        // Tokio reads stdin in a blocking task that cannot be cancelled and prevents the runtime
+        // from shutting down. We use a thread that does not block the process from exiting.
+        std::thread::spawn(move || read_stdin_requests(tx));
+    } else {
+        listen_on_socket(source, tx)?;
+    }
+    Ok(rx)
+}
+
+#[cfg(unix)]
+fn listen_on_socket(path: &str, tx: mpsc::Sender<ControlRequest>) -> Result<()> {
+    use tokio::io::BufReader;
+    use zinnia_runtime::anyhow::Context;
+
+    // Remove the socket file left behind by the previous zinniad process
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(anyhow!(err).context(format!("cannot remove old control socket {path}")))
+        }
+        _ => {}
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("cannot create control socket {path}"))?;
+    log::info!("Reading control requests from socket {path}");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(read_requests(BufReader::new(stream), tx.clone()));
+                }
+                Err(err) => log::warn!("Cannot accept control connection: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_on_socket(path: &str, _tx: mpsc::Sender<ControlRequest>) -> Result<()> {
+    Err(anyhow!(
+        "Cannot create control socket {path}: Unix domain sockets are not supported on this platform. Use `stdin` instead."
+    ))
+}
+
+#[cfg(any(unix, test))]
+async fn read_requests<R: AsyncBufRead + Unpin>(reader: R, tx: mpsc::Sender<ControlRequest>) {
+    let mut lines = reader.lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                log::warn!("Cannot read control request: {err}");
+                break;
+            }
+        };
+        if let Some(request) = parse_line(&line) {
+            if tx.send(request).await.is_err() {
+                // zinniad is shutting down
+                break;
+            }
+        }
+    }
+}
+
+fn read_stdin_requests(tx: mpsc::Sender<ControlRequest>) {
+    for line in std::io::stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                log::warn!("Cannot read control request: {err}");
+                break;
+            }
+        };
+        if let Some(request) = parse_line(&line) {
+            if tx.blocking_send(request).is_err() {
+                // zinniad is shutting down
+                break;
+            }
+        }
+    }
+}
+
+// Parse a line received via the control channel, report invalid requests to Station
+fn parse_line(line: &str) -> Option<ControlRequest> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    match parse_request(line) {
+        Ok(request) => Some(request),
+        Err(err) => {
+            respond_error(&serde_json::Value::Null, PARSE_ERROR, &err.to_string());
+            None
+        }
+    }
+}
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const JOB_HISTORY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How did the module stop running.
+pub enum ModuleOutcome {
+    Finished(Result<()>),
+    /// Start the module again, e.g. after its configuration changed.
+    Restart,
+    /// The module was removed from the config file.
+    Stop,
+    Shutdown,
+}
+
+/// Supervisor drives the module while reporting heartbeat events and handling the requests
+/// received via the control channel and the changes of the config file.
+pub struct Supervisor {
+    module: Option<ModuleConfig>,
+    metrics: Arc<Metrics>,
+    history: Option<Arc<JobHistory>>,
+    requests: Option<mpsc::Receiver<ControlRequest>>,
+    config_updates: Option<mpsc::Receiver<ConfigUpdate>>,
+    shutdown: Option<Pin<Box<dyn Future<Output = ShutdownSignal>>>>,
+    wallet_address: String,
+    paused: bool,
+    started: Instant,
+}
+
+impl Supervisor {
+    /// Create a new instance. `module` is `None` when the config file does not list any module,
+    /// `requests` is `None` when the control channel is disabled.
+    pub fn new(
+        module: Option<ModuleConfig>,
+        metrics: Arc<Metrics>,
+        requests: Option<mpsc::Receiver<ControlRequest>>,
+        wallet_address: String,
+    ) -> Self {
+        Self {
+            module,
+            metrics,
+            history: None,
+            requests,
+            config_updates: None,
+            shutdown: None,
+            wallet_address,
+            paused: false,
+            started: Instant::now(),
+        }
+    }
+
+    /// Report the job history when the module starts and then every hour, and allow Station
+    /// to query it via the `history` request.
+    pub fn with_job_history(mut self, history: Arc<JobHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Apply changes of the module list and of the wallet address made in the config file.
+    pub fn with_config_updates(mut self, updates: mpsc::Receiver<ConfigUpdate>) -> Self {
+        self.config_updates = Some(updates);
+        self
+    }
+
+    /// Stop the module when the `shutdown` future completes, e.g. when zinniad receives
+    /// a shutdown signal.
+    pub fn with_shutdown(
+        mut self,
+        shutdown: impl Future<Output = ShutdownSignal> + 'static,
+    ) -> Self {
+        self.shutdown = Some(Box::pin(shutdown));
+        self
+    }
+
+    /// The wallet address to use for the next run of the module. Station can change it via
+    /// the `setWalletAddress` request.
+    pub fn wallet_address(&self) -> &str {
+        &self.wallet_address
+    }
+
+    /// The module to run next, `None` when there is no module to run.
+    pub fn module(&self) -> Option<&ModuleConfig> {
+        self.module.as_ref()
+    }
+
+    fn module_name(&self) -> &str {
+        self.module
+            .as_ref()
+            .map(|m| m.name.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Drive the module until it finishes, Station asks us to restart it or shut down, zinniad
+    /// receives a shutdown signal or the module configuration changes.
+    ///
+    /// The heartbeat is reported only when the module yields to the event loop, therefore
+    /// a missing heartbeat means the module is stuck. The same applies to control requests.
+    /// A paused module is not polled at all, its timers fire after the module is resumed.
+    ///
+    /// When there is no module to run, call this method with a future that never completes
+    /// to wait for a config change adding a module.
+    pub async fn run<F: Future<Output = Result<()>>>(&mut self, module: F) -> ModuleOutcome {
+        self.started = Instant::now();
+        let mut heartbeat = tokio::time::interval_at(
+            tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+            HEARTBEAT_INTERVAL,
+        );
+        // The first tick completes immediately, we report the history when the module starts
+        let mut history_summary = tokio::time::interval(JOB_HISTORY_INTERVAL);
+        tokio::pin!(module);
+
+        loop {
+            tokio::select! {
+                // Handle control requests before polling the module, e.g. `pause` must take
+                // effect immediately
+                biased;
+                _ = shutdown_requested(&mut self.shutdown) => return ModuleOutcome::Shutdown,
+                request = next_message(&mut self.requests) => match request {
+                    Some(request) => {
+                        if let Some(outcome) = self.handle_request(&request) {
+                            return outcome;
+                        }
+                    }
+                    None => {
+                        log::debug!("The control channel was closed");
+                        self.requests = None;
+                    }
+                },
+                update = next_message(&mut self.config_updates) => match update {
+                    Some(update) => {
+                        if let Some(outcome) = self.apply_config_update(update) {
+                            return outcome;
+                        }
+                    }
+                    None => self.config_updates = None,
+                },
+                _ = heartbeat.tick(), if self.module.is_some() => {
+                    log_heartbeat(self.module_name(), self.started.elapsed());
+                }
+                _ = history_summary.tick(), if self.module.is_some() && self.history.is_some() => {
+                    log_job_history(self.module_name(), &self.job_history());
+                }
+                result = &mut module, if !self.paused => return ModuleOutcome::Finished(result),
+            }
+        }
+    }
+
+    fn apply_config_update(&mut self, update: ConfigUpdate) -> Option<ModuleOutcome> {
+        log::debug!("Applying config update {update:?}");
+        // `Config::resolve` rejects configurations with more than one module
+        debug_assert!(update.modules.len() <= 1);
+        let module = update.modules.into_iter().next();
+
+        let mut restart = false;
+        if let Some(address) = update.wallet_address {
+            restart = address != self.wallet_address;
+            self.wallet_address = address;
+        }
+
+        if module != self.module {
+            let previous = std::mem::replace(&mut self.module, module);
+            return match (previous, &self.module) {
+                // Start the new module from scratch
+                (None, _) => Some(ModuleOutcome::Restart),
+                (Some(previous), Some(module)) if previous.name == module.name => {
+                    Some(ModuleOutcome::Restart)
+                }
+                // The previous module was removed or replaced by a different one
+                (Some(_), _) => Some(ModuleOutcome::Stop),
+            };
+        }
+
+        (restart && self.module.is_some()).then_some(ModuleOutcome::Restart)
+    }
+
+    fn handle_request(&mut self, request: &ControlRequest) -> Option<ModuleOutcome> {
+        log::debug!("Handling control request {request:?}");
+
+        if let Some(module) = request.params.get("module") {
+            if module.as_str() != Some(self.module_name()) {
+                request.respond_error(INVALID_PARAMS, &format!("Unknown module {module}"));
+                return None;
+            }
+        }
+
+        match request.method.as_str() {
+            "status" => request.respond(self.status()),
+            "stats" => {
+                let mut stats = self.metrics.module_totals(self.module_name());
+                stats["module"] = json!(self.module_name());
+                stats["custom"] = self.metrics.module_stats(self.module_name());
+                request.respond(stats);
+            }
+            "history" => match self.history {
+                Some(_) => {
+                    let mut history = self.job_history();
+                    history["module"] = json!(self.module_name());
+                    request.respond(history);
+                }
+                None => request.respond_error(METHOD_NOT_FOUND, "Job history is not enabled"),
+            },
+            "pause" => {
+                self.paused = true;
+                request.respond(self.status());
+            }
+            "resume" => {
+                self.paused = false;
+                request.respond(self.status());
+            }
+            "restart" => {
+                request.respond(self.status());
+                return Some(ModuleOutcome::Restart);
+            }
+            "shutdown" => {
+                request.respond(self.status());
+                return Some(ModuleOutcome::Shutdown);
+            }
+            "setWalletAddress" => {
+                let address = match request.params.get("address").and_then(|a| a.as_str()) {
+                    Some(address) if !address.trim().is_empty() => address,
+                    _ => {
+                        request.respond_error(INVALID_PARAMS, "Missing parameter: address");
+                        return None;
+                    }
+                };
+                if address == self.wallet_address {
+                    request.respond(json!({ "walletAddress": address, "restarting": false }));
+                    return None;
+                }
+                // The module reads the address only once at startup, we must restart it
+                self.wallet_address = address.to_string();
+                request.respond(json!({ "walletAddress": address, "restarting": true }));
+                return Some(ModuleOutcome::Restart);
+            }
+            method => request.respond_error(METHOD_NOT_FOUND, &format!("Unknown method {method}")),
+        }
+        None
+    }
+
+    fn job_history(&self) -> serde_json::Value {
+        match &self.history {
+            Some(history) => history.module_summary(self.module_name(), chrono::Utc::now()),
+            None => serde_json::Value::Null,
+        }
+    }
+
+    fn status(&self) -> serde_json::Value {
+        json!({
+            "module": self.module.as_ref().map(|m| &m.name),
+            "state": match (&self.module, self.paused) {
+                (None, _) => "idle",
+                (Some(_), true) => "paused",
+                (Some(_), false) => "running",
+            },
+            "uptime": self.started.elapsed().as_secs(),
+            "restarts": self.metrics.module_totals(self.module_name())["restarts"],
+            "walletAddress": self.wallet_address,
+        })
+    }
+}
+
+async fn next_message<T>(channel: &mut Option<mpsc::Receiver<T>>) -> Option<T> {
+    match channel {
+        Some(rx) => rx.recv().await,
+        // The channel is disabled or closed, wait forever
+        None => std::future::pending().await,
+    }
+}
+
+async fn shutdown_requested(
+    shutdown: &mut Option<Pin<Box<dyn Future<Output = ShutdownSignal>>>>,
+) -> ShutdownSignal {
+    match shutdown {
+        Some(shutdown) => shutdown.await,
+        None => std::future::pending().await,
+    }
+}
+
+fn parse_request(line: &str) -> Result<ControlRequest> {
+    serde_json::from_str(line).map_err(|err| anyhow!("Invalid control request: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            parse_request(r#"{"id":1,"method":"setWalletAddress","params":{"address":"f1x"}}"#)
+                .unwrap(),
+            ControlRequest {
+                id: json!(1),
+                method: "setWalletAddress".into(),
+                params: json!({ "address": "f1x" }),
+            }
+        );
+
+        assert_eq!(
+            parse_request(r#"{"method":"status"}"#).unwrap(),
+            ControlRequest {
+                id: serde_json::Value::Null,
+                method: "status".into(),
+                params: serde_json::Value::Null,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        assert!(parse_request("pause").is_err());
+        assert!(parse_request(r#"{"id":1}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_newline_delimited_requests() {
+        let input: &[u8] = b"{\"id\":1,\"method\":\"pause\"}\n\n{\"id\":2,\"method\":\"resume\"}\n";
+        let (tx, mut rx) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        read_requests(input, tx).await;
+
+        assert_eq!(rx.recv().await.unwrap().method, "pause");
+        assert_eq!(rx.recv().await.unwrap().method, "resume");
+        assert_eq!(rx.recv().await, None);
+    }
+
+    fn request(method: &str, params: serde_json::Value) -> ControlRequest {
+        ControlRequest {
+            id: json!(1),
+            method: method.into(),
+            params,
+        }
+    }
+
+    fn module(name: &str) -> ModuleConfig {
+        ModuleConfig {
+            name: name.into(),
+            file: format!("{name}.js").into(),
+            max_activities_per_minute: 30,
+            allow_net: None,
+        }
+    }
+
+    fn supervisor() -> Supervisor {
+        Supervisor::new(
+            Some(module("saturn")),
+            Arc::new(Metrics::default()),
+            None,
+            "f1old".into(),
+        )
+    }
+
+    #[test]
+    fn pauses_and_resumes_the_module() {
+        let mut supervisor = supervisor();
+
+        assert!(supervisor
+            .handle_request(&request("pause", json!({ "module": "saturn" })))
+            .is_none());
+        assert_eq!(supervisor.status()["state"], "paused");
+
+        assert!(supervisor
+            .handle_request(&request("resume", serde_json::Value::Null))
+            .is_none());
+        assert_eq!(supervisor.status()["state"], "running");
+    }
+
+    #[test]
+    fn rejects_requests_for_unknown_modules() {
+        let mut supervisor = supervisor();
+        assert!(supervisor
+            .handle_request(&request("pause", json!({ "module": "other" })))
+            .is_none());
+        assert_eq!(supervisor.status()["state"], "running");
+    }
+
+    #[test]
+    fn restarts_the_module_after_wallet_address_change() {
+        let mut supervisor = supervisor();
+
+        let outcome =
+            supervisor.handle_request(&request("setWalletAddress", json!({ "address": "f1old" })));
+        assert!(outcome.is_none());
+
+        let outcome =
+            supervisor.handle_request(&request("setWalletAddress", json!({ "address": "f1new" })));
+        assert!(matches!(outcome, Some(ModuleOutcome::Restart)));
+        assert_eq!(supervisor.wallet_address(), "f1new");
+
+        let outcome = supervisor.handle_request(&request("setWalletAddress", json!({})));
+        assert!(outcome.is_none());
+        assert_eq!(supervisor.wallet_address(), "f1new");
+    }
+
+    #[test]
+    fn reports_job_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = Arc::new(JobHistory::load(
+            dir.path().join("history.json"),
+            Default::default(),
+        ));
+        history.job_completed("saturn", chrono::Utc::now());
+        let supervisor = supervisor().with_job_history(history);
+
+        let summary = supervisor.job_history();
+        assert_eq!(summary["hourly"][0]["jobs"], 1);
+        assert_eq!(summary["daily"][0]["jobs"], 1);
+    }
+
+    #[test]
+    fn handles_restart_and_shutdown() {
+        let mut supervisor = supervisor();
+        assert!(matches!(
+            supervisor.handle_request(&request("restart", serde_json::Value::Null)),
+            Some(ModuleOutcome::Restart)
+        ));
+        assert!(matches!(
+            supervisor.handle_request(&request("shutdown", serde_json::Value::Null)),
+            Some(ModuleOutcome::Shutdown)
+        ));
+        assert!(supervisor
+            .handle_request(&request("unknown", serde_json::Value::Null))
+            .is_none());
+    }
+
+    #[test]
+    fn applies_config_updates() {
+        let mut supervisor = supervisor();
+        let update = |wallet_address: Option<&str>, modules: Vec<ModuleConfig>| ConfigUpdate {
+            wallet_address: wallet_address.map(String::from),
+            modules,
+        };
+
+        // Nothing changed
+        let outcome = supervisor.apply_config_update(update(None, vec![module("saturn")]));
+        assert!(outcome.is_none());
+
+        // The module settings changed
+        let saturn = ModuleConfig {
+            allow_net: Some(vec!["example.com".into()]),
+            ..module("saturn")
+        };
+        let outcome = supervisor.apply_config_update(update(None, vec![saturn.clone()]));
+        assert!(matches!(outcome, Some(ModuleOutcome::Restart)));
+        assert_eq!(supervisor.module(), Some(&saturn));
+
+        // The wallet address changed
+        let outcome = supervisor.apply_config_update(update(Some("f1new"), vec![saturn]));
+        assert!(matches!(outcome, Some(ModuleOutcome::Restart)));
+        assert_eq!(supervisor.wallet_address(), "f1new");
+
+        // The module was replaced by a different one
+        let outcome = supervisor.apply_config_update(update(None, vec![module("spark")]));
+        assert!(matches!(outcome, Some(ModuleOutcome::Stop)));
+        assert_eq!(supervisor.module(), Some(&module("spark")));
+
+        // The module was removed
+        let outcome = supervisor.apply_config_update(update(None, vec![]));
+        assert!(matches!(outcome, Some(ModuleOutcome::Stop)));
+        assert_eq!(supervisor.status()["state"], "idle");
+
+        // A module was added
+        let outcome = supervisor.apply_config_update(update(None, vec![module("saturn")]));
+        assert!(matches!(outcome, Some(ModuleOutcome::Restart)));
+    }
+
+    #[tokio::test]
+    async fn does_not_poll_paused_module() {
+        let (tx, rx) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let mut supervisor = Supervisor::new(
+            Some(module("saturn")),
+            Arc::new(Metrics::default()),
+            Some(rx),
+            "f1test".into(),
+        );
+        tx.send(request("pause", serde_json::Value::Null))
+            .await
+            .unwrap();
+        tx.send(request("shutdown", serde_json::Value::Null))
+            .await
+            .unwrap();
+
+        let outcome = supervisor.run(async { Ok(()) }).await;
+        assert!(matches!(outcome, ModuleOutcome::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn shuts_down_on_signal() {
+        let mut supervisor =
+            supervisor().with_shutdown(std::future::ready(ShutdownSignal::Terminate));
+
+        let outcome = supervisor.run(std::future::pending()).await;
+        assert!(matches!(outcome, ModuleOutcome::Shutdown));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reads_requests_from_socket() -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("control.sock");
+        let path = path.to_str().unwrap();
+
+        let mut rx = start_control_channel(path)?;
+        let mut stream = tokio::net::UnixStream::connect(path).await?;
+        stream
+            .write_all(b"{\"id\":\"a\",\"method\":\"status\"}\n")
+            .await?;
+
+        let request = rx.recv().await.unwrap();
+        assert_eq!(request.id, json!("a"));
+        assert_eq!(request.method, "status");
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tokio::task::JoinHandle;
+use zinnia_runtime::anyhow::{Context, Result};
+use zinnia_runtime::deno_core::ModuleSpecifier;
+use zinnia_runtime::{LogLevel, MetricUpdate, Reporter};
+
+/// When and how to rotate log files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationConfig {
+    /// Rotate the log file when it grows beyond this size (in bytes).
+    pub max_size: u64,
+    /// Rotate the log file when it's older than this. The age is checked only when writing
+    /// a new entry.
+    pub max_age: Option<Duration>,
+    /// How many rotated files to keep. Older files are deleted.
+    pub max_files: usize,
+    /// Compress rotated files using gzip.
+    pub compress: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10 * 1024 * 1024,
+            max_age: Some(Duration::from_secs(24 * 3600)),
+            max_files: 10,
+            compress: true,
+        }
+    }
+}
+
+/// A log file that rotates itself based on the size and age. Rotated files are renamed
+/// to `{stem}.{timestamp}-{sequence}.log` (plus `.gz` when compressed). Compression runs
+/// on a blocking thread so that writing a log entry does not stall the event loop.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    config: RotationConfig,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    compression: Option<JoinHandle<()>>,
+}
+
+impl RotatingFile {
+    /// Open the log file for appending, creating the parent directory when needed.
+    pub fn open(path: PathBuf, config: RotationConfig) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create log directory {}", dir.display()))?;
+        }
+        let (file, size, opened_at) = open_log_file(&path)?;
+        Ok(Self {
+            path,
+            config,
+            file,
+            size,
+            opened_at,
+            compression: None,
+        })
+    }
+
+    /// Append a line to the log file, rotating the file first if needed.
+    /// The line should not include the final newline character.
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.should_rotate(len) {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")
+            .with_context(|| format!("Cannot write to log file {}", self.path.display()))?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn should_rotate(&self, len: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        if self.size + len > self.config.max_size {
+            return true;
+        }
+        match self.config.max_age {
+            None => false,
+            Some(max_age) => self
+                .opened_at
+                .elapsed()
+                .map(|age| age >= max_age)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+
+        let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+        let rotated = self.rotated_path(&timestamp);
+        fs::rename(&self.path, &rotated).with_context(|| {
+            format!(
+                "Cannot rename log file {} to {}",
+                self.path.display(),
+                rotated.display()
+            )
+        })?;
+
+        (self.file, self.size, self.opened_at) = open_log_file(&self.path)?;
+
+        if !self.config.compress {
+            return prune_rotated_files(&self.path, self.config.max_files);
+        }
+
+        let path = self.path.clone();
+        let max_files = self.config.max_files;
+        self.compression = Some(tokio::task::spawn_blocking(move || {
+            compress_file(&rotated)
+                .and_then(|_| prune_rotated_files(&path, max_files))
+                .unwrap_or_else(|err| {
+                    log::warn!("Cannot compress rotated log file: {err:?}");
+                });
+        }));
+        Ok(())
+    }
+
+    fn rotated_path(&self, timestamp: &str) -> PathBuf {
+        // The file is rotated more than once per millisecond when a single log entry is larger
+        // than `max_size`. The sequence number keeps such files apart, and the fixed-width
+        // format allows us to sort the rotated files by their names.
+        let stem = file_stem(&self.path);
+        (0..)
+            .map(|seq| {
+                self.path
+                    .with_file_name(format!("{stem}.{timestamp}-{seq:04}.log"))
+            })
+            .find(|path| {
+                let mut compressed = path.as_os_str().to_owned();
+                compressed.push(".gz");
+                !path.exists() && !Path::new(&compressed).exists()
+            })
+            .expect("there is always an unused sequence number")
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Files rotated from the log file at `path`, sorted from the oldest to the newest.
+fn rotated_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return Ok(vec![]),
+    };
+    let prefix = format!("{}.", file_stem(path));
+
+    let mut files = vec![];
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Cannot list log directory {}", dir.display()))?
+    {
+        let entry_path = entry?.path();
+        let name = entry_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if entry_path != path
+            && name.starts_with(&prefix)
+            && (name.ends_with(".log") || name.ends_with(".log.gz"))
+        {
+            files.push(entry_path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn prune_rotated_files(path: &Path, max_files: usize) -> Result<()> {
+    let files = rotated_files(path)?;
+    let excess = files.len().saturating_sub(max_files);
+    for path in &files[..excess] {
+        match fs::remove_file(path) {
+            // Another rotation pruning the files in the background deleted it already
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            result => {
+                result.with_context(|| format!("Cannot delete old log file {}", path.display()))?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open_log_file(path: &Path) -> Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open log file {}", path.display()))?;
+    let metadata = file.metadata()?;
+    // Not all platforms & filesystems support file creation time, fall back to "now"
+    let created = metadata.created().unwrap_or_else(|_| SystemTime::now());
+    Ok((file, metadata.len(), created))
+}
+
+fn compress_file(path: &Path) -> Result<()> {
+    let mut target = path.as_os_str().to_owned();
+    target.push(".gz");
+
+    let mut input = BufReader::new(File::open(path)?);
+    let mut encoder = GzEncoder::new(File::create(&target)?, Compression::default());
+    io::copy(&mut input, &mut encoder)
+        .and_then(|_| encoder.finish())
+        .with_context(|| format!("Cannot compress log file {}", path.display()))?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// FileReporter writes activities and Console logs to a rotating log file and forwards
+/// all events to the inner reporter.
+pub struct FileReporter<R: Reporter> {
+    inner: R,
+    file: RefCell<RotatingFile>,
+}
+
+impl<R: Reporter> FileReporter<R> {
+    pub fn new(inner: R, file: RotatingFile) -> Self {
+        Self {
+            inner,
+            file: RefCell::new(file),
+        }
+    }
+
+    fn write_entry(&self, level: &str, msg: &str) {
+        let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
+        let line = format!("{timestamp} {level:<14} {msg}");
+        self.file
+            .borrow_mut()
+            .write_line(&line)
+            .unwrap_or_else(|err| {
+                // We are ignoring errors because there isn't much to do in such case
+                log::debug!("Cannot write log entry {line:?}: {err:?}");
+            });
+    }
+}
+
+impl<R: Reporter> Reporter for FileReporter<R> {
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.write_entry(&format!("console.{level}"), msg.trim_end());
+        self.inner.log(level, msg);
+    }
+
+    fn info_activity(&self, msg: &str) {
+        self.write_entry("activity:info", msg);
+        self.inner.info_activity(msg);
+    }
+
+    fn error_activity(&self, msg: &str) {
+        self.write_entry("activity:error", msg);
+        self.inner.error_activity(msg);
+    }
+
+    fn job_completed(&self) {
+        // Modules complete jobs very often, we don't want to flood the log file
+        self.inner.job_completed();
+    }
+
+    fn metric(&self, update: &MetricUpdate) {
+        self.inner.metric(update);
+    }
+
+    fn module_loaded(&self, specifier: &ModuleSpecifier) {
+        self.inner.module_loaded(specifier);
+    }
+
+    fn module_started(&self) {
+        self.inner.module_started();
+    }
+
+    fn flush_activities(&self) {
+        self.inner.flush_activities();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Read;
+    use tempfile::tempdir;
+    use zinnia_runtime::RecordingReporter;
+
+    fn list_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn writes_logs_and_activities() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("logs").join("zinniad.log");
+        let file = RotatingFile::open(path.clone(), RotationConfig::default())?;
+        let reporter = FileReporter::new(RecordingReporter::new(), file);
+
+        reporter.log(LogLevel::Info, "hello\n");
+        reporter.info_activity("started");
+        reporter.error_activity("failed");
+        reporter.job_completed();
+
+        let content = fs::read_to_string(&path)?;
+        let entries: Vec<&str> = content
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                "console.info   hello",
+                "activity:info  started",
+                "activity:error failed",
+            ]
+        );
+
+        assert_eq!(
+            *reporter.inner.events.borrow(),
+            vec![
+                "console.info: hello\n",
+                "INFO: started",
+                "ERROR: failed",
+                "JOB-COMPLETED",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rotates_and_compresses_files_by_size() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("zinniad.log");
+        let mut file = RotatingFile::open(
+            path.clone(),
+            RotationConfig {
+                max_size: 10,
+                max_age: None,
+                max_files: 10,
+                compress: true,
+            },
+        )?;
+
+        file.write_line("first")?;
+        file.write_line("second")?;
+        file.compression
+            .take()
+            .expect("compression started")
+            .await?;
+
+        assert_eq!(fs::read_to_string(&path)?, "second\n");
+
+        let files = list_files(dir.path());
+        assert_eq!(files.len(), 2, "{files:?}");
+        assert!(files[0].starts_with("zinniad.") && files[0].ends_with(".log.gz"));
+
+        let mut rotated = String::new();
+        flate2::read::GzDecoder::new(File::open(dir.path().join(&files[0]))?)
+            .read_to_string(&mut rotated)?;
+        assert_eq!(rotated, "first\n");
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_files_by_age() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("zinniad.log");
+        let mut file = RotatingFile::open(
+            path.clone(),
+            RotationConfig {
+                max_age: Some(Duration::from_millis(0)),
+                compress: false,
+                ..RotationConfig::default()
+            },
+        )?;
+
+        file.write_line("first")?;
+        file.write_line("second")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "second\n");
+        assert_eq!(list_files(dir.path()).len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_limited_number_of_rotated_files() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("zinniad.log");
+        let config = RotationConfig {
+            max_size: 1,
+            max_age: None,
+            max_files: 2,
+            compress: false,
+        };
+
+        // Simulate files rotated in the past
+        for timestamp in ["20230101T000000.000Z-0000", "20230102T000000.000Z-0000"] {
+            fs::write(dir.path().join(format!("zinniad.{timestamp}.log")), "old\n")?;
+        }
+        fs::write(dir.path().join("unrelated.log"), "keep me\n")?;
+
+        let mut file = RotatingFile::open(path, config)?;
+        file.write_line("first")?;
+        file.write_line("second")?;
+
+        let files = list_files(dir.path());
+        assert_eq!(files.len(), 4, "{files:?}");
+        assert!(!files.contains(&"zinniad.20230101T000000.000Z-0000.log".to_string()));
+        assert!(files.contains(&"zinniad.20230102T000000.000Z-0000.log".to_string()));
+        assert!(files.contains(&"zinniad.log".to_string()));
+        assert!(files.contains(&"unrelated.log".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_overwrite_files_rotated_in_the_same_millisecond() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("zinniad.log");
+        let file = RotatingFile::open(path, RotationConfig::default())?;
+
+        let timestamp = "20230101T000000.000Z";
+        fs::write(
+            dir.path().join(format!("zinniad.{timestamp}-0000.log.gz")),
+            "",
+        )?;
+        fs::write(dir.path().join(format!("zinniad.{timestamp}-0001.log")), "")?;
+
+        assert_eq!(
+            file.rotated_path(timestamp),
+            dir.path().join(format!("zinniad.{timestamp}-0002.log"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use zinnia_runtime::anyhow::{Context, Result};
+
+use crate::state::write_atomically;
+
+/// How long to keep the history of completed jobs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryRetention {
+    /// Number of hourly buckets to keep, including the current hour.
+    pub hours: u32,
+    /// Number of daily buckets to keep, including the current day.
+    pub days: u32,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self {
+            hours: 48,
+            days: 30,
+        }
+    }
+}
+
+/// Rolling history of jobs completed by each module, bucketed by hour and by day (in UTC).
+/// The history is persisted in a JSON file in the state directory.
+#[derive(Debug)]
+pub struct JobHistory {
+    file: PathBuf,
+    retention: HistoryRetention,
+    data: Mutex<HistoryData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct HistoryData {
+    #[serde(default)]
+    modules: BTreeMap<String, ModuleHistory>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct ModuleHistory {
+    /// Number of jobs completed in the hour starting at the given time
+    #[serde(default)]
+    hourly: BTreeMap<DateTime<Utc>, u64>,
+    /// Number of jobs completed in the day starting at the given time
+    #[serde(default)]
+    daily: BTreeMap<DateTime<Utc>, u64>,
+}
+
+impl JobHistory {
+    /// Load the history from the given file. When the file cannot be parsed, we start with
+    /// an empty history, because the history is not critical for the operation of zinniad.
+    pub fn load(file: PathBuf, retention: HistoryRetention) -> Self {
+        let data = match std::fs::read_to_string(&file) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                log::warn!(
+                    "Cannot parse job history from {}, starting with an empty history: {err}",
+                    file.display()
+                );
+                HistoryData::default()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HistoryData::default(),
+            Err(err) => {
+                log::warn!(
+                    "Cannot read job history from {}, starting with an empty history: {err}",
+                    file.display()
+                );
+                HistoryData::default()
+            }
+        };
+        Self {
+            file,
+            retention,
+            data: Mutex::new(data),
+        }
+    }
+
+    /// Record a job completed by the module at the given time.
+    pub fn job_completed(&self, module: &str, now: DateTime<Utc>) {
+        let mut data = self.data.lock().unwrap();
+        let history = data.modules.entry(module.to_string()).or_default();
+        *history.hourly.entry(hour_start(now)).or_default() += 1;
+        *history.daily.entry(day_start(now)).or_default() += 1;
+    }
+
+    /// Remove buckets outside of the retention window and write the history to the file.
+    pub fn store(&self, now: DateTime<Utc>) -> Result<()> {
+        let payload = {
+            let mut data = self.data.lock().unwrap();
+            self.prune(&mut data, now);
+            serde_json::to_string_pretty(&*data).context("Cannot serialize job history")?
+        };
+        write_atomically(&self.file, &payload)
+            .with_context(|| format!("Cannot write job history to {}", self.file.display()))?;
+        log::debug!("Job history stored in {}", self.file.display());
+        Ok(())
+    }
+
+    /// The history of the given module, in the format we report to Station.
+    pub fn module_summary(&self, module: &str, now: DateTime<Utc>) -> serde_json::Value {
+        let mut data = self.data.lock().unwrap();
+        self.prune(&mut data, now);
+        let history = data.modules.get(module).cloned().unwrap_or_default();
+
+        let buckets = |buckets: &BTreeMap<DateTime<Utc>, u64>| -> Vec<serde_json::Value> {
+            buckets
+                .iter()
+                .map(|(start, jobs)| json!({ "start": start, "jobs": jobs }))
+                .collect()
+        };
+        json!({
+            "hourly": buckets(&history.hourly),
+            "daily": buckets(&history.daily),
+        })
+    }
+
+    fn prune(&self, data: &mut HistoryData, now: DateTime<Utc>) {
+        let oldest_hour = hour_start(now) - Duration::hours(self.retention.hours as i64 - 1);
+        let oldest_day = day_start(now) - Duration::days(self.retention.days as i64 - 1);
+        for history in data.modules.values_mut() {
+            history.hourly.retain(|start, _| *start >= oldest_hour);
+            history.daily.retain(|start, _| *start >= oldest_day);
+        }
+        data.modules
+            .retain(|_, history| !history.hourly.is_empty() || !history.daily.is_empty());
+    }
+}
+
+fn hour_start(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(Duration::hours(1))
+        .expect("cannot truncate timestamp to the hour")
+}
+
+fn day_start(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(Duration::days(1))
+        .expect("cannot truncate timestamp to the day")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn time(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().into()
+    }
+
+    #[test]
+    fn counts_jobs_per_hour_and_day() {
+        let dir = tempdir().unwrap();
+        let history = JobHistory::load(dir.path().join("history.json"), Default::default());
+        history.job_completed("saturn", time("2025-01-01T10:15:00Z"));
+        history.job_completed("saturn", time("2025-01-01T10:45:00Z"));
+        history.job_completed("saturn", time("2025-01-01T11:05:00Z"));
+        history.job_completed("spark", time("2025-01-01T11:05:00Z"));
+
+        assert_eq!(
+            history.module_summary("saturn", time("2025-01-01T11:30:00Z")),
+            json!({
+                "hourly": [
+                    { "start": "2025-01-01T10:00:00Z", "jobs": 2 },
+                    { "start": "2025-01-01T11:00:00Z", "jobs": 1 },
+                ],
+                "daily": [
+                    { "start": "2025-01-01T00:00:00Z", "jobs": 3 },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn removes_buckets_outside_of_retention() {
+        let dir = tempdir().unwrap();
+        let history = JobHistory::load(
+            dir.path().join("history.json"),
+            HistoryRetention { hours: 2, days: 2 },
+        );
+        history.job_completed("saturn", time("2025-01-01T10:15:00Z"));
+        history.job_completed("saturn", time("2025-01-02T10:15:00Z"));
+        history.job_completed("saturn", time("2025-01-03T09:15:00Z"));
+        history.job_completed("saturn", time("2025-01-03T10:15:00Z"));
+
+        let summary = history.module_summary("saturn", time("2025-01-03T10:30:00Z"));
+        assert_eq!(
+            summary["hourly"],
+            json!([
+                { "start": "2025-01-03T09:00:00Z", "jobs": 1 },
+                { "start": "2025-01-03T10:00:00Z", "jobs": 1 },
+            ])
+        );
+        assert_eq!(
+            summary["daily"],
+            json!([
+                { "start": "2025-01-02T00:00:00Z", "jobs": 1 },
+                { "start": "2025-01-03T00:00:00Z", "jobs": 2 },
+            ])
+        );
+    }
+
+    #[test]
+    fn persists_history() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("state").join("history.json");
+        let now = Utc::now();
+
+        let history = JobHistory::load(file.clone(), Default::default());
+        history.job_completed("saturn", now);
+        history.store(now)?;
+
+        let history = JobHistory::load(file, Default::default());
+        assert_eq!(history.module_summary("saturn", now)["daily"][0]["jobs"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_corrupted_history() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("history.json");
+        std::fs::write(&file, "{")?;
+
+        let history = JobHistory::load(file, Default::default());
+        assert_eq!(
+            history.module_summary("saturn", Utc::now()),
+            json!({ "hourly": [], "daily": [] })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::time::MissedTickBehavior;
+use zinnia_runtime::anyhow::{Context, Result};
+use zinnia_runtime::RetrievalGate;
+
+use crate::metrics::Metrics;
+
+// How often to clean up Lassie's temp directory and check the free disk space
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+const MB: u64 = 1024 * 1024;
+
+/// Limits for Lassie's temp directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempDirQuota {
+    /// Delete the oldest files when the directory grows beyond this size (in bytes).
+    pub max_size: u64,
+    /// Delete files that were not modified for this long, Lassie is no longer using them.
+    pub max_age: Duration,
+    /// Pause IPFS retrievals when there is less free disk space than this (in bytes).
+    pub min_free_space: u64,
+}
+
+/// What a single cleanup of the temp directory did.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CleanupReport {
+    pub removed_files: u64,
+    pub removed_bytes: u64,
+    /// Total size of the files left in the directory
+    pub remaining_bytes: u64,
+}
+
+/// Clean up Lassie's temp directory and check the free disk space in the background.
+pub fn start_lassie_gc(
+    dir: PathBuf,
+    quota: TempDirQuota,
+    metrics: Arc<Metrics>,
+    retrieval_gate: RetrievalGate,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            collect_garbage(&dir, &quota, &metrics);
+            check_free_space(&dir, &quota, &metrics, &retrieval_gate);
+        }
+    });
+}
+
+fn collect_garbage(dir: &Path, quota: &TempDirQuota, metrics: &Metrics) {
+    let report = match clean_temp_dir(dir, quota, SystemTime::now()) {
+        Ok(report) => report,
+        Err(err) => {
+            log::warn!("Cannot clean Lassie tempdir: {err:?}");
+            return;
+        }
+    };
+    if report.removed_files > 0 {
+        log::info!(
+            "Removed {} files ({} MB) from Lassie tempdir, {} MB left",
+            report.removed_files,
+            report.removed_bytes / MB,
+            report.remaining_bytes / MB,
+        );
+    } else {
+        log::debug!(
+            "Lassie tempdir is within limits ({} MB)",
+            report.remaining_bytes / MB
+        );
+    }
+    metrics.lassie_tempdir_cleaned(&report);
+}
+
+fn check_free_space(
+    dir: &Path,
+    quota: &TempDirQuota,
+    metrics: &Metrics,
+    retrieval_gate: &RetrievalGate,
+) {
+    let available = match fs3::available_space(dir) {
+        Ok(available) => available,
+        Err(err) => {
+            log::warn!("Cannot check free disk space in {}: {err}", dir.display());
+            return;
+        }
+    };
+
+    let was_paused = retrieval_gate.paused_reason().is_some();
+    let paused = available < quota.min_free_space;
+    if paused {
+        if !was_paused {
+            log::warn!(
+                "Only {} MB of free disk space left, pausing IPFS retrievals",
+                available / MB
+            );
+        }
+        retrieval_gate.pause(&format!(
+            "only {} MB of free disk space left",
+            available / MB
+        ));
+    } else if was_paused {
+        log::info!(
+            "{} MB of free disk space available, resuming IPFS retrievals",
+            available / MB
+        );
+        retrieval_gate.resume();
+    }
+    metrics.disk_space_checked(available, paused);
+}
+
+/// Delete files that were not modified for longer than `quota.max_age`, then delete the oldest
+/// files until the directory fits into `quota.max_size`. Retrievals using deleted files fail.
+pub fn clean_temp_dir(dir: &Path, quota: &TempDirQuota, now: SystemTime) -> Result<CleanupReport> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("cannot list files in Lassie's temp dir {dir:?}"))?
+    {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::warn!("Cannot parse dir entry in Lassie tempdir: {:?}", err);
+                continue;
+            }
+        };
+        // We are assuming that Lassie creates only files, never subdirectories
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => files.push((
+                metadata.modified().unwrap_or(now),
+                entry.path(),
+                metadata.len(),
+            )),
+            _ => continue,
+        }
+    }
+
+    // Oldest files first
+    files.sort();
+
+    let mut report = CleanupReport {
+        remaining_bytes: files.iter().map(|(_, _, size)| size).sum(),
+        ..Default::default()
+    };
+    for (modified, path, size) in files {
+        let stale = now
+            .duration_since(modified)
+            .map(|age| age >= quota.max_age)
+            .unwrap_or_default();
+        if !stale && report.remaining_bytes <= quota.max_size {
+            continue;
+        }
+        log::trace!("Removing Lassie temp file {:?}", path);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                report.removed_files += 1;
+                report.removed_bytes += size;
+                report.remaining_bytes -= size;
+            }
+            Err(err) => log::warn!("Cannot remove Lassie temp file {:?}: {:?}", path, err),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn quota(max_size: u64) -> TempDirQuota {
+        TempDirQuota {
+            max_size,
+            max_age: HOUR,
+            min_free_space: 0,
+        }
+    }
+
+    fn create_file(dir: &Path, name: &str, size: usize, modified: SystemTime) {
+        let path = dir.join(name);
+        fs::write(&path, vec![0u8; size]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn list_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn removes_stale_files() -> Result<()> {
+        let dir = tempdir()?;
+        let now = SystemTime::now();
+        create_file(dir.path(), "stale", 10, now - 2 * HOUR);
+        create_file(dir.path(), "fresh", 20, now);
+
+        let report = clean_temp_dir(dir.path(), &quota(1000), now)?;
+        assert_eq!(
+            report,
+            CleanupReport {
+                removed_files: 1,
+                removed_bytes: 10,
+                remaining_bytes: 20,
+            }
+        );
+        assert_eq!(list_files(dir.path()), vec!["fresh"]);
+        Ok(())
+    }
+
+    #[test]
+    fn removes_oldest_files_above_quota() -> Result<()> {
+        let dir = tempdir()?;
+        let now = SystemTime::now();
+        create_file(dir.path(), "first", 10, now - Duration::from_secs(30));
+        create_file(dir.path(), "second", 10, now - Duration::from_secs(20));
+        create_file(dir.path(), "third", 10, now - Duration::from_secs(10));
+
+        let report = clean_temp_dir(dir.path(), &quota(15), now)?;
+        assert_eq!(report.removed_files, 2);
+        assert_eq!(report.remaining_bytes, 10);
+        assert_eq!(list_files(dir.path()), vec!["third"]);
+        Ok(())
+    }
+
+    #[test]
+    fn pauses_retrievals_when_disk_is_full() {
+        let dir = tempdir().unwrap();
+        let metrics = Metrics::default();
+        let gate = RetrievalGate::default();
+
+        let full = TempDirQuota {
+            min_free_space: u64::MAX,
+            ..quota(0)
+        };
+        check_free_space(dir.path(), &full, &metrics, &gate);
+        assert!(gate.paused_reason().is_some());
+
+        check_free_space(dir.path(), &quota(0), &metrics, &gate);
+        assert_eq!(gate.paused_reason(), None);
+    }
+}
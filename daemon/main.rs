@@ -1,107 +1,317 @@
 mod args;
+mod config;
+mod control;
+mod file_reporter;
+mod job_history;
+mod lassie_gc;
+mod metrics;
+mod metrics_server;
+mod signals;
 mod state;
 mod station_reporter;
 
 use std::fs;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
 
 use args::CliArgs;
-use clap::Parser;
+use clap::CommandFactory;
 
 use zinnia_runtime::anyhow::{anyhow, Context, Error, Result};
 use zinnia_runtime::{
-    get_module_root, lassie, lassie_config, resolve_path, run_js_module, BootstrapOptions,
+    any_and_jserrorbox_downcast_ref, get_module_root, lassie, lassie_config, resolve_path,
+    run_js_module, ActivityLimits, BootstrapOptions, CoreError, LassieBackend, Reporter,
+    RetrievalBackend, RetrievalGate, TelemetryOptions,
 };
 
-use crate::station_reporter::{log_started_activity, StationReporter};
+use crate::config::{watch_config_file, Config, ModuleConfig};
+use crate::control::{start_control_channel, ModuleOutcome, Supervisor};
+use crate::file_reporter::{FileReporter, RotatingFile, RotationConfig};
+use crate::job_history::{HistoryRetention, JobHistory};
+use crate::lassie_gc::{start_lassie_gc, TempDirQuota};
+use crate::metrics::Metrics;
+use crate::metrics_server::start_metrics_server;
+use crate::signals::{handle_shutdown_signals, ShutdownSignal};
+use crate::station_reporter::{
+    log_module_exited, log_started_activity, log_uncaught_exception, ModuleExit, StationReporter,
+};
+
+/// The rate-limiting window of module activities, see `ActivityLimits::interval`.
+const ACTIVITY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     setup_logger();
-    let cli_args = CliArgs::parse_from(std::env::args());
+    let matches = CliArgs::command().get_matches_from(std::env::args());
+
+    let config = match Config::resolve(&matches) {
+        Ok(config) => config,
+        Err(err) => return exit_with_error(err),
+    };
+    let deadline = Duration::from_secs(config.args.shutdown_timeout_secs);
+    let shutdown = match handle_shutdown_signals(deadline) {
+        Ok(shutdown) => shutdown,
+        Err(err) => return exit_with_error(err),
+    };
 
-    match run(cli_args).await {
-        Ok(_) => (),
+    match run(config, shutdown).await {
+        Ok(output) => output.shutdown(),
         Err(err) => exit_with_error(err),
     }
 }
 
-async fn run(config: CliArgs) -> Result<RunOutput> {
-    log::info!("Starting zinniad with config {config:?}");
+async fn run(
+    config: Config,
+    shutdown: impl Future<Output = ShutdownSignal> + 'static,
+) -> Result<RunOutput> {
+    log::info!(
+        "Starting zinniad with config {:?} and config file {:?}",
+        config.args,
+        config.file
+    );
 
-    if config.files.is_empty() {
+    // Without the config file, there is no way to add modules later
+    if config.modules.is_empty() && config.file.is_none() {
         return Err(anyhow!("You must provide at least one module to run."));
     }
-    if config.files.len() > 1 {
-        return Err(anyhow!(
-            "We do not yet support running more than one module."
-        ));
-    }
 
-    let state_file = PathBuf::from(config.state_root).join("state.json");
+    let args = &config.args;
+
+    let state_file = PathBuf::from(&args.state_root).join("state.json");
     log::debug!("Using state file: {}", state_file.display());
-    let lassie_temp_dir = PathBuf::from(config.cache_root).join("lassie");
+    let lassie_temp_dir = PathBuf::from(&args.cache_root).join("lassie");
 
     setup_lassie_tempdir(&lassie_temp_dir)?;
 
+    let retrieval = config.retrieval();
+    let proxy = config.proxy();
+    if proxy.is_some() {
+        log::warn!(
+            "Lassie does not support proxies, IPFS retrievals connect to providers directly"
+        );
+    }
     let lassie_config = lassie::DaemonConfig {
-        temp_dir: Some(lassie_temp_dir),
-        ..lassie_config()
+        temp_dir: Some(lassie_temp_dir.clone()),
+        ..lassie_config(&retrieval)
     };
     let lassie_daemon = Arc::new(
         lassie::Daemon::start(lassie_config)
             .context("cannot initialize the IPFS retrieval client Lassie")?,
     );
+    let retrieval_backend: Arc<dyn RetrievalBackend> =
+        Arc::new(LassieBackend::new(Arc::clone(&lassie_daemon)));
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = args.metrics_listen {
+        start_metrics_server(addr, Arc::clone(&metrics)).await?;
+    }
+
+    let retrieval_gate = RetrievalGate::default();
+    start_lassie_gc(
+        lassie_temp_dir.clone(),
+        TempDirQuota {
+            max_size: args.lassie_temp_max_size_mb * 1024 * 1024,
+            max_age: Duration::from_secs(args.lassie_temp_max_age_mins * 60),
+            min_free_space: args.min_free_disk_space_mb * 1024 * 1024,
+        },
+        Arc::clone(&metrics),
+        retrieval_gate.clone(),
+    );
 
     log_started_activity();
 
-    let file = &config.files[0];
-
-    // TODO: configurable module name and version
-    // https://github.com/filecoin-station/zinnia/issues/147
-    let module_name = file.trim_end_matches(".js");
-
-    let main_module = resolve_path(
-        file,
-        &std::env::current_dir().context("unable to get current working directory")?,
-    )?;
-    let module_root = get_module_root(&main_module)?;
-
-    let runtime_config = BootstrapOptions {
-        zinnia_version: env!("CARGO_PKG_VERSION"),
-        agent_version: format!("zinniad/{} {module_name}", env!("CARGO_PKG_VERSION")),
-        wallet_address: config.wallet_address,
-        station_id: config.station_id,
-        reporter: Rc::new(StationReporter::new(
-            state_file,
-            Duration::from_millis(200),
-            module_name.into(),
-        )),
-        lassie_daemon: Arc::clone(&lassie_daemon),
-        module_root: Some(module_root),
-        rng_seed: None,
+    let job_history = Arc::new(JobHistory::load(
+        PathBuf::from(&args.state_root).join("job-history.json"),
+        HistoryRetention {
+            hours: args.job_history_hours,
+            days: args.job_history_days,
+        },
+    ));
+
+    let requests = match &args.control {
+        Some(source) => Some(start_control_channel(source)?),
+        None => None,
+    };
+    let mut supervisor = Supervisor::new(
+        config.modules.first().cloned(),
+        Arc::clone(&metrics),
+        requests,
+        config.wallet_address().into(),
+    )
+    .with_job_history(Arc::clone(&job_history))
+    .with_shutdown(shutdown);
+    if config.file.is_some() {
+        supervisor = supervisor.with_config_updates(watch_config_file(config.clone()));
+    }
+
+    let cwd = std::env::current_dir().context("unable to get current working directory")?;
+
+    let result = loop {
+        let module = match supervisor.module() {
+            Some(module) => module.clone(),
+            None => {
+                log::info!("There is no module to run, waiting for changes in the config file");
+                match supervisor.run(std::future::pending()).await {
+                    ModuleOutcome::Shutdown => break Ok(()),
+                    _ => continue,
+                }
+            }
+        };
+        let module_name = &module.name;
+
+        let main_module = resolve_path(&module.file.to_string_lossy(), &cwd)?;
+        let module_root = get_module_root(&main_module)?;
+
+        let runtime_config = BootstrapOptions {
+            zinnia_version: env!("CARGO_PKG_VERSION"),
+            agent_version: format!("zinniad/{} {module_name}", env!("CARGO_PKG_VERSION")),
+            wallet_address: supervisor.wallet_address().into(),
+            station_id: config.station_id().into(),
+            reporter: create_reporter(args, &state_file, &module, &metrics, &job_history)?,
+            retrieval_backend: Arc::clone(&retrieval_backend),
+            metrics: Arc::clone(metrics.runtime()),
+            telemetry: args.otel_endpoint.clone().map(|endpoint| TelemetryOptions {
+                export_console: args.otel_console,
+                ..TelemetryOptions::new(endpoint, module_name.clone())
+            }),
+            module_root: Some(module_root),
+            rng_seed: None,
+            virtual_clock_start: None,
+            allow_net: module.allow_net.clone(),
+            retrieval_gate: retrieval_gate.clone(),
+            retrieval: retrieval.clone(),
+            tls: config.tls(),
+            proxy: proxy.clone(),
+            network_fixture: None,
+        };
+
+        log::info!("Starting module {main_module}");
+        let module_run = run_module_flushing_activities(
+            run_js_module(&main_module, &runtime_config),
+            runtime_config.reporter.as_ref(),
+        );
+        let outcome = supervisor.run(module_run).await;
+
+        // Drop the reporter to flush pending events before reporting the exit
+        drop(runtime_config);
+        match outcome {
+            ModuleOutcome::Finished(result) => {
+                if let Err(err) = &result {
+                    if let Some(CoreError::Js(js_error)) =
+                        any_and_jserrorbox_downcast_ref::<CoreError>(err)
+                    {
+                        log_uncaught_exception(module_name, js_error);
+                    }
+                }
+                log_module_exited(module_name, ModuleExit::Finished(&result));
+                break result;
+            }
+            ModuleOutcome::Restart => {
+                log_module_exited(module_name, ModuleExit::Restart);
+                metrics.module_restarted(module_name);
+            }
+            ModuleOutcome::Stop => log_module_exited(module_name, ModuleExit::Stopped),
+            ModuleOutcome::Shutdown => {
+                log_module_exited(module_name, ModuleExit::Shutdown);
+                break Ok(());
+            }
+        }
     };
 
-    // TODO: handle module exit and restart it
-    // https://github.com/filecoin-station/zinnia/issues/146
-    log::info!("Starting module {main_module}");
     #[allow(clippy::let_unit_value)]
-    let module_output = run_js_module(&main_module, &runtime_config).await?;
+    let module_output = result?;
 
     Ok(RunOutput {
         module_output,
         lassie_daemon,
+        lassie_temp_dir,
     })
 }
 
+/// Drive the module while reporting the activities held back by the reporter every
+/// `ACTIVITY_INTERVAL`. Otherwise they would be reported only after the module reports another
+/// activity or stops.
+async fn run_module_flushing_activities(
+    module: impl Future<Output = Result<()>>,
+    reporter: &dyn Reporter,
+) -> Result<()> {
+    let mut flush = tokio::time::interval_at(
+        tokio::time::Instant::now() + ACTIVITY_INTERVAL,
+        ACTIVITY_INTERVAL,
+    );
+    tokio::pin!(module);
+    loop {
+        tokio::select! {
+            result = &mut module => return result,
+            _ = flush.tick() => reporter.flush_activities(),
+        }
+    }
+}
+
+fn create_reporter(
+    args: &CliArgs,
+    state_file: &Path,
+    module: &ModuleConfig,
+    metrics: &Arc<Metrics>,
+    job_history: &Arc<JobHistory>,
+) -> Result<Rc<dyn Reporter>> {
+    let station_reporter = StationReporter::new(
+        state_file.to_path_buf(),
+        Duration::from_millis(200),
+        module.name.clone(),
+        Arc::clone(metrics),
+    )?
+    .with_activity_limits(ActivityLimits {
+        max_activities: module.max_activities_per_minute,
+        interval: ACTIVITY_INTERVAL,
+    })
+    .with_job_history(Arc::clone(job_history));
+    let reporter: Rc<dyn Reporter> = match &args.log_dir {
+        None => Rc::new(station_reporter),
+        Some(log_dir) => {
+            let log_file = PathBuf::from(log_dir).join("zinniad.log");
+            log::debug!("Writing logs to {}", log_file.display());
+            let rotation = RotationConfig {
+                max_size: args.log_max_size_mb * 1024 * 1024,
+                max_age: match args.log_max_age_hours {
+                    0 => None,
+                    hours => Some(Duration::from_secs(hours * 3600)),
+                },
+                max_files: args.log_max_files,
+                compress: args.log_compress,
+            };
+            Rc::new(FileReporter::new(
+                station_reporter,
+                RotatingFile::open(log_file, rotation)?,
+            ))
+        }
+    };
+    Ok(reporter)
+}
+
 #[allow(dead_code)]
 struct RunOutput {
     module_output: (),
     // for testing
     lassie_daemon: Arc<lassie::Daemon>,
+    lassie_temp_dir: PathBuf,
+}
+
+impl RunOutput {
+    /// Stop the Lassie daemon and remove temp files of retrievals that did not finish.
+    fn shutdown(self) {
+        match Arc::try_unwrap(self.lassie_daemon) {
+            Ok(daemon) => drop(daemon),
+            Err(_) => log::warn!("Cannot stop Lassie, the daemon is still in use"),
+        }
+        if let Err(err) = clean_lassie_tempdir(&self.lassie_temp_dir) {
+            log::warn!("Cannot clean Lassie tempdir: {err:?}");
+        }
+        log::info!("zinniad stopped");
+    }
 }
 
 fn setup_logger() {
@@ -126,6 +336,10 @@ fn setup_lassie_tempdir(lassie_temp_dir: &Path) -> Result<()> {
         return Ok(());
     }
 
+    clean_lassie_tempdir(lassie_temp_dir)
+}
+
+fn clean_lassie_tempdir(lassie_temp_dir: &Path) -> Result<()> {
     log::debug!(
         "Cleaning left-over files in Lassie tempdir {:?}",
         lassie_temp_dir
@@ -174,13 +388,51 @@ mod tests {
         let temp = assert_fs::TempDir::new().expect("cannot create a new temp directory");
 
         let args = CliArgs {
+            config: None,
             cache_root: temp.join("cache").to_string_lossy().into(),
             state_root: temp.join("state").to_string_lossy().into(),
-            wallet_address: "f1test".to_string(),
-            station_id: "a".repeat(88),
+            wallet_address: Some("f1test".to_string()),
+            station_id: Some("a".repeat(88)),
+            metrics_listen: None,
+            otel_endpoint: None,
+            otel_console: false,
+            ca_cert: vec![],
+            client_cert: None,
+            client_key: None,
+            unsafely_ignore_certificate_errors: None,
+            proxy: None,
+            no_proxy: None,
+            max_activities_per_minute: 30,
+            log_dir: None,
+            log_max_size_mb: 10,
+            log_max_age_hours: 24,
+            log_max_files: 10,
+            log_compress: true,
+            control: None,
+            shutdown_timeout_secs: 10,
+            lassie_provider_timeout_secs: 86400,
+            lassie_global_timeout_secs: 86400,
+            lassie_max_blocks: None,
+            max_concurrent_retrievals: None,
+            lassie_protocols: vec![],
+            lassie_providers: vec![],
+            lassie_denied_providers: vec![],
+            lassie_temp_max_size_mb: 10240,
+            lassie_temp_max_age_mins: 60,
+            min_free_disk_space_mb: 1024,
+            job_history_hours: 48,
+            job_history_days: 30,
             files: vec![mod_js.path().to_string_lossy().to_string()],
         };
-        let RunOutput { lassie_daemon, .. } = run(args).await.expect("cannot run dummy.js");
+        let config = Config {
+            modules: vec![ModuleConfig::from_cli_arg(&args.files[0], &args)],
+            args,
+            file: None,
+            matches: Default::default(),
+        };
+        let RunOutput { lassie_daemon, .. } = run(config, std::future::pending())
+            .await
+            .expect("cannot run dummy.js");
 
         assert!(
             lassie_daemon.access_token().is_some(),
@@ -0,0 +1,728 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+use zinnia_runtime::{Histogram, MetricKind, MetricUpdate, RuntimeMetrics, DURATION_BUCKETS};
+
+use crate::lassie_gc::CleanupReport;
+use crate::state::CounterState;
+
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Metrics collected by zinniad, exposed in the OpenMetrics text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    runtime: Arc<RuntimeMetrics>,
+    modules: Mutex<BTreeMap<String, ModuleMetrics>>,
+    custom: Mutex<CustomMetrics>,
+    lassie_tempdir: Mutex<TempDirMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct ModuleMetrics {
+    jobs_completed: u64,
+    info_activities: u64,
+    error_activities: u64,
+    restarts: u64,
+}
+
+#[derive(Debug, Default)]
+struct TempDirMetrics {
+    cleanups: u64,
+    removed_files: u64,
+    removed_bytes: u64,
+    size_bytes: u64,
+    disk_available_bytes: Option<u64>,
+    retrievals_paused: bool,
+}
+
+// Identifies a time series of a custom metric. The field order matters, we want to keep all series
+// of the same metric together when iterating over sorted collections.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SeriesKey {
+    name: String,
+    module: String,
+    labels: BTreeMap<String, String>,
+}
+
+/// Metrics defined by modules via `Zinnia.metrics` APIs
+#[derive(Debug, Default)]
+struct CustomMetrics {
+    kinds: BTreeMap<String, MetricKind>,
+    counters: BTreeMap<SeriesKey, f64>,
+    gauges: BTreeMap<SeriesKey, f64>,
+    histograms: BTreeMap<SeriesKey, Histogram>,
+}
+
+impl Metrics {
+    /// Metrics collected by the Zinnia runtime, pass this value to `BootstrapOptions`.
+    pub fn runtime(&self) -> &Arc<RuntimeMetrics> {
+        &self.runtime
+    }
+
+    pub fn job_completed(&self, module: &str) {
+        self.update_module(module, |m| m.jobs_completed += 1);
+    }
+
+    pub fn info_activity(&self, module: &str) {
+        self.update_module(module, |m| m.info_activities += 1);
+    }
+
+    pub fn error_activity(&self, module: &str) {
+        self.update_module(module, |m| m.error_activities += 1);
+    }
+
+    pub fn module_restarted(&self, module: &str) {
+        self.update_module(module, |m| m.restarts += 1);
+    }
+
+    /// Record a cleanup of Lassie's temp directory.
+    pub fn lassie_tempdir_cleaned(&self, report: &CleanupReport) {
+        let mut m = self.lassie_tempdir.lock().unwrap();
+        m.cleanups += 1;
+        m.removed_files += report.removed_files;
+        m.removed_bytes += report.removed_bytes;
+        m.size_bytes = report.remaining_bytes;
+    }
+
+    /// Record the free disk space available for Lassie's temp files.
+    pub fn disk_space_checked(&self, available_bytes: u64, retrievals_paused: bool) {
+        let mut m = self.lassie_tempdir.lock().unwrap();
+        m.disk_available_bytes = Some(available_bytes);
+        m.retrievals_paused = retrievals_paused;
+    }
+
+    /// Record an update of a custom metric reported by the module.
+    pub fn custom_metric(&self, module: &str, update: &MetricUpdate) {
+        let mut custom = self.custom.lock().unwrap();
+        let kind = *custom
+            .kinds
+            .entry(update.name.clone())
+            .or_insert(update.kind);
+        if kind != update.kind {
+            log::debug!(
+                "Ignoring update of {} {} from module {module}, the metric was already defined as {kind}",
+                update.kind,
+                update.name,
+            );
+            return;
+        }
+
+        let key = SeriesKey {
+            name: update.name.clone(),
+            module: module.to_string(),
+            labels: update.labels.clone(),
+        };
+        match update.kind {
+            MetricKind::Counter => *custom.counters.entry(key).or_default() += update.value,
+            MetricKind::Gauge => {
+                custom.gauges.insert(key, update.value);
+            }
+            MetricKind::Histogram => custom
+                .histograms
+                .entry(key)
+                .or_insert_with(|| Histogram::new(DURATION_BUCKETS))
+                .observe(update.value),
+        }
+    }
+
+    /// Restore custom counters loaded from the state file.
+    pub fn restore_counters(&self, module: &str, counters: &[CounterState]) {
+        let mut custom = self.custom.lock().unwrap();
+        for c in counters {
+            custom
+                .kinds
+                .entry(c.name.clone())
+                .or_insert(MetricKind::Counter);
+            let key = SeriesKey {
+                name: c.name.clone(),
+                module: module.to_string(),
+                labels: c.labels.clone(),
+            };
+            custom.counters.insert(key, c.value);
+        }
+    }
+
+    /// Custom counters of the given module, in the format we persist in the state file.
+    pub fn module_counters(&self, module: &str) -> Vec<CounterState> {
+        let custom = self.custom.lock().unwrap();
+        custom
+            .counters
+            .iter()
+            .filter(|(key, _)| key.module == module)
+            .map(|(key, value)| CounterState {
+                name: key.name.clone(),
+                labels: key.labels.clone(),
+                value: *value,
+            })
+            .collect()
+    }
+
+    /// Built-in counters of the given module since zinniad started, in the format we report
+    /// to Station.
+    pub fn module_totals(&self, module: &str) -> serde_json::Value {
+        let modules = self.modules.lock().unwrap();
+        let m = modules.get(module);
+        json!({
+            "jobsCompleted": m.map(|m| m.jobs_completed).unwrap_or_default(),
+            "infoActivities": m.map(|m| m.info_activities).unwrap_or_default(),
+            "errorActivities": m.map(|m| m.error_activities).unwrap_or_default(),
+            "restarts": m.map(|m| m.restarts).unwrap_or_default(),
+        })
+    }
+
+    /// Custom metrics of the given module, in the format we report in ND-JSON `stats` events.
+    pub fn module_stats(&self, module: &str) -> serde_json::Value {
+        let custom = self.custom.lock().unwrap();
+        let of_module = |key: &&SeriesKey| key.module == module;
+
+        let counters: Vec<_> = custom
+            .counters
+            .iter()
+            .filter(|(key, _)| of_module(key))
+            .map(|(key, value)| json!({ "name": key.name, "labels": key.labels, "value": value }))
+            .collect();
+        let gauges: Vec<_> = custom
+            .gauges
+            .iter()
+            .filter(|(key, _)| of_module(key))
+            .map(|(key, value)| json!({ "name": key.name, "labels": key.labels, "value": value }))
+            .collect();
+        let histograms: Vec<_> = custom
+            .histograms
+            .iter()
+            .filter(|(key, _)| of_module(key))
+            .map(|(key, h)| {
+                json!({
+                    "name": key.name,
+                    "labels": key.labels,
+                    "count": h.count(),
+                    "sum": h.sum(),
+                })
+            })
+            .collect();
+
+        json!({
+            "counters": counters,
+            "gauges": gauges,
+            "histograms": histograms,
+        })
+    }
+
+    fn update_module<F: FnOnce(&mut ModuleMetrics)>(&self, module: &str, f: F) {
+        let mut modules = self.modules.lock().unwrap();
+        match modules.get_mut(module) {
+            Some(m) => f(m),
+            None => {
+                let mut m = ModuleMetrics::default();
+                f(&mut m);
+                modules.insert(module.to_string(), m);
+            }
+        }
+    }
+
+    /// Render all metrics in the OpenMetrics text format.
+    pub fn encode(&self) -> String {
+        let mut out = OpenMetricsWriter::default();
+
+        {
+            let modules = self.modules.lock().unwrap();
+
+            out.family(
+                "zinnia_jobs_completed",
+                "counter",
+                "Number of jobs completed by the module since zinniad started.",
+            );
+            for (name, m) in modules.iter() {
+                out.sample(
+                    "zinnia_jobs_completed_total",
+                    &[("module", name.as_str())],
+                    m.jobs_completed,
+                );
+            }
+
+            out.family(
+                "zinnia_activities",
+                "counter",
+                "Number of activities reported by the module.",
+            );
+            for (name, m) in modules.iter() {
+                out.sample(
+                    "zinnia_activities_total",
+                    &[("module", name.as_str()), ("level", "info")],
+                    m.info_activities,
+                );
+                out.sample(
+                    "zinnia_activities_total",
+                    &[("module", name.as_str()), ("level", "error")],
+                    m.error_activities,
+                );
+            }
+
+            out.family(
+                "zinnia_module_restarts",
+                "counter",
+                "Number of times the module was restarted.",
+            );
+            for (name, m) in modules.iter() {
+                out.sample(
+                    "zinnia_module_restarts_total",
+                    &[("module", name.as_str())],
+                    m.restarts,
+                );
+            }
+        }
+
+        let rt = &self.runtime;
+
+        out.family(
+            "zinnia_fetch_requests",
+            "counter",
+            "Number of requests made via the Fetch API.",
+        );
+        out.sample(
+            "zinnia_fetch_requests_total",
+            &[("scheme", "http")],
+            rt.http_requests.get(),
+        );
+        out.sample(
+            "zinnia_fetch_requests_total",
+            &[("scheme", "ipfs")],
+            rt.ipfs_requests.get(),
+        );
+
+        out.family(
+            "zinnia_fetch_received_bytes",
+            "counter",
+            "Number of response body bytes received via the Fetch API.",
+        );
+        out.sample(
+            "zinnia_fetch_received_bytes_total",
+            &[],
+            rt.fetch_bytes_received.get(),
+        );
+
+        out.family(
+            "zinnia_ipfs_retrievals",
+            "counter",
+            "Number of finished IPFS retrievals.",
+        );
+        out.sample(
+            "zinnia_ipfs_retrievals_total",
+            &[("result", "success")],
+            rt.ipfs_retrievals_succeeded.get(),
+        );
+        out.sample(
+            "zinnia_ipfs_retrievals_total",
+            &[("result", "failure")],
+            rt.ipfs_retrievals_failed.get(),
+        );
+
+        out.histogram(
+            "zinnia_ipfs_retrieval_duration_seconds",
+            "Time spent retrieving the full content of IPFS requests.",
+            &[],
+            &rt.ipfs_retrieval_duration,
+        );
+
+        out.family(
+            "zinnia_heap_used_bytes",
+            "gauge",
+            "V8 heap memory used by the module.",
+        );
+        out.sample("zinnia_heap_used_bytes", &[], rt.heap_used_bytes.get());
+
+        out.family(
+            "zinnia_heap_total_bytes",
+            "gauge",
+            "V8 heap memory allocated for the module.",
+        );
+        out.sample("zinnia_heap_total_bytes", &[], rt.heap_total_bytes.get());
+
+        out.family(
+            "zinnia_event_loop_lag_seconds",
+            "gauge",
+            "How late the last event-loop timer fired.",
+        );
+        out.sample(
+            "zinnia_event_loop_lag_seconds",
+            &[],
+            rt.event_loop_lag_seconds.get(),
+        );
+
+        self.lassie_tempdir.lock().unwrap().encode(&mut out);
+        self.custom.lock().unwrap().encode(&mut out);
+
+        out.finish()
+    }
+}
+
+impl TempDirMetrics {
+    fn encode(&self, out: &mut OpenMetricsWriter) {
+        out.family(
+            "zinnia_lassie_tempdir_cleanups",
+            "counter",
+            "Number of cleanups of Lassie's temp directory.",
+        );
+        out.sample("zinnia_lassie_tempdir_cleanups_total", &[], self.cleanups);
+
+        out.family(
+            "zinnia_lassie_tempdir_removed_files",
+            "counter",
+            "Number of stale files removed from Lassie's temp directory.",
+        );
+        out.sample(
+            "zinnia_lassie_tempdir_removed_files_total",
+            &[],
+            self.removed_files,
+        );
+
+        out.family(
+            "zinnia_lassie_tempdir_removed_bytes",
+            "counter",
+            "Size of stale files removed from Lassie's temp directory.",
+        );
+        out.sample(
+            "zinnia_lassie_tempdir_removed_bytes_total",
+            &[],
+            self.removed_bytes,
+        );
+
+        out.family(
+            "zinnia_lassie_tempdir_size_bytes",
+            "gauge",
+            "Size of files in Lassie's temp directory after the last cleanup.",
+        );
+        out.sample("zinnia_lassie_tempdir_size_bytes", &[], self.size_bytes);
+
+        if let Some(available) = self.disk_available_bytes {
+            out.family(
+                "zinnia_disk_available_bytes",
+                "gauge",
+                "Free disk space available for Lassie's temp files.",
+            );
+            out.sample("zinnia_disk_available_bytes", &[], available);
+        }
+
+        out.family(
+            "zinnia_ipfs_retrievals_paused",
+            "gauge",
+            "Whether IPFS retrievals are paused because the disk is nearly full.",
+        );
+        out.sample(
+            "zinnia_ipfs_retrievals_paused",
+            &[],
+            u64::from(self.retrievals_paused),
+        );
+    }
+}
+
+impl CustomMetrics {
+    fn encode(&self, out: &mut OpenMetricsWriter) {
+        let mut last_family = None;
+        let mut start_family = |out: &mut OpenMetricsWriter, key: &SeriesKey, kind: &str| {
+            if last_family != Some(key.name.clone()) {
+                out.family(
+                    &custom_metric_name(&key.name),
+                    kind,
+                    "Custom metric defined by the module.",
+                );
+                last_family = Some(key.name.clone());
+            }
+        };
+
+        for (key, value) in self.counters.iter() {
+            start_family(out, key, "counter");
+            let name = format!("{}_total", custom_metric_name(&key.name));
+            out.sample(&name, &key.series_labels(), *value);
+        }
+
+        for (key, value) in self.gauges.iter() {
+            start_family(out, key, "gauge");
+            out.sample(&custom_metric_name(&key.name), &key.series_labels(), *value);
+        }
+
+        for (key, histogram) in self.histograms.iter() {
+            start_family(out, key, "histogram");
+            out.histogram_samples(
+                &custom_metric_name(&key.name),
+                &key.series_labels(),
+                histogram,
+            );
+        }
+    }
+}
+
+impl SeriesKey {
+    fn series_labels(&self) -> Vec<(&str, &str)> {
+        std::iter::once(("module", self.module.as_str()))
+            .chain(self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .collect()
+    }
+}
+
+// Prefix custom metrics to avoid conflicts with metrics provided by zinniad
+fn custom_metric_name(name: &str) -> String {
+    let name = name.strip_suffix("_total").unwrap_or(name);
+    format!("zinnia_custom_{name}")
+}
+
+/// A minimal writer producing the OpenMetrics text exposition format.
+/// See https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+#[derive(Default)]
+pub struct OpenMetricsWriter {
+    buf: String,
+}
+
+impl OpenMetricsWriter {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.buf, "# TYPE {name} {kind}");
+        let _ = writeln!(self.buf, "# HELP {name} {}", escape(help));
+    }
+
+    pub fn sample<V: MetricValue>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.buf.push_str(name);
+        if !labels.is_empty() {
+            self.buf.push('{');
+            for (ix, (key, val)) in labels.iter().enumerate() {
+                if ix > 0 {
+                    self.buf.push(',');
+                }
+                let _ = write!(self.buf, "{key}=\"{}\"", escape(val));
+            }
+            self.buf.push('}');
+        }
+        let _ = writeln!(self.buf, " {}", value.format());
+    }
+
+    pub fn histogram(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        histogram: &Histogram,
+    ) {
+        self.family(name, "histogram", help);
+        self.histogram_samples(name, labels, histogram);
+    }
+
+    pub fn histogram_samples(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        histogram: &Histogram,
+    ) {
+        let bucket_name = format!("{name}_bucket");
+        for (bound, count) in histogram.cumulative_buckets() {
+            let le = bound.format();
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            self.sample(&bucket_name, &bucket_labels, count);
+        }
+        self.sample(&format!("{name}_count"), labels, histogram.count());
+        self.sample(&format!("{name}_sum"), labels, histogram.sum());
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buf.push_str("# EOF\n");
+        self.buf
+    }
+}
+
+pub trait MetricValue {
+    fn format(&self) -> String;
+}
+
+impl MetricValue for u64 {
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl MetricValue for f64 {
+    fn format(&self) -> String {
+        if self.is_nan() {
+            "NaN".into()
+        } else if self.is_infinite() {
+            if self.is_sign_positive() {
+                "+Inf".into()
+            } else {
+                "-Inf".into()
+            }
+        } else {
+            // Debug formatting always includes the decimal point, e.g. `1.0`
+            format!("{self:?}")
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn encodes_module_metrics() {
+        let metrics = Metrics::default();
+        metrics.job_completed("saturn");
+        metrics.job_completed("saturn");
+        metrics.error_activity("saturn");
+
+        let text = metrics.encode();
+        assert!(
+            text.contains("zinnia_jobs_completed_total{module=\"saturn\"} 2\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("zinnia_activities_total{module=\"saturn\",level=\"info\"} 0\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("zinnia_activities_total{module=\"saturn\",level=\"error\"} 1\n"),
+            "{text}"
+        );
+        assert!(text.ends_with("# EOF\n"), "{text}");
+    }
+
+    #[test]
+    fn encodes_lassie_tempdir_metrics() {
+        let metrics = Metrics::default();
+        metrics.lassie_tempdir_cleaned(&CleanupReport {
+            removed_files: 2,
+            removed_bytes: 300,
+            remaining_bytes: 100,
+        });
+        metrics.disk_space_checked(4096, true);
+
+        let text = metrics.encode();
+        for line in [
+            "zinnia_lassie_tempdir_cleanups_total 1\n",
+            "zinnia_lassie_tempdir_removed_files_total 2\n",
+            "zinnia_lassie_tempdir_removed_bytes_total 300\n",
+            "zinnia_lassie_tempdir_size_bytes 100\n",
+            "zinnia_disk_available_bytes 4096\n",
+            "zinnia_ipfs_retrievals_paused 1\n",
+        ] {
+            assert!(text.contains(line), "{line:?} not found in {text}");
+        }
+    }
+
+    #[test]
+    fn reports_module_totals() {
+        let metrics = Metrics::default();
+        metrics.job_completed("saturn");
+        metrics.info_activity("saturn");
+        metrics.module_restarted("saturn");
+
+        assert_eq!(
+            metrics.module_totals("saturn"),
+            json!({
+                "jobsCompleted": 1,
+                "infoActivities": 1,
+                "errorActivities": 0,
+                "restarts": 1,
+            })
+        );
+        assert_eq!(metrics.module_totals("unknown")["jobsCompleted"], 0);
+    }
+
+    #[test]
+    fn encodes_custom_metrics() {
+        let metrics = Metrics::default();
+        let update = |kind, name: &str, value| MetricUpdate {
+            kind,
+            name: name.into(),
+            labels: BTreeMap::from([("status".into(), "ok".into())]),
+            value,
+        };
+        metrics.custom_metric("saturn", &update(MetricKind::Counter, "requests", 1.0));
+        metrics.custom_metric("saturn", &update(MetricKind::Counter, "requests", 2.0));
+        metrics.custom_metric("saturn", &update(MetricKind::Gauge, "peers", 10.0));
+        metrics.custom_metric("saturn", &update(MetricKind::Gauge, "peers", 7.0));
+        // conflicting kind is ignored
+        metrics.custom_metric("saturn", &update(MetricKind::Gauge, "requests", 5.0));
+
+        let text = metrics.encode();
+        assert!(
+            text.contains(concat!(
+                "# TYPE zinnia_custom_requests counter\n",
+                "# HELP zinnia_custom_requests Custom metric defined by the module.\n",
+                "zinnia_custom_requests_total{module=\"saturn\",status=\"ok\"} 3.0\n",
+            )),
+            "{text}"
+        );
+        assert!(
+            text.contains("zinnia_custom_peers{module=\"saturn\",status=\"ok\"} 7.0\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn restores_counters() {
+        let metrics = Metrics::default();
+        let counters = vec![CounterState {
+            name: "requests".into(),
+            labels: BTreeMap::new(),
+            value: 10.0,
+        }];
+        metrics.restore_counters("saturn", &counters);
+        metrics.custom_metric(
+            "saturn",
+            &MetricUpdate {
+                kind: MetricKind::Counter,
+                name: "requests".into(),
+                labels: BTreeMap::new(),
+                value: 1.0,
+            },
+        );
+
+        assert_eq!(
+            metrics.module_counters("saturn"),
+            vec![CounterState {
+                name: "requests".into(),
+                labels: BTreeMap::new(),
+                value: 11.0,
+            }]
+        );
+        assert_eq!(metrics.module_counters("other"), vec![]);
+    }
+
+    #[test]
+    fn encodes_histogram() {
+        let histogram = Histogram::new(&[0.5, 1.0]);
+        histogram.observe(0.7);
+
+        let mut out = OpenMetricsWriter::default();
+        out.histogram("duration_seconds", "Some help.", &[("a", "b")], &histogram);
+
+        assert_eq!(
+            out.finish(),
+            [
+                "# TYPE duration_seconds histogram\n",
+                "# HELP duration_seconds Some help.\n",
+                "duration_seconds_bucket{a=\"b\",le=\"0.5\"} 0\n",
+                "duration_seconds_bucket{a=\"b\",le=\"1.0\"} 1\n",
+                "duration_seconds_bucket{a=\"b\",le=\"+Inf\"} 1\n",
+                "duration_seconds_count{a=\"b\"} 1\n",
+                "duration_seconds_sum{a=\"b\"} 0.7\n",
+                "# EOF\n",
+            ]
+            .join("")
+        );
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut out = OpenMetricsWriter::default();
+        out.sample("x", &[("module", "a\"b\\c\nd")], 1u64);
+        assert_eq!(out.finish(), "x{module=\"a\\\"b\\\\c\\nd\"} 1\n# EOF\n");
+    }
+}
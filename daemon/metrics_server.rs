@@ -0,0 +1,154 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::net::{TcpListener, TcpStream};
+use zinnia_runtime::anyhow::{Context, Result};
+
+use crate::metrics::{Metrics, OPENMETRICS_CONTENT_TYPE};
+
+// Clients must send the request headers within this time. Hyper limits the size of the headers.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Close connections kept open for longer, e.g. by clients that don't read the response
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Start an HTTP server providing metrics at `GET /metrics` in the background.
+/// Returns the address where the server is listening.
+pub async fn start_metrics_server(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("cannot start the metrics server on {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    log::info!("Serving metrics at http://{local_addr}/metrics");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(handle_connection(socket, Arc::clone(&metrics)));
+                }
+                Err(err) => log::warn!("Cannot accept metrics connection: {err}"),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle_connection(socket: TcpStream, metrics: Arc<Metrics>) {
+    let service = service_fn(move |request| {
+        let response = respond(&request, &metrics);
+        async move { Ok::<_, Infallible>(response) }
+    });
+    let connection = http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(HEADER_READ_TIMEOUT)
+        .keep_alive(false)
+        .serve_connection(TokioIo::new(socket), service);
+
+    // We are ignoring errors because there isn't much to do in such case
+    match tokio::time::timeout(CONNECTION_TIMEOUT, connection).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::debug!("Cannot handle metrics request: {err}"),
+        Err(_) => log::debug!("Metrics connection timed out"),
+    }
+}
+
+fn respond(request: &Request<Incoming>, metrics: &Metrics) -> Response<Full<Bytes>> {
+    let (status, content_type, body) = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => (StatusCode::OK, OPENMETRICS_CONTENT_TYPE, metrics.encode()),
+        (_, "/metrics") => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "text/plain; charset=utf-8",
+            "Method Not Allowed\n".to_string(),
+        ),
+        _ => (
+            StatusCode::NOT_FOUND,
+            "text/plain; charset=utf-8",
+            "Not Found\n".to_string(),
+        ),
+    };
+
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn serves_metrics() -> Result<()> {
+        let metrics = Arc::new(Metrics::default());
+        metrics.job_completed("test");
+        let addr = start_metrics_server("127.0.0.1:0".parse()?, metrics).await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(
+            response.contains("zinnia_jobs_completed_total{module=\"test\"} 1\n"),
+            "{response}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_paths() -> Result<()> {
+        let metrics = Arc::new(Metrics::default());
+        let addr = start_metrics_server("127.0.0.1:0".parse()?, metrics).await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{response}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_connections_without_request_headers() -> Result<()> {
+        let metrics = Arc::new(Metrics::default());
+        let addr = start_metrics_server("127.0.0.1:0".parse()?, metrics).await?;
+
+        // The header line never ends
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nX-Padding: ")
+            .await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        let response = String::from_utf8_lossy(&response);
+        assert!(
+            response.is_empty() || response.starts_with("HTTP/1.1 408 "),
+            "{response}"
+        );
+        Ok(())
+    }
+}
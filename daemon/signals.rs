@@ -0,0 +1,144 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use zinnia_runtime::anyhow::{anyhow, Context, Result};
+
+/// A signal asking zinniad to shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// SIGINT on Unix, Ctrl+C on Windows
+    Interrupt,
+    /// SIGTERM (Unix only)
+    Terminate,
+}
+
+impl ShutdownSignal {
+    /// The exit code to use when zinniad does not shut down gracefully before the deadline.
+    /// We follow the convention used by shells for processes terminated by a signal.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ShutdownSignal::Interrupt => 128 + 2,
+            ShutdownSignal::Terminate => 128 + 15,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownSignal::Interrupt => write!(f, "SIGINT"),
+            ShutdownSignal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+/// Listen for SIGINT and SIGTERM. The returned future completes when the first signal arrives,
+/// the caller should then stop the module and persist the state. When zinniad does not exit
+/// within the given deadline or receives another signal, we exit the process immediately.
+///
+/// Call this function before starting the runtime, so that zinniad can shut down gracefully
+/// while it's still initializing.
+///
+/// Signals are handled on a dedicated thread, because a module stuck in a busy loop blocks
+/// the main thread and we must be able to enforce the deadline in such case too.
+pub fn handle_shutdown_signals(deadline: Duration) -> Result<impl Future<Output = ShutdownSignal>> {
+    let (tx, mut rx) = mpsc::channel(1);
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    std::thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = ready_tx.send(Err(anyhow!(err)));
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                let mut signals = match Signals::new() {
+                    Ok(signals) => signals,
+                    Err(err) => {
+                        let _ = ready_tx.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+
+                let signal = signals.recv().await;
+                log::info!("Received {signal}, shutting down");
+                // The receiver is gone when zinniad is already shutting down
+                let _ = tx.send(signal).await;
+
+                tokio::select! {
+                    _ = tokio::time::sleep(deadline) => {
+                        log::error!("zinniad did not shut down within {deadline:?}, exiting");
+                    }
+                    again = signals.recv() => {
+                        log::warn!("Received {again} again, exiting immediately");
+                    }
+                }
+                zinnia_runtime::exit(signal.exit_code());
+            });
+        })
+        .context("cannot start the signal handler")?;
+
+    ready_rx
+        .recv()
+        .context("the signal handler exited unexpectedly")??;
+    Ok(async move {
+        match rx.recv().await {
+            Some(signal) => signal,
+            // The signal handler exits the process right after dropping the sender
+            None => std::future::pending().await,
+        }
+    })
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt()).context("cannot listen for SIGINT")?,
+            terminate: signal(SignalKind::terminate()).context("cannot listen for SIGTERM")?,
+        })
+    }
+
+    async fn recv(&mut self) -> ShutdownSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => ShutdownSignal::Interrupt,
+            _ = self.terminate.recv() => ShutdownSignal::Terminate,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals {
+    ctrl_c: tokio::signal::windows::CtrlC,
+}
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            ctrl_c: tokio::signal::windows::ctrl_c().context("cannot listen for Ctrl+C")?,
+        })
+    }
+
+    async fn recv(&mut self) -> ShutdownSignal {
+        self.ctrl_c.recv().await;
+        ShutdownSignal::Interrupt
+    }
+}
@@ -1,61 +1,283 @@
 use atomicwrites::{AtomicFile, OverwriteBehavior};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
 use std::io::Write;
-use std::path::Path;
-use zinnia_runtime::anyhow::{self, Context, Result};
+use std::path::{Path, PathBuf};
+use zinnia_runtime::anyhow::{self, anyhow, Context, Result};
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// Version of the state file schema written by this build of zinniad. Increment it when changing
+/// the structure of `State` and add a migration from the previous version to `MIGRATIONS`.
+pub const STATE_VERSION: u64 = 3;
+
+/// Migrations upgrading the state file from version `N` to `N+1`, where `N` is the index in this
+/// list plus one. State files created before we introduced versioning are treated as version 1.
+const MIGRATIONS: &[fn(serde_json::Value) -> Result<serde_json::Value>] =
+    &[migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// State files created before version 3 did not record the module name. We keep their data under
+/// this key until a module claims it, see `State::start_module`.
+const UNNAMED_MODULE: &str = "";
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct State {
-    pub total_jobs_completed: u64,
+    pub version: u64,
+
+    /// State of each module, keyed by the module name
+    #[serde(default)]
+    pub modules: BTreeMap<String, ModuleState>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            version: STATE_VERSION,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ModuleState {
+    pub jobs_completed: u64,
+
+    /// Custom counters defined by the module via `Zinnia.metrics.counter()`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub counters: Vec<CounterState>,
+
+    /// When was the module started for the first time
+    #[serde(default)]
+    pub first_run_at: Option<DateTime<Utc>>,
+
+    /// When was the module started most recently
+    #[serde(default)]
+    pub last_run_at: Option<DateTime<Utc>>,
+
+    /// How many times was the module started, including normal zinniad starts and upgrades
+    #[serde(default)]
+    pub starts: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CounterState {
+    pub name: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
 }
 
 impl State {
+    /// The total number of jobs completed by all modules.
+    pub fn total_jobs_completed(&self) -> u64 {
+        self.modules.values().map(|m| m.jobs_completed).sum()
+    }
+
+    /// Record a new run of the given module and return its state.
+    pub fn start_module(&mut self, module_name: &str, now: DateTime<Utc>) -> &mut ModuleState {
+        // The data migrated from an older state file belongs to the first module we run
+        if let Some(unnamed) = self.modules.remove(UNNAMED_MODULE) {
+            if !self.modules.contains_key(module_name) {
+                self.modules.insert(module_name.to_string(), unnamed);
+            }
+        }
+
+        let module = self.module_mut(module_name);
+        module.first_run_at.get_or_insert(now);
+        module.last_run_at = Some(now);
+        module.starts += 1;
+        module
+    }
+
+    /// The state of the given module, creating an empty state if needed.
+    pub fn module_mut(&mut self, module_name: &str) -> &mut ModuleState {
+        self.modules.entry(module_name.to_string()).or_default()
+    }
+
+    /// Load the state from the given file, upgrading it to the current schema version.
+    ///
+    /// When the state file is corrupted, we move it aside and recover the state from the backup
+    /// created by the last successful load. We never recover from a state file created by a newer
+    /// version of zinniad, because we would lose the data we don't understand.
     pub fn load(state_file: &Path) -> Result<Self> {
         log::debug!("Loading initial state from {}", state_file.display());
-        match std::fs::read_to_string(state_file) {
-            Err(err) => match err.kind() {
-                std::io::ErrorKind::NotFound => {
-                    let state = State::default();
-                    log::debug!("State file not found, returning {state:?}");
-                    Ok(state)
+        let backup_file = backup_path(state_file);
+
+        match Self::load_file(state_file) {
+            Ok(None) => {
+                let state = State::default();
+                log::debug!("State file not found, returning {state:?}");
+                Ok(state)
+            }
+            Ok(Some(state)) => {
+                log::debug!("Loaded initial state: {state:?}");
+                // Keep the state we were able to load as the last good backup
+                if let Err(err) = std::fs::copy(state_file, &backup_file) {
+                    log::warn!("Cannot back up state to {}: {err}", backup_file.display());
                 }
-                _ => Err(anyhow::Error::new(err).context(format!(
-                    "Cannot load initial state from {}",
-                    state_file.display()
-                ))),
-            },
-            Ok(data) => {
-                let state = serde_json::from_str::<State>(&data).with_context(|| {
-                    format!("Cannot parse initial state from {}", state_file.display())
+                Ok(state)
+            }
+            Err(err) if is_unrecoverable(&err) => Err(err),
+            Err(err) => {
+                log::warn!("{err:#}");
+                let corrupted_file = with_suffix(state_file, ".corrupted");
+                std::fs::rename(state_file, &corrupted_file).with_context(|| {
+                    format!(
+                        "Cannot move corrupted state file {} to {}",
+                        state_file.display(),
+                        corrupted_file.display(),
+                    )
                 })?;
-                log::debug!("Loaded initial state: {state:?}");
+                log::warn!(
+                    "Moved corrupted state file to {}, recovering from {}",
+                    corrupted_file.display(),
+                    backup_file.display(),
+                );
+
+                let state = match Self::load_file(&backup_file) {
+                    Ok(Some(state)) => state,
+                    Ok(None) => {
+                        log::warn!("State backup not found, starting with an empty state");
+                        State::default()
+                    }
+                    Err(err) if is_unrecoverable(&err) => return Err(err),
+                    Err(err) => {
+                        log::warn!("{err:#}");
+                        log::warn!("Cannot recover the state, starting with an empty state");
+                        State::default()
+                    }
+                };
+                log::debug!("Recovered initial state: {state:?}");
                 Ok(state)
             }
         }
     }
 
+    // Returns `Ok(None)` when the file does not exist.
+    fn load_file(state_file: &Path) -> Result<Option<Self>> {
+        let data = match std::fs::read_to_string(state_file) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(anyhow::Error::new(err).context(format!(
+                    "Cannot load initial state from {}",
+                    state_file.display()
+                )))
+            }
+        };
+
+        let parse = || {
+            let value = serde_json::from_str::<serde_json::Value>(&data)?;
+            let value = migrate(value)?;
+            let state = serde_json::from_value::<State>(value)?;
+            Ok::<State, anyhow::Error>(state)
+        };
+        let state = parse()
+            .with_context(|| format!("Cannot parse initial state from {}", state_file.display()))?;
+        Ok(Some(state))
+    }
+
     pub fn store(&self, state_file: &Path) -> Result<()> {
         let payload = serde_json::to_string_pretty(self).context("Cannot serialize state")?;
+        write_atomically(state_file, &payload)
+            .with_context(|| format!("Cannot write state to {}", state_file.display()))?;
+        log::debug!("State stored in {}", state_file.display());
+        Ok(())
+    }
+}
 
-        let mut write_result = AtomicFile::new(state_file, OverwriteBehavior::AllowOverwrite)
-            .write(|f| f.write_all(payload.as_bytes()));
-
-        if let Err(atomicwrites::Error::Internal(err)) = &write_result {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                if let Some(parent) = state_file.parent() {
-                    std::fs::create_dir_all(parent).with_context(|| {
-                        format!("Cannot create state directory {}", parent.display(),)
-                    })?;
-                    write_result = AtomicFile::new(state_file, OverwriteBehavior::AllowOverwrite)
-                        .write(|f| f.write_all(payload.as_bytes()));
-                }
+/// Replace the content of the given file atomically, creating the parent directory when needed.
+pub fn write_atomically(file: &Path, payload: &str) -> Result<()> {
+    let mut write_result = AtomicFile::new(file, OverwriteBehavior::AllowOverwrite)
+        .write(|f| f.write_all(payload.as_bytes()));
+
+    if let Err(atomicwrites::Error::Internal(err)) = &write_result {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent).with_context(|| {
+                    format!("Cannot create state directory {}", parent.display(),)
+                })?;
+                write_result = AtomicFile::new(file, OverwriteBehavior::AllowOverwrite)
+                    .write(|f| f.write_all(payload.as_bytes()));
             }
         }
+    }
 
-        write_result.with_context(|| format!("Cannot write state to {}", state_file.display()))?;
-        log::debug!("State stored in {}", state_file.display());
-        Ok(())
+    write_result?;
+    Ok(())
+}
+
+/// The state file was created by a newer version of zinniad.
+#[derive(Debug)]
+struct UnsupportedVersion(u64);
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The state file was created by a newer version of zinniad (state version {}, \
+            this version supports up to {}). Downgrading is not supported, please upgrade zinniad.",
+            self.0, STATE_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+// We can recover only from corrupted content, not from I/O errors or newer state versions
+fn is_unrecoverable(err: &anyhow::Error) -> bool {
+    err.is::<UnsupportedVersion>() || err.is::<std::io::Error>()
+}
+
+fn migrate(mut value: serde_json::Value) -> Result<serde_json::Value> {
+    let mut version = match value.get("version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| anyhow!("Invalid state version {v}"))?,
+    };
+    if version > STATE_VERSION {
+        return Err(UnsupportedVersion(version).into());
+    }
+    while version < STATE_VERSION {
+        log::info!("Migrating state from version {version} to {}", version + 1);
+        value = MIGRATIONS[(version - 1) as usize](value)
+            .with_context(|| format!("Cannot migrate state from version {version}"))?;
+        version += 1;
+        debug_assert_eq!(value["version"], json!(version));
     }
+    Ok(value)
+}
+
+// Version 2 introduced the `version` field, the data did not change
+fn migrate_v1_to_v2(mut value: serde_json::Value) -> Result<serde_json::Value> {
+    let state = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("State must be an object"))?;
+    state.insert("version".into(), json!(2));
+    Ok(value)
+}
+
+// Version 3 keeps a separate state for each module. Older files did not record the module name.
+fn migrate_v2_to_v3(value: serde_json::Value) -> Result<serde_json::Value> {
+    let module = json!({
+        "jobs_completed": value.get("total_jobs_completed").cloned().unwrap_or(json!(0)),
+        "counters": value.get("counters").cloned().unwrap_or(json!([])),
+    });
+    Ok(json!({
+        "version": 3,
+        "modules": { UNNAMED_MODULE: module },
+    }))
+}
+
+fn backup_path(state_file: &Path) -> PathBuf {
+    with_suffix(state_file, ".bak")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
 }
 
 #[cfg(test)]
@@ -65,12 +287,18 @@ mod tests {
     use tempfile::tempdir;
     use zinnia_runtime::anyhow::Result;
 
+    fn state_with_jobs(module_name: &str, jobs_completed: u64) -> State {
+        let mut state = State::default();
+        state.module_mut(module_name).jobs_completed = jobs_completed;
+        state
+    }
+
     #[test]
     fn loads_empty_state() -> Result<()> {
         let state_dir = tempdir()?;
         let state_file = state_dir.path().join("state.json");
         let loaded = State::load(&state_file)?;
-        assert_eq!(loaded.total_jobs_completed, 0, "total_jobs_completed");
+        assert_eq!(loaded.total_jobs_completed(), 0, "total_jobs_completed");
         Ok(())
     }
 
@@ -78,12 +306,140 @@ mod tests {
     fn creates_missing_directories() -> Result<()> {
         let state_dir = tempdir()?;
         let state_file = state_dir.path().join("subdir").join("state.json");
-        let state = State {
-            total_jobs_completed: 1,
-        };
+        state_with_jobs("saturn", 1).store(&state_file)?;
+        let loaded = State::load(&state_file)?;
+        assert_eq!(loaded.total_jobs_completed(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn stores_counters() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        let counters = vec![CounterState {
+            name: "requests".into(),
+            labels: BTreeMap::from([("status".into(), "ok".into())]),
+            value: 3.0,
+        }];
+        let mut state = state_with_jobs("saturn", 1);
+        state.module_mut("saturn").counters = counters.clone();
         state.store(&state_file)?;
         let loaded = State::load(&state_file)?;
-        assert_eq!(loaded.total_jobs_completed, 1);
+        assert_eq!(loaded.modules["saturn"].counters, counters);
+        Ok(())
+    }
+
+    #[test]
+    fn derives_total_from_modules() {
+        let mut state = state_with_jobs("saturn", 3);
+        state.module_mut("spark").jobs_completed = 4;
+        assert_eq!(state.total_jobs_completed(), 7);
+    }
+
+    #[test]
+    fn records_module_runs() {
+        let mut state = State::default();
+        let first = Utc::now();
+        let module = state.start_module("saturn", first);
+        assert_eq!(module.first_run_at, Some(first));
+        assert_eq!(module.starts, 1);
+
+        let second = first + chrono::Duration::seconds(10);
+        let module = state.start_module("saturn", second);
+        assert_eq!(module.first_run_at, Some(first));
+        assert_eq!(module.last_run_at, Some(second));
+        assert_eq!(module.starts, 2);
+    }
+
+    #[test]
+    fn loads_state_without_counters() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        std::fs::write(&state_file, r#"{"total_jobs_completed":7}"#)?;
+        let mut loaded = State::load(&state_file)?;
+        assert_eq!(loaded.total_jobs_completed(), 7);
+        assert_eq!(loaded.start_module("saturn", Utc::now()).counters, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_unversioned_state() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        std::fs::write(
+            &state_file,
+            r#"{"total_jobs_completed":7,"counters":[{"name":"requests","value":2.0}]}"#,
+        )?;
+
+        let mut loaded = State::load(&state_file)?;
+        assert_eq!(loaded.version, STATE_VERSION);
+        let module = loaded.start_module("saturn", Utc::now());
+        assert_eq!(module.jobs_completed, 7);
+        assert_eq!(module.counters.len(), 1);
+        assert_eq!(module.starts, 1);
+        loaded.store(&state_file)?;
+
+        let stored: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&state_file)?)?;
+        assert_eq!(stored["version"], json!(STATE_VERSION));
+        assert_eq!(stored["modules"]["saturn"]["jobs_completed"], 7);
+        assert_eq!(stored["modules"].as_object().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_overwrite_module_state_with_unnamed_state() {
+        let mut state = state_with_jobs("saturn", 3);
+        state.module_mut(UNNAMED_MODULE).jobs_completed = 10;
+
+        assert_eq!(state.start_module("saturn", Utc::now()).jobs_completed, 3);
+        assert!(!state.modules.contains_key(UNNAMED_MODULE));
+    }
+
+    #[test]
+    fn refuses_to_downgrade() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        let data = format!(r#"{{"version":{},"modules":{{}}}}"#, STATE_VERSION + 1);
+        std::fs::write(&state_file, &data)?;
+
+        let err = State::load(&state_file).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("created by a newer version of zinniad"),
+            "{err:#}"
+        );
+        // The state file must be left intact
+        assert_eq!(std::fs::read_to_string(&state_file)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn recovers_corrupted_state_from_backup() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        state_with_jobs("saturn", 3).store(&state_file)?;
+        // Loading a valid state creates the backup
+        State::load(&state_file)?;
+
+        std::fs::write(&state_file, "{\"modules")?;
+        let loaded = State::load(&state_file)?;
+        assert_eq!(loaded.total_jobs_completed(), 3);
+        assert_eq!(
+            std::fs::read_to_string(state_dir.path().join("state.json.corrupted"))?,
+            "{\"modules"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn starts_with_empty_state_when_backup_is_missing() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        std::fs::write(&state_file, "not json")?;
+
+        let loaded = State::load(&state_file)?;
+        assert_eq!(loaded.total_jobs_completed(), 0);
+        assert!(!state_file.exists());
         Ok(())
     }
 }
@@ -1,60 +1,128 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::io::{stderr, stdout, Write};
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::SecondsFormat;
 use serde_json::{json, Map};
-use zinnia_runtime::{JobCompletionTracker, LogLevel, Reporter};
+use zinnia_runtime::anyhow::Result;
+use zinnia_runtime::deno_core::error::JsError;
+use zinnia_runtime::deno_core::ModuleSpecifier;
+use zinnia_runtime::{
+    ActivityLevel, ActivityLimits, ActivityThrottle, JobCompletionTracker, LogLevel, MetricKind,
+    MetricUpdate, Reporter,
+};
 
+use crate::job_history::JobHistory;
+use crate::metrics::Metrics;
 use crate::state::State;
 
+/// Version of the ND-JSON protocol used to report events to Station. Every event includes
+/// this value in the `protocolVersion` field. Increment it when making breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 /// StationReporter reports activities to stdout as ND-JSON stream and all Console logs to stderr
 pub struct StationReporter {
     tracker: RefCell<JobCompletionTracker>,
+    activities: RefCell<ActivityThrottle>,
     module_name: String,
     log_target: String,
     state_file: PathBuf,
+    state: RefCell<State>,
+    metrics: Arc<Metrics>,
+    history: Option<Arc<JobHistory>>,
+    stats_delay: Duration,
+    // When did we print the last `stats` event and are there any changes since then?
+    last_stats_report: Cell<Option<Instant>>,
+    stats_changed: Cell<bool>,
+    // Did any counter change since we persisted the state? Gauges and histograms are not persisted.
+    counters_changed: Cell<bool>,
 }
 
 impl StationReporter {
     /// Create a new instance.
     ///
-    /// `job_report_delay` specifies how often the information about new jobs is printed.
-    pub fn new(state_file: PathBuf, job_report_delay: Duration, module_name: String) -> Self {
+    /// `job_report_delay` specifies how often the information about new jobs and custom metrics
+    /// is printed.
+    pub fn new(
+        state_file: PathBuf,
+        job_report_delay: Duration,
+        module_name: String,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
         let log_target = format!("module:{module_name}");
-        let initial_job_count = State::load(&state_file)
-            // NOTE(bajtos) We are intentionally calling unwrap() to crash the process in case
-            // it's not possible to read the state file or parse the content.
-            .unwrap()
-            .total_jobs_completed;
+        let mut state = State::load(&state_file)?;
+        let module_state = state.start_module(&module_name, chrono::Utc::now());
+        let initial_job_count = module_state.jobs_completed;
+        metrics.restore_counters(&module_name, &module_state.counters);
+        // Persist the information about this run
+        state.store(&state_file)?;
 
         let reporter = Self {
             tracker: RefCell::new(JobCompletionTracker::new(
                 initial_job_count,
                 job_report_delay,
             )),
+            activities: RefCell::new(ActivityThrottle::new(ActivityLimits::default())),
             module_name,
             log_target,
             state_file,
+            state: RefCell::new(state),
+            metrics,
+            history: None,
+            stats_delay: job_report_delay,
+            last_stats_report: Cell::new(None),
+            stats_changed: Cell::new(false),
+            counters_changed: Cell::new(false),
         };
 
         // Report the initial job count to prevent Station Desktop from showing incorrect job count
         // until a Zinnia module completes the first job
         reporter.print_jobs_completed(initial_job_count);
 
-        reporter
+        Ok(reporter)
+    }
+
+    /// Configure how many activities can be reported to Station and how often.
+    pub fn with_activity_limits(mut self, limits: ActivityLimits) -> Self {
+        self.activities = RefCell::new(ActivityThrottle::new(limits));
+        self
+    }
+
+    /// Record completed jobs in the given history and persist it together with the state.
+    pub fn with_job_history(mut self, history: Arc<JobHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    fn print_activity(&self, level: ActivityLevel, msg: &str) {
+        let event_type = match level {
+            ActivityLevel::Info => "activity:info",
+            ActivityLevel::Error => "activity:error",
+        };
+        let event = json!({
+            "type": event_type,
+            "module": self.module_name,
+            "message": msg,
+        });
+        print_event(&event);
     }
 
-    fn print_jobs_completed(&self, total: u64) {
-        // TODO: print data from all modules
-        // https://github.com/filecoin-station/zinnia/issues/144
-        // modules: {"saturn": 100, "retrieval-checker": 23}}
-        let mut modules = Map::new();
-        modules.insert(self.module_name.clone(), json!(total));
+    /// Print the number of jobs completed by all modules, `module_total` is the up-to-date
+    /// number of jobs completed by this module.
+    fn print_jobs_completed(&self, module_total: u64) {
+        let mut state = self.state.borrow_mut();
+        state.module_mut(&self.module_name).jobs_completed = module_total;
 
+        let modules: Map<_, _> = state
+            .modules
+            .iter()
+            .map(|(name, m)| (name.clone(), json!(m.jobs_completed)))
+            .collect();
         let event = json!({
             "type": "jobs-completed",
-            "total": total,
+            "total": state.total_jobs_completed(),
             "modules": modules,
         });
 
@@ -67,20 +135,53 @@ impl StationReporter {
         // out of disk space, Station will remember the higher job count we reported before
         // crashing due to the `unwrap()` call below, but we will report a lower value after Station
         // restarts us and we load the old counter from the state file.
-        let state = State {
-            total_jobs_completed: total,
-        };
+        self.store_state(total);
+        if let Some(history) = &self.history {
+            history.store(chrono::Utc::now()).unwrap_or_else(|err| {
+                // The history is not critical, we don't want to crash the process
+                log::warn!("{err:#}");
+            });
+        }
+        self.print_jobs_completed(total)
+    }
+
+    fn store_state(&self, jobs_completed: u64) {
+        let mut state = self.state.borrow_mut();
+        let module_state = state.module_mut(&self.module_name);
+        module_state.jobs_completed = jobs_completed;
+        module_state.counters = self.metrics.module_counters(&self.module_name);
         state
             .store(&self.state_file)
             // NOTE(bajtos) We are intentionally calling unwrap() to crash the process in case
             // we cannot store the state into the file.
             .unwrap();
+        self.counters_changed.set(false);
+    }
 
-        self.print_jobs_completed(total)
+    fn update_stats(&self) {
+        // IMPORTANT: Persist the counters before reporting them, see `update_jobs_completed`.
+        // Updates of gauges and histograms don't change the persisted state, we don't rewrite
+        // the state file for them.
+        if self.counters_changed.get() {
+            self.store_state(self.tracker.borrow().counter());
+        }
+
+        let mut event = self.metrics.module_stats(&self.module_name);
+        event["type"] = json!("stats");
+        event["module"] = json!(self.module_name);
+        print_event(&event);
+
+        self.last_stats_report.set(Some(Instant::now()));
+        self.stats_changed.set(false);
     }
 }
 
-fn print_event(data: &serde_json::Value) {
+/// Print an event to stdout as a single line of JSON, adding `timestamp` and `protocolVersion`.
+pub fn print_event(data: &serde_json::Value) {
+    let mut data = data.clone();
+    data["timestamp"] = json!(chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+    data["protocolVersion"] = json!(PROTOCOL_VERSION);
+
     writeln!(stdout(), "{data}")
         .and_then(|_| stdout().flush())
         .unwrap_or_else(|err| {
@@ -97,6 +198,67 @@ pub fn log_started_activity() {
     print_event(&event);
 }
 
+/// Why did the module stop running.
+pub enum ModuleExit<'a> {
+    /// The module finished, `result` is the outcome of running it.
+    Finished(&'a Result<()>),
+    /// Station asked us to restart the module via the control channel, or the module
+    /// configuration changed.
+    Restart,
+    /// The module was removed from the config file.
+    Stopped,
+    /// Station asked us to shut down via the control channel.
+    Shutdown,
+}
+
+/// Report that the module stopped.
+pub fn log_module_exited(module_name: &str, exit: ModuleExit) {
+    let (reason, error) = match exit {
+        ModuleExit::Finished(Ok(())) => ("completed", serde_json::Value::Null),
+        ModuleExit::Finished(Err(err)) => ("error", json!({ "message": format!("{err:#}") })),
+        ModuleExit::Restart => ("restart", serde_json::Value::Null),
+        ModuleExit::Stopped => ("stopped", serde_json::Value::Null),
+        ModuleExit::Shutdown => ("shutdown", serde_json::Value::Null),
+    };
+    let event = json!({
+        "type": "module:exited",
+        "module": module_name,
+        "reason": reason,
+        "error": error,
+    });
+    print_event(&event);
+}
+
+/// Report an uncaught JavaScript exception that terminated the module.
+pub fn log_uncaught_exception(module_name: &str, error: &JsError) {
+    let event = json!({
+        "type": "module:uncaught-exception",
+        "module": module_name,
+        "name": error.name,
+        "message": error.exception_message,
+        "stack": error.stack,
+    });
+    print_event(&event);
+}
+
+/// Report the history of jobs completed by the module.
+pub fn log_job_history(module_name: &str, summary: &serde_json::Value) {
+    let mut event = summary.clone();
+    event["type"] = json!("jobs-history");
+    event["module"] = json!(module_name);
+    print_event(&event);
+}
+
+/// Report that the module is still running and the event loop is responsive.
+pub fn log_heartbeat(module_name: &str, uptime: Duration) {
+    let event = json!({
+        "type": "heartbeat",
+        "module": module_name,
+        "uptime": uptime.as_secs(),
+    });
+    print_event(&event);
+}
+
 #[allow(unused)]
 pub fn log_info_activity(msg: &str) {
     let event = json!({
@@ -119,9 +281,17 @@ pub fn log_error_activity(msg: &str) {
 
 impl Drop for StationReporter {
     fn drop(&mut self) {
+        self.activities
+            .borrow_mut()
+            .flush(|level, msg| self.print_activity(level, msg));
+
         self.tracker
             .borrow_mut()
-            .flush(|n| self.print_jobs_completed(n));
+            .flush(|n| self.update_jobs_completed(n));
+
+        if self.stats_changed.get() {
+            self.update_stats();
+        }
     }
 }
 
@@ -136,34 +306,78 @@ impl Reporter for StationReporter {
     }
 
     fn info_activity(&self, msg: &str) {
+        self.metrics.info_activity(&self.module_name);
+        self.activities
+            .borrow_mut()
+            .activity(ActivityLevel::Info, msg, |level, msg| {
+                self.print_activity(level, msg)
+            });
+    }
+
+    fn error_activity(&self, msg: &str) {
+        self.metrics.error_activity(&self.module_name);
+        self.activities
+            .borrow_mut()
+            .activity(ActivityLevel::Error, msg, |level, msg| {
+                self.print_activity(level, msg)
+            });
+    }
+
+    fn job_completed(&self) {
+        self.metrics.job_completed(&self.module_name);
+        if let Some(history) = &self.history {
+            history.job_completed(&self.module_name, chrono::Utc::now());
+        }
+        self.tracker
+            .borrow_mut()
+            .job_completed(|n| self.update_jobs_completed(n));
+    }
+
+    fn metric(&self, update: &MetricUpdate) {
+        self.metrics.custom_metric(&self.module_name, update);
+        self.stats_changed.set(true);
+        if update.kind == MetricKind::Counter {
+            self.counters_changed.set(true);
+        }
+
+        if let Some(last) = self.last_stats_report.get() {
+            if last.elapsed() < self.stats_delay {
+                return;
+            }
+        }
+        self.update_stats();
+    }
+
+    fn module_loaded(&self, specifier: &ModuleSpecifier) {
         let event = json!({
-            "type": "activity:info",
+            "type": "module:loaded",
             "module": self.module_name,
-            "message": msg,
+            "specifier": specifier.as_str(),
         });
         print_event(&event);
     }
 
-    fn error_activity(&self, msg: &str) {
+    fn module_started(&self) {
         let event = json!({
-            "type": "activity:error",
+            "type": "module:started",
             "module": self.module_name,
-            "message": msg,
         });
         print_event(&event);
     }
 
-    fn job_completed(&self) {
-        self.tracker
+    fn flush_activities(&self) {
+        self.activities
             .borrow_mut()
-            .job_completed(|n| self.update_jobs_completed(n));
+            .flush_expired(|level, msg| self.print_activity(level, msg));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::CounterState;
     use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
     use tempfile::tempdir;
     use zinnia_runtime::anyhow::Result;
 
@@ -173,7 +387,8 @@ mod tests {
     fn persists_job_counter() -> Result<()> {
         let state_dir = tempdir()?;
         let state_file = state_dir.path().join("state.json");
-        let reporter = StationReporter::new(state_file.clone(), NO_DELAY, "test".into());
+        let reporter =
+            StationReporter::new(state_file.clone(), NO_DELAY, "test".into(), Arc::default())?;
         assert_eq!(reporter.tracker.borrow().counter(), 0, "initial count");
 
         reporter.job_completed();
@@ -183,7 +398,7 @@ mod tests {
             "count after a job was completed"
         );
 
-        let reporter = StationReporter::new(state_file, NO_DELAY, "test".into());
+        let reporter = StationReporter::new(state_file, NO_DELAY, "test".into(), Arc::default())?;
         assert_eq!(
             reporter.tracker.borrow().counter(),
             1,
@@ -192,4 +407,108 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn keeps_job_counters_of_other_modules() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        let mut state = State::default();
+        state.module_mut("other").jobs_completed = 5;
+        state.store(&state_file)?;
+
+        let reporter =
+            StationReporter::new(state_file.clone(), NO_DELAY, "test".into(), Arc::default())?;
+        reporter.job_completed();
+        drop(reporter);
+
+        let state = State::load(&state_file)?;
+        assert_eq!(state.modules["other"].jobs_completed, 5);
+        assert_eq!(state.modules["test"].jobs_completed, 1);
+        assert_eq!(state.total_jobs_completed(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn records_module_starts() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        for _ in 0..3 {
+            StationReporter::new(state_file.clone(), NO_DELAY, "test".into(), Arc::default())?;
+        }
+
+        let state = State::load(&state_file)?;
+        let module = &state.modules["test"];
+        assert_eq!(module.starts, 3);
+        assert!(module.first_run_at.is_some());
+        assert!(module.first_run_at <= module.last_run_at);
+        Ok(())
+    }
+
+    #[test]
+    fn persists_custom_counters() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        let update = MetricUpdate {
+            kind: MetricKind::Counter,
+            name: "requests".into(),
+            labels: BTreeMap::new(),
+            value: 2.0,
+        };
+
+        let reporter =
+            StationReporter::new(state_file.clone(), NO_DELAY, "test".into(), Arc::default())?;
+        reporter.metric(&update);
+        drop(reporter);
+
+        let metrics = Arc::new(Metrics::default());
+        let reporter =
+            StationReporter::new(state_file, NO_DELAY, "test".into(), Arc::clone(&metrics))?;
+        reporter.metric(&update);
+
+        assert_eq!(
+            metrics.module_counters("test"),
+            vec![CounterState {
+                name: "requests".into(),
+                labels: BTreeMap::new(),
+                value: 4.0,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn persists_state_for_counters_only() -> Result<()> {
+        let state_dir = tempdir()?;
+        let state_file = state_dir.path().join("state.json");
+        let reporter =
+            StationReporter::new(state_file.clone(), NO_DELAY, "test".into(), Arc::default())?;
+        std::fs::remove_file(&state_file)?;
+
+        for kind in [MetricKind::Gauge, MetricKind::Histogram] {
+            reporter.metric(&MetricUpdate {
+                kind,
+                name: "latency".into(),
+                labels: BTreeMap::new(),
+                value: 1.0,
+            });
+        }
+        assert!(
+            !state_file.exists(),
+            "state stored after gauge and histogram updates"
+        );
+
+        reporter.metric(&MetricUpdate {
+            kind: MetricKind::Counter,
+            name: "requests".into(),
+            labels: BTreeMap::new(),
+            value: 1.0,
+        });
+        assert!(
+            state_file.exists(),
+            "state not stored after a counter update"
+        );
+
+        Ok(())
+    }
 }
@@ -1,5 +1,5 @@
 use std::fs::read_dir;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::time::Duration;
@@ -125,3 +125,243 @@ pub fn it_removes_lassie_temp_on_start() {
         "all files from the previous run should have been deleted"
     );
 }
+
+#[test]
+pub fn it_reports_module_lifecycle_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let temp_root = tempdir().expect("cannot create temporary directory");
+
+    let mut mod_js = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    mod_js.push("tests");
+    mod_js.push("fixtures");
+    mod_js.push("throw-error.js");
+
+    let bin = assert_cmd::cargo::cargo_bin("zinniad");
+    let output = Command::new(bin)
+        .env("NO_COLOR", "1")
+        .env("FIL_WALLET_ADDRESS", "f1test")
+        .env("STATION_ID", "a".repeat(88))
+        .env(
+            "CACHE_ROOT",
+            temp_root.path().join("cache").display().to_string(),
+        )
+        .env(
+            "STATE_ROOT",
+            temp_root.path().join("state").display().to_string(),
+        )
+        .args([&mod_js.as_os_str()])
+        .stderr(Stdio::null())
+        .output()
+        .expect("cannot run zinniad");
+
+    assert!(!output.status.success(), "zinniad should have failed");
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .inspect(|ln| println!("[zinniad] {}", ln))
+        .map(|ln| serde_json::from_str(ln).expect("cannot parse event"))
+        .collect();
+
+    for event in &events {
+        assert_eq!(event["protocolVersion"], 1, "{event}");
+        assert!(event["timestamp"].is_string(), "{event}");
+    }
+
+    let types: Vec<&str> = events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .filter(|t| !["jobs-completed", "jobs-history"].contains(t))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "activity:started",
+            "module:loaded",
+            "module:started",
+            "activity:info",
+            "module:uncaught-exception",
+            "module:exited",
+        ]
+    );
+
+    let find_event = |event_type: &str| {
+        events
+            .iter()
+            .find(|event| event["type"] == event_type)
+            .unwrap()
+    };
+    let exception = find_event("module:uncaught-exception");
+    assert!(
+        exception["message"].as_str().unwrap().contains("boom"),
+        "{exception}"
+    );
+    let exited = find_event("module:exited");
+    assert_eq!(exited["reason"], "error");
+    assert!(exited["error"]["message"].is_string(), "{exited}");
+}
+
+#[test]
+pub fn it_handles_control_requests_from_stdin() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let temp_root = tempdir().expect("cannot create temporary directory");
+
+    let mut mod_js = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    mod_js.push("tests");
+    mod_js.push("fixtures");
+    mod_js.push("run-forever.js");
+
+    let bin = assert_cmd::cargo::cargo_bin("zinniad");
+    let mut child = Command::new(bin)
+        .env("NO_COLOR", "1")
+        .env("FIL_WALLET_ADDRESS", "f1test")
+        .env("STATION_ID", "a".repeat(88))
+        .env(
+            "CACHE_ROOT",
+            temp_root.path().join("cache").display().to_string(),
+        )
+        .env(
+            "STATE_ROOT",
+            temp_root.path().join("state").display().to_string(),
+        )
+        .args(["--control", "stdin"])
+        .args([&mod_js.as_os_str()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("cannot spawn zinniad");
+
+    let mut stdin = child.stdin.take().expect("cannot take child's stdin");
+    let mut events = BufReader::new(child.stdout.take().expect("cannot take child's stdout"))
+        .lines()
+        .map(|it| it.expect("cannot read from child's stdout"))
+        .inspect(|ln| println!("[zinniad] {}", ln))
+        .map(|ln| serde_json::from_str::<serde_json::Value>(&ln).expect("cannot parse event"));
+
+    let mut wait_for = |event_type: &str| {
+        events
+            .find(|event| event["type"] == event_type)
+            .unwrap_or_else(|| panic!("zinniad did not report {event_type}"))
+    };
+
+    wait_for("activity:info");
+
+    let mut send = |request: &str| {
+        writeln!(stdin, "{request}").expect("cannot write to child's stdin");
+        stdin.flush().expect("cannot flush child's stdin");
+    };
+
+    send(r#"{"id":1,"method":"pause"}"#);
+    let response = wait_for("rpc:response");
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["state"], "paused");
+
+    send(r#"{"id":2,"method":"unknown"}"#);
+    let response = wait_for("rpc:response");
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["error"]["code"], -32601);
+
+    send(r#"{"id":3,"method":"resume"}"#);
+    let response = wait_for("rpc:response");
+    assert_eq!(response["result"]["state"], "running");
+
+    send(r#"{"id":4,"method":"setWalletAddress","params":{"address":"f1new"}}"#);
+    let response = wait_for("rpc:response");
+    assert_eq!(response["result"]["restarting"], true);
+    assert_eq!(wait_for("module:exited")["reason"], "restart");
+    wait_for("module:started");
+
+    send(r#"{"id":5,"method":"status"}"#);
+    let response = wait_for("rpc:response");
+    assert_eq!(response["result"]["walletAddress"], "f1new");
+    assert_eq!(response["result"]["restarts"], 1);
+
+    send(r#"{"id":6,"method":"shutdown"}"#);
+    assert_eq!(wait_for("rpc:response")["id"], 6);
+    assert_eq!(wait_for("module:exited")["reason"], "shutdown");
+
+    let status = child.wait().expect("cannot wait for zinniad to exit");
+    assert!(status.success(), "zinniad exited with {status}");
+}
+
+#[cfg(unix)]
+#[test]
+pub fn it_shuts_down_gracefully_on_sigterm() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let temp_root = tempdir().expect("cannot create temporary directory");
+    let state_root = temp_root.path().join("state");
+
+    let mut mod_js = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    mod_js.push("tests");
+    mod_js.push("fixtures");
+    mod_js.push("run-forever.js");
+
+    let bin = assert_cmd::cargo::cargo_bin("zinniad");
+    let mut child = Command::new(bin)
+        .env("NO_COLOR", "1")
+        .env("FIL_WALLET_ADDRESS", "f1test")
+        .env("STATION_ID", "a".repeat(88))
+        .env(
+            "CACHE_ROOT",
+            temp_root.path().join("cache").display().to_string(),
+        )
+        .env("STATE_ROOT", state_root.display().to_string())
+        .args([&mod_js.as_os_str()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("cannot spawn zinniad");
+
+    let mut events = BufReader::new(child.stdout.take().expect("cannot take child's stdout"))
+        .lines()
+        .map(|it| it.expect("cannot read from child's stdout"))
+        .inspect(|ln| println!("[zinniad] {}", ln))
+        .map(|ln| serde_json::from_str::<serde_json::Value>(&ln).expect("cannot parse event"));
+
+    events
+        .find(|event| event["type"] == "activity:info")
+        .expect("zinniad did not report the module activity");
+    // Let the module complete a few jobs
+    std::thread::sleep(Duration::from_millis(500));
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .expect("cannot send SIGTERM to zinniad");
+    assert!(status.success(), "kill exited with {status}");
+
+    let events: Vec<serde_json::Value> = events.collect();
+    let status = child.wait().expect("cannot wait for zinniad to exit");
+    assert!(status.success(), "zinniad exited with {status}");
+
+    let exited = events
+        .iter()
+        .find(|event| event["type"] == "module:exited")
+        .expect("zinniad did not report module:exited");
+    assert_eq!(exited["reason"], "shutdown");
+
+    // The final number of completed jobs is reported and persisted
+    let reported = events
+        .iter()
+        .rev()
+        .find(|event| event["type"] == "jobs-completed")
+        .expect("zinniad did not report jobs-completed")["total"]
+        .as_u64()
+        .unwrap();
+    assert!(reported > 0, "the module did not complete any jobs");
+
+    let state: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(state_root.join("state.json")).expect("cannot read state file"),
+    )
+    .expect("cannot parse state file");
+    let persisted: u64 = state["modules"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|module| module["jobs_completed"].as_u64().unwrap())
+        .sum();
+    assert_eq!(persisted, reported);
+}
@@ -0,0 +1,2 @@
+Zinnia.activity.info("Started");
+setInterval(() => Zinnia.jobCompleted(), 100);
@@ -0,0 +1,2 @@
+Zinnia.activity.info("About to fail");
+throw new Error("boom");
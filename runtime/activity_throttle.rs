@@ -0,0 +1,353 @@
+use std::time::{Duration, Instant};
+
+/// The level of an activity reported via `Zinnia.activity` APIs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityLevel {
+    Info,
+    Error,
+}
+
+/// Limits protecting the activity log from modules reporting too many activities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActivityLimits {
+    /// How many activities can be reported within one `interval`. Activities above this limit
+    /// are dropped, the number of dropped activities is reported when the next interval starts.
+    pub max_activities: u32,
+
+    /// The length of the rate-limiting window. This is also how often we report the number of
+    /// repetitions when the module keeps reporting the same message.
+    pub interval: Duration,
+}
+
+impl ActivityLimits {
+    /// Report all activities. Identical consecutive activities are still collapsed into one
+    /// entry per minute.
+    pub fn unlimited() -> Self {
+        Self {
+            max_activities: u32::MAX,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for ActivityLimits {
+    fn default() -> Self {
+        Self {
+            max_activities: 30,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Rate-limits activities and collapses identical consecutive messages into a single
+/// "(repeated N times)" entry.
+#[derive(Debug)]
+pub struct ActivityThrottle {
+    limits: ActivityLimits,
+
+    // The current rate-limiting window: when it started and how many activities we reported
+    window: Option<(Instant, u32)>,
+    // Activities dropped in the current window and whether any of them was an error
+    dropped: u64,
+    dropped_errors: bool,
+
+    // The last activity received, whether it was reported and how many times it was repeated
+    // since `repeats_since`
+    last: Option<(ActivityLevel, String)>,
+    last_reported: bool,
+    repeats: u64,
+    repeats_since: Option<Instant>,
+}
+
+impl ActivityThrottle {
+    pub fn new(limits: ActivityLimits) -> Self {
+        Self {
+            limits,
+            window: None,
+            dropped: 0,
+            dropped_errors: false,
+            last: None,
+            last_reported: false,
+            repeats: 0,
+            repeats_since: None,
+        }
+    }
+
+    /// Process a new activity. `report` is called for every entry that should be shown in the
+    /// activity log, it can be called more than once (e.g. to report the repetitions of the
+    /// previous message first) or not at all.
+    pub fn activity<F: FnMut(ActivityLevel, &str)>(
+        &mut self,
+        level: ActivityLevel,
+        msg: &str,
+        mut report: F,
+    ) {
+        let now = Instant::now();
+        let is_repeat = matches!(&self.last, Some((l, m)) if *l == level && m == msg);
+
+        if is_repeat && self.last_reported {
+            self.repeats += 1;
+            if self.interval_elapsed(self.repeats_since, now) {
+                self.flush_repeats(&mut report);
+                self.repeats_since = Some(now);
+            }
+            return;
+        }
+
+        if !is_repeat {
+            self.flush_repeats(&mut report);
+            self.last = Some((level, msg.to_string()));
+            self.repeats_since = Some(now);
+        }
+
+        self.last_reported = self.within_limit(level, now, &mut report);
+        if self.last_reported {
+            report(level, msg);
+        }
+    }
+
+    /// Report repetitions and dropped activities that were not reported yet.
+    pub fn flush<F: FnMut(ActivityLevel, &str)>(&mut self, mut report: F) {
+        self.flush_repeats(&mut report);
+        self.flush_dropped(&mut report);
+    }
+
+    /// Report repetitions and dropped activities once the interval they were collected in has
+    /// elapsed. Call this periodically, otherwise these entries are reported only when the
+    /// module reports another activity.
+    pub fn flush_expired<F: FnMut(ActivityLevel, &str)>(&mut self, mut report: F) {
+        let now = Instant::now();
+        if self.repeats > 0 && self.interval_elapsed(self.repeats_since, now) {
+            self.flush_repeats(&mut report);
+            self.repeats_since = Some(now);
+        }
+        if self.dropped > 0 && self.interval_elapsed(self.window.map(|(start, _)| start), now) {
+            self.flush_dropped(&mut report);
+            self.window = None;
+        }
+    }
+
+    fn within_limit<F: FnMut(ActivityLevel, &str)>(
+        &mut self,
+        level: ActivityLevel,
+        now: Instant,
+        report: &mut F,
+    ) -> bool {
+        if self.interval_elapsed(self.window.map(|(start, _)| start), now) {
+            self.flush_dropped(report);
+            self.window = Some((now, 0));
+        }
+
+        match &mut self.window {
+            Some((_, count)) if *count < self.limits.max_activities => {
+                *count += 1;
+                true
+            }
+            _ => {
+                self.dropped += 1;
+                self.dropped_errors |= level == ActivityLevel::Error;
+                false
+            }
+        }
+    }
+
+    fn interval_elapsed(&self, since: Option<Instant>, now: Instant) -> bool {
+        match since {
+            None => true,
+            Some(since) => now.duration_since(since) >= self.limits.interval,
+        }
+    }
+
+    fn flush_repeats<F: FnMut(ActivityLevel, &str)>(&mut self, report: &mut F) {
+        if self.repeats == 0 {
+            return;
+        }
+        if let Some((level, msg)) = &self.last {
+            let times = plural(self.repeats, "time", "times");
+            let msg = format!("{msg} (repeated {} {times})", self.repeats);
+            report(*level, &msg);
+        }
+        self.repeats = 0;
+    }
+
+    fn flush_dropped<F: FnMut(ActivityLevel, &str)>(&mut self, report: &mut F) {
+        if self.dropped == 0 {
+            return;
+        }
+        let level = match self.dropped_errors {
+            true => ActivityLevel::Error,
+            false => ActivityLevel::Info,
+        };
+        let activities = plural(self.dropped, "activity", "activities");
+        let msg = format!(
+            "Dropped {} {activities} reported too frequently by the module",
+            self.dropped
+        );
+        report(level, &msg);
+        self.dropped = 0;
+        self.dropped_errors = false;
+    }
+}
+
+fn plural<'a>(count: u64, singular: &'a str, plural: &'a str) -> &'a str {
+    match count {
+        1 => singular,
+        _ => plural,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const LONG_INTERVAL: Duration = Duration::from_secs(3600);
+    const SHORT_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn record(
+        throttle: &mut ActivityThrottle,
+        reported: &mut Vec<String>,
+        level: ActivityLevel,
+        msg: &str,
+    ) {
+        throttle.activity(level, msg, |l, m| reported.push(format!("{l:?}: {m}")));
+    }
+
+    #[test]
+    fn collapses_identical_consecutive_messages() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 100,
+            interval: LONG_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        for _ in 0..3 {
+            record(&mut throttle, &mut reported, ActivityLevel::Error, "boom");
+        }
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "ok");
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "ok");
+        throttle.flush(|l, m| reported.push(format!("{l:?}: {m}")));
+
+        assert_eq!(
+            reported,
+            vec![
+                "Error: boom",
+                "Error: boom (repeated 2 times)",
+                "Info: ok",
+                "Info: ok (repeated 1 time)",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_repetitions_periodically() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 100,
+            interval: SHORT_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "ping");
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "ping");
+        std::thread::sleep(SHORT_INTERVAL);
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "ping");
+
+        assert_eq!(
+            reported,
+            vec!["Info: ping", "Info: ping (repeated 2 times)"]
+        );
+    }
+
+    #[test]
+    fn drops_activities_above_the_limit() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 2,
+            interval: LONG_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        for msg in ["one", "two", "three", "four"] {
+            record(&mut throttle, &mut reported, ActivityLevel::Info, msg);
+        }
+        record(&mut throttle, &mut reported, ActivityLevel::Error, "five");
+        throttle.flush(|l, m| reported.push(format!("{l:?}: {m}")));
+
+        assert_eq!(
+            reported,
+            vec![
+                "Info: one",
+                "Info: two",
+                "Error: Dropped 3 activities reported too frequently by the module",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_dropped_activities_in_the_next_window() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 1,
+            interval: SHORT_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "one");
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "two");
+        std::thread::sleep(SHORT_INTERVAL);
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "three");
+
+        assert_eq!(
+            reported,
+            vec![
+                "Info: one",
+                "Info: Dropped 1 activity reported too frequently by the module",
+                "Info: three",
+            ]
+        );
+    }
+
+    #[test]
+    fn flushes_repetitions_when_the_interval_elapses() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 100,
+            interval: SHORT_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        for _ in 0..3 {
+            record(&mut throttle, &mut reported, ActivityLevel::Info, "ping");
+        }
+        throttle.flush_expired(|l, m| reported.push(format!("{l:?}: {m}")));
+        assert_eq!(reported, vec!["Info: ping"]);
+
+        std::thread::sleep(SHORT_INTERVAL);
+        throttle.flush_expired(|l, m| reported.push(format!("{l:?}: {m}")));
+        assert_eq!(
+            reported,
+            vec!["Info: ping", "Info: ping (repeated 2 times)"]
+        );
+    }
+
+    #[test]
+    fn flushes_dropped_activities_when_the_interval_elapses() {
+        let mut throttle = ActivityThrottle::new(ActivityLimits {
+            max_activities: 1,
+            interval: SHORT_INTERVAL,
+        });
+        let mut reported = vec![];
+
+        record(&mut throttle, &mut reported, ActivityLevel::Info, "one");
+        record(&mut throttle, &mut reported, ActivityLevel::Error, "two");
+        throttle.flush_expired(|l, m| reported.push(format!("{l:?}: {m}")));
+        assert_eq!(reported, vec!["Info: one"]);
+
+        std::thread::sleep(SHORT_INTERVAL);
+        throttle.flush_expired(|l, m| reported.push(format!("{l:?}: {m}")));
+        assert_eq!(
+            reported,
+            vec![
+                "Info: one",
+                "Error: Dropped 1 activity reported too frequently by the module",
+            ]
+        );
+    }
+}
@@ -6,7 +6,7 @@ use termcolor::{Ansi, Color, ColorSpec, WriteColor};
 
 use crate::anyhow::Result;
 use crate::colors::use_color;
-use crate::{LogLevel, Reporter};
+use crate::{ActivityLevel, ActivityLimits, ActivityThrottle, LogLevel, MetricUpdate, Reporter};
 
 #[derive(Debug)]
 pub struct JobCompletionTracker {
@@ -61,15 +61,31 @@ impl JobCompletionTracker {
 /// ConsoleReporter logs activities to stdout and debug logs to stderr
 pub struct ConsoleReporter {
     tracker: RefCell<JobCompletionTracker>,
+    activities: RefCell<ActivityThrottle>,
 }
 
 impl ConsoleReporter {
     /// Create a new instance.
     ///
     /// `job_report_delay` specifies how often the information about new jobs is printed.
+    /// All activities are printed, see `with_activity_limits()`.
     pub fn new(job_report_delay: Duration) -> Self {
         Self {
             tracker: RefCell::new(JobCompletionTracker::new(0, job_report_delay)),
+            activities: RefCell::new(ActivityThrottle::new(ActivityLimits::unlimited())),
+        }
+    }
+
+    /// Configure how many activities can be printed and how often.
+    pub fn with_activity_limits(mut self, limits: ActivityLimits) -> Self {
+        self.activities = RefCell::new(ActivityThrottle::new(limits));
+        self
+    }
+
+    fn print_activity(&self, level: ActivityLevel, msg: &str) {
+        match level {
+            ActivityLevel::Info => self.report("INFO", msg, Color::Green),
+            ActivityLevel::Error => self.report("ERROR", msg, Color::Red),
         }
     }
 
@@ -112,6 +128,9 @@ fn print_raw_report<W: Write>(w: &mut W, scope: &str, msg: &str) -> std::io::Res
 
 impl Drop for ConsoleReporter {
     fn drop(&mut self) {
+        self.activities
+            .borrow_mut()
+            .flush(|level, msg| self.print_activity(level, msg));
         self.tracker
             .borrow_mut()
             .flush(|n| self.print_jobs_completed(n));
@@ -133,11 +152,19 @@ impl Reporter for ConsoleReporter {
     }
 
     fn info_activity(&self, msg: &str) {
-        self.report("INFO", msg, Color::Green);
+        self.activities
+            .borrow_mut()
+            .activity(ActivityLevel::Info, msg, |level, msg| {
+                self.print_activity(level, msg)
+            });
     }
 
     fn error_activity(&self, msg: &str) {
-        self.report("ERROR", msg, Color::Red);
+        self.activities
+            .borrow_mut()
+            .activity(ActivityLevel::Error, msg, |level, msg| {
+                self.print_activity(level, msg)
+            });
     }
 
     fn job_completed(&self) {
@@ -145,6 +172,11 @@ impl Reporter for ConsoleReporter {
             .borrow_mut()
             .job_completed(|n| self.print_jobs_completed(n));
     }
+
+    fn metric(&self, update: &MetricUpdate) {
+        // Printing every update would flood the terminal, modules can update metrics very often
+        log::debug!("Metric updated: {update}");
+    }
 }
 
 fn now_str() -> impl std::fmt::Display {
@@ -1,21 +1,84 @@
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use deno_core::anyhow::Result;
 use deno_core::error::JsError;
+use deno_core::futures::channel::oneshot;
+use deno_core::serde_json::Value;
 use deno_core::url::Url;
-use deno_core::{op2, OpState};
-use deno_fetch::{FetchPermissions, FsError};
+use deno_core::{op2, AsyncResult, BufView, OpState, Resource, ResourceId, ToJsBuffer};
+use deno_crypto::rand::rngs::StdRng;
+use deno_crypto::rand::{Rng, SeedableRng};
+use deno_error::JsErrorBox;
+use deno_fetch::{
+    create_http_client, ConnectionTiming, CreateHttpClientOptions, EncodedResponseSize, FetchError,
+    FetchPermissions, FetchRequestResource, FetchResponse, FetchResponseResource, FsError,
+    HttpClientResource,
+};
 use deno_net::NetPermissions;
 use deno_permissions::{PermissionCheckError, PermissionDeniedError};
-use deno_web::TimersPermission;
+use deno_web::{StartTime, TimersPermission};
 use deno_websocket::WebSocketPermissions;
+use hyper_util::client::legacy::connect::HttpInfo;
+use serde::Serialize;
 
-use crate::Reporter;
+use crate::ipfs::{CarVerifier, DagScope, VerificationReport};
+use crate::network_fixture::FixtureState;
+use crate::retrieval_backend::RetrievalCredentials;
+use crate::{
+    MetricUpdate, ProxyConfig, Reporter, RetrievalEndpoint, RetrievalGate, RuntimeMetrics,
+};
 
-/// Hard-coded permissions
-pub struct ZinniaPermissions;
+/// Permissions of the module. Most of them are hard-coded, the embedder can restrict
+/// the hosts the module can connect to.
+pub struct ZinniaPermissions {
+    /// Hostnames the module can connect to via `fetch` and `WebSocket`. All hosts are allowed
+    /// when `None`. Requests to the IPFS retrieval client are always allowed.
+    pub allow_net: Option<Vec<String>>,
+    /// URL of the HTTP server handling IPFS retrievals, e.g. the local Lassie daemon.
+    pub retrieval_url: Option<Url>,
+}
+
+impl ZinniaPermissions {
+    fn check_url(&self, url: &Url) -> Result<(), PermissionCheckError> {
+        let allow_net = match &self.allow_net {
+            None => return Ok(()),
+            Some(list) => list,
+        };
+        let host = url.host_str().unwrap_or_default();
+        if let Some(retrieval_url) = &self.retrieval_url {
+            if url.origin() == retrieval_url.origin() {
+                return Ok(());
+            }
+        }
+        if is_host_allowed(allow_net, host) {
+            return Ok(());
+        }
+        Err(PermissionCheckError::PermissionDenied(
+            PermissionDeniedError::Fatal {
+                access: format!("net access to {host:?}"),
+            },
+        ))
+    }
+}
+
+/// Check the host against the list of allowed hosts. The entry `*.example.com` allows all
+/// subdomains of `example.com`, `*` allows all hosts.
+pub fn is_host_allowed(allow_net: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allow_net.iter().any(|entry| {
+        let entry = entry.to_ascii_lowercase();
+        match entry.strip_prefix("*.") {
+            _ if entry == "*" => true,
+            Some(domain) => host.ends_with(&format!(".{domain}")),
+            None => host == entry,
+        }
+    })
+}
 
 impl TimersPermission for ZinniaPermissions {
     fn allow_hrtime(&mut self) -> bool {
@@ -31,8 +94,8 @@ impl TimersPermission for ZinniaPermissions {
 }
 
 impl FetchPermissions for ZinniaPermissions {
-    fn check_net_url(&mut self, _url: &Url, _api_name: &str) -> Result<(), PermissionCheckError> {
-        Ok(())
+    fn check_net_url(&mut self, url: &Url, _api_name: &str) -> Result<(), PermissionCheckError> {
+        self.check_url(url)
     }
     fn check_read<'a>(
         &mut self,
@@ -110,10 +173,10 @@ impl NetPermissions for ZinniaPermissions {
 impl WebSocketPermissions for ZinniaPermissions {
     fn check_net_url(
         &mut self,
-        _url: &deno_core::url::Url,
+        url: &deno_core::url::Url,
         _api_name: &str,
     ) -> std::result::Result<(), PermissionCheckError> {
-        Ok(())
+        self.check_url(url)
     }
 }
 
@@ -124,7 +187,24 @@ deno_core::extension!(
         op_info_activity,
         op_error_activity,
         op_zinnia_log,
+        op_report_metric,
         op_format_test_error,
+        op_fetch_started,
+        op_fetch_body_ended,
+        op_fetch_network_timing,
+        op_fetch_direct_client,
+        op_ipfs_retrieval_finished,
+        op_ipfs_retrievals_paused,
+        op_ipfs_credentials_id,
+        op_ipfs_read_car,
+        op_car_verifier_create,
+        op_car_verifier_write,
+        op_car_verifier_finish,
+        op_network_fixture_entries,
+        op_network_fixture_record,
+        op_virtual_clock_set,
+        op_virtual_clock_clear,
+        op_seeded_math_random,
 
         op_bootstrap_stderr_no_color,
         op_bootstrap_stdout_no_color,
@@ -137,20 +217,60 @@ deno_core::extension!(
       "98_global_scope.js",
       "internals.js",
       "fetch.js",
+      "fake_timers.js",
+      "fetch_mock.js",
+      "ipfs.js",
+      "network_fixture.js",
+      "resource_timing.js",
       "test.js",
       "vendored/asserts.bundle.js",
       "99_main.js",
     ],
     options = {
         reporter: Rc<dyn Reporter>,
+        metrics: Arc<RuntimeMetrics>,
+        allow_net: Option<Vec<String>>,
+        proxy: Option<ProxyConfig>,
+        network_fixture: Option<FixtureState>,
+        rng_seed: Option<u64>,
+        retrieval_endpoint: RetrievalEndpoint,
+        retrieval_credentials: Option<RetrievalCredentials>,
+        retrieval_gate: RetrievalGate,
+    },
+    // `performance.now()` reports the time of the fake clock while the tests use fake timers,
+    // `fetch` response bodies and their network timing are tracked by the runtime
+    middleware = |op| match op.name {
+        "op_now" => op.with_implementation_from(&op_zinnia_now()),
+        "op_fetch_send" => op.with_implementation_from(&op_zinnia_fetch_send()),
+        _ => op,
     },
     state = |state, options| {
-        state.put(ZinniaPermissions {});
+        state.put(ZinniaPermissions {
+            allow_net: options.allow_net,
+            retrieval_url: options.retrieval_endpoint.http_url().cloned(),
+        });
+        state.put(FetchProxy {
+            config: options.proxy,
+            direct_client: None,
+        });
+        if let Some(fixture) = options.network_fixture {
+            state.put(fixture);
+        }
+        if let Some(seed) = options.rng_seed {
+            state.put(SeededRandom(StdRng::seed_from_u64(seed)));
+        }
+        state.put(options.retrieval_endpoint);
+        if let Some(credentials) = options.retrieval_credentials {
+            state.put(credentials);
+        }
         state.put(Rc::clone(&options.reporter));
+        state.put(Arc::clone(&options.metrics));
+        state.put(options.retrieval_gate);
     }
 );
 
 type StoredReporter = Rc<dyn Reporter>;
+type StoredMetrics = Arc<RuntimeMetrics>;
 
 #[op2(fast)]
 fn op_job_completed(state: &mut OpState) {
@@ -170,12 +290,581 @@ fn op_error_activity(state: &mut OpState, #[string] msg: &str) {
     reporter.error_activity(msg);
 }
 
+#[op2]
+fn op_report_metric(state: &mut OpState, #[serde] update: MetricUpdate) {
+    let reporter = state.borrow::<StoredReporter>();
+    reporter.metric(&update);
+}
+
 #[op2(fast)]
 fn op_zinnia_log(state: &mut OpState, #[string] msg: &str, #[smi] level: i32) {
     let reporter = state.borrow::<StoredReporter>();
     reporter.log(level.into(), msg);
 }
 
+#[op2(fast)]
+fn op_fetch_started(state: &mut OpState, is_ipfs: bool) {
+    let metrics = state.borrow::<StoredMetrics>();
+    if is_ipfs {
+        metrics.ipfs_requests.inc();
+    } else {
+        metrics.http_requests.inc();
+    }
+}
+
+// Replaces `op_fetch_send` from `deno_fetch`. Ops generated by `op2` cannot be called from other
+// crates, this is a copy of the original op, except that the response body is wrapped in
+// `TrackedResponseBody`. This way we can count the bytes received without wrapping the body stream
+// in JavaScript, which would disable the fast path of `Response.arrayBuffer()` and friends. The
+// body also keeps the network timing of the response, see `op_fetch_network_timing`.
+#[op2(async)]
+#[serde]
+async fn op_zinnia_fetch_send(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<FetchResponse, FetchError> {
+    let request = state
+        .borrow_mut()
+        .resource_table
+        .take::<FetchRequestResource>(rid)?;
+    let request = Rc::try_unwrap(request)
+        .ok()
+        .expect("multiple op_fetch_send ongoing");
+
+    let response = match request.future.await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            // Like `deno_fetch`, report the cause of hyper body errors, so that `fetch.js` can
+            // reconstruct the error chain
+            if let FetchError::ClientSend(send_err) = &err {
+                if let Some(cause) = std::error::Error::source(&send_err.source)
+                    .and_then(|err| err.downcast_ref::<hyper::Error>())
+                    .and_then(std::error::Error::source)
+                {
+                    return Ok(FetchResponse {
+                        error: Some((err.to_string(), cause.to_string())),
+                        ..Default::default()
+                    });
+                }
+            }
+            return Err(err);
+        }
+        Err(_) => return Err(FetchError::RequestCanceled),
+    };
+
+    let status = response.status();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(key, value)| (key.as_str().into(), value.as_bytes().into()))
+        .collect();
+    let content_length = hyper::body::Body::size_hint(response.body()).exact();
+    let remote_addr = response
+        .extensions()
+        .get::<HttpInfo>()
+        .map(|info| info.remote_addr());
+    let network = NetworkInfo {
+        version: response.version(),
+        connection: response.extensions().get::<ConnectionTiming>().copied(),
+        encoded_size: response.extensions().get::<EncodedResponseSize>().cloned(),
+    };
+
+    let mut state = state.borrow_mut();
+    let metrics = Arc::clone(state.borrow::<StoredMetrics>());
+    let (sender, receiver) = oneshot::channel();
+    let response_rid = state.resource_table.add(TrackedResponseBody {
+        body: Rc::new(FetchResponseResource::new(response, content_length)),
+        metrics,
+        bytes_received: Cell::new(0),
+        network,
+        ended: RefCell::new(Some(sender)),
+        on_ended: RefCell::new(Some(receiver)),
+    });
+
+    Ok(FetchResponse {
+        status: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or("").to_string(),
+        headers,
+        url: request.url.into(),
+        response_rid,
+        content_length,
+        remote_addr_ip: remote_addr.map(|addr| addr.ip().to_string()),
+        remote_addr_port: remote_addr.map(|addr| addr.port()),
+        error: None,
+    })
+}
+
+/// The body of a `fetch` response. Counts the bytes received and reports when the body was
+/// received to the end, failed, or was dropped before it was read to the end, see
+/// `op_fetch_body_ended`.
+struct TrackedResponseBody {
+    body: Rc<FetchResponseResource>,
+    metrics: StoredMetrics,
+    bytes_received: Cell<u64>,
+    network: NetworkInfo,
+    ended: RefCell<Option<oneshot::Sender<BodyEnded>>>,
+    on_ended: RefCell<Option<oneshot::Receiver<BodyEnded>>>,
+}
+
+/// What the HTTP client reported about the connection and the size of the response as received,
+/// see `op_fetch_network_timing`.
+struct NetworkInfo {
+    version: hyper::Version,
+    connection: Option<ConnectionTiming>,
+    encoded_size: Option<EncodedResponseSize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BodyEnded {
+    bytes_received: u64,
+    /// The body size before decompression, 0 when unknown
+    encoded_bytes_received: u64,
+    success: bool,
+}
+
+impl TrackedResponseBody {
+    fn end(&self, success: bool) {
+        if let Some(sender) = self.ended.borrow_mut().take() {
+            let encoded_size = self.network.encoded_size.as_ref();
+            let _ = sender.send(BodyEnded {
+                bytes_received: self.bytes_received.get(),
+                encoded_bytes_received: encoded_size.map_or(0, EncodedResponseSize::body),
+                success,
+            });
+        }
+    }
+}
+
+impl Resource for TrackedResponseBody {
+    fn name(&self) -> Cow<str> {
+        self.body.name()
+    }
+
+    fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+        Box::pin(async move {
+            let result = self.body.clone().read(limit).await;
+            match &result {
+                Ok(chunk) if chunk.is_empty() => self.end(true),
+                Ok(chunk) => {
+                    let len = chunk.len() as u64;
+                    self.bytes_received.set(self.bytes_received.get() + len);
+                    self.metrics.fetch_bytes_received.inc_by(len);
+                }
+                Err(_) => self.end(false),
+            }
+            result
+        })
+    }
+
+    fn size_hint(&self) -> (u64, Option<u64>) {
+        self.body.size_hint()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.body.clone().close()
+    }
+}
+
+impl Drop for TrackedResponseBody {
+    fn drop(&mut self) {
+        // The body was cancelled, or the response was garbage-collected before the body was read
+        self.end(false);
+    }
+}
+
+// Resolves when the body of a `fetch` response ended, see `TrackedResponseBody`. The returned
+// promise should be unref'ed, the body may never be read.
+#[op2(async)]
+#[serde]
+async fn op_fetch_body_ended(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<BodyEnded, JsErrorBox> {
+    let body = state
+        .borrow()
+        .resource_table
+        .get::<TrackedResponseBody>(rid)
+        .map_err(|err| JsErrorBox::generic(err.to_string()))?;
+    let on_ended = body.on_ended.borrow_mut().take();
+    drop(body);
+    let on_ended =
+        on_ended.ok_or_else(|| JsErrorBox::generic("The response body is already tracked"))?;
+    Ok(on_ended.await.unwrap_or(BodyEnded {
+        bytes_received: 0,
+        encoded_bytes_received: 0,
+        success: false,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkTiming {
+    /// The ALPN protocol ID of the HTTP version, e.g. `http/1.1`
+    next_hop_protocol: &'static str,
+    /// The size of the response status line and headers, 0 when unknown (HTTP/2)
+    head_size: u64,
+    connection: Option<ConnectionPhases>,
+}
+
+/// How many milliseconds ago the phases of establishing the connection happened. The connection
+/// may have been established for an earlier request and reused.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionPhases {
+    domain_lookup_start: f64,
+    domain_lookup_end: f64,
+    connect_start: f64,
+    secure_connection_start: Option<f64>,
+    connect_end: f64,
+}
+
+// Returns the network timing of a `fetch` response tracked by `TrackedResponseBody`. Timestamps
+// are relative to the time of the call, `resource_timing.js` converts them to `performance.now()`
+// timestamps.
+#[op2]
+#[serde]
+fn op_fetch_network_timing(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+) -> Result<NetworkTiming, JsErrorBox> {
+    let body = state
+        .resource_table
+        .get::<TrackedResponseBody>(rid)
+        .map_err(|err| JsErrorBox::generic(err.to_string()))?;
+    let network = &body.network;
+    let now = Instant::now();
+    let ms_ago = |instant: Instant| now.duration_since(instant).as_secs_f64() * 1000.0;
+    let connection = network.connection.map(|timing| {
+        let domain_lookup_end = timing.domain_lookup_end.unwrap_or(timing.start);
+        ConnectionPhases {
+            domain_lookup_start: ms_ago(timing.start),
+            domain_lookup_end: ms_ago(domain_lookup_end),
+            connect_start: ms_ago(domain_lookup_end),
+            secure_connection_start: timing.secure_connection_start.map(ms_ago),
+            connect_end: ms_ago(timing.end),
+        }
+    });
+    Ok(NetworkTiming {
+        next_hop_protocol: match network.version {
+            hyper::Version::HTTP_10 => "http/1.0",
+            hyper::Version::HTTP_11 => "http/1.1",
+            hyper::Version::HTTP_2 => "h2",
+            hyper::Version::HTTP_3 => "h3",
+            _ => "",
+        },
+        head_size: network
+            .encoded_size
+            .as_ref()
+            .and_then(EncodedResponseSize::head)
+            .unwrap_or(0),
+        connection,
+    })
+}
+
+/// The proxy configured by the embedder and the HTTP client for requests to hosts excluded from
+/// the proxy. The default HTTP client of `fetch` sends all requests via the proxy.
+struct FetchProxy {
+    config: Option<ProxyConfig>,
+    direct_client: Option<ResourceId>,
+}
+
+// Returns the HTTP client to use for a request to the given host when the request must bypass
+// the proxy, e.g. a request to the local Lassie daemon. Returns `None` for the default client.
+#[op2]
+#[serde]
+fn op_fetch_direct_client(
+    state: &mut OpState,
+    #[string] host: &str,
+) -> Result<Option<ResourceId>, JsErrorBox> {
+    let fetch_proxy = state.borrow::<FetchProxy>();
+    match &fetch_proxy.config {
+        Some(proxy) if proxy.bypasses(host) => {}
+        _ => return Ok(None),
+    }
+    if let Some(rid) = fetch_proxy.direct_client {
+        return Ok(Some(rid));
+    }
+
+    // Use the same settings as the default client, except for the proxy
+    let options = state.borrow::<deno_fetch::Options>();
+    let client = create_http_client(
+        &options.user_agent,
+        CreateHttpClientOptions {
+            root_cert_store: options.root_cert_store()?,
+            dns_resolver: options.resolver.clone(),
+            unsafely_ignore_certificate_errors: options.unsafely_ignore_certificate_errors.clone(),
+            client_cert_chain_and_key: options
+                .client_cert_chain_and_key
+                .clone()
+                .try_into()
+                .unwrap_or_default(),
+            client_builder_hook: options.client_builder_hook,
+            proxy_from_env: false,
+            ..Default::default()
+        },
+    )
+    .map_err(JsErrorBox::from_err)?;
+    let rid = state.resource_table.add(HttpClientResource {
+        client,
+        allow_host: false,
+    });
+    state.borrow_mut::<FetchProxy>().direct_client = Some(rid);
+    Ok(Some(rid))
+}
+
+#[op2(fast)]
+fn op_ipfs_retrieval_finished(state: &mut OpState, success: bool, duration_ms: f64) {
+    let metrics = state.borrow::<StoredMetrics>();
+    if success {
+        metrics.ipfs_retrievals_succeeded.inc();
+    } else {
+        metrics.ipfs_retrievals_failed.inc();
+    }
+    metrics
+        .ipfs_retrieval_duration
+        .observe(duration_ms / 1000.0);
+}
+
+// Returns an empty string when retrievals are allowed
+#[op2]
+#[string]
+fn op_ipfs_retrievals_paused(state: &mut OpState) -> String {
+    state
+        .borrow::<RetrievalGate>()
+        .paused_reason()
+        .unwrap_or_default()
+}
+
+// The value of the header marking requests for the retrieval backend that need the credentials
+// configured by the embedder, see `add_retrieval_credentials`
+#[op2]
+#[string]
+fn op_ipfs_credentials_id(state: &mut OpState) -> Option<String> {
+    state
+        .try_borrow::<RetrievalCredentials>()
+        .map(|credentials| credentials.id().to_string())
+}
+
+// Read `{cid}.car` from the directory configured by `CarDirectoryBackend`. Returns `None` when
+// the file does not exist.
+#[op2(async)]
+#[serde]
+async fn op_ipfs_read_car(
+    state: Rc<RefCell<OpState>>,
+    #[string] cid: String,
+) -> Result<Option<ToJsBuffer>, JsErrorBox> {
+    let dir = match state.borrow().borrow::<RetrievalEndpoint>() {
+        RetrievalEndpoint::CarDirectory(dir) => dir.clone(),
+        _ => {
+            return Err(JsErrorBox::generic(
+                "IPFS retrievals are not using a CAR directory",
+            ))
+        }
+    };
+    // Don't allow the module to read files outside of the directory
+    if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(JsErrorBox::type_error(format!("Invalid CID {cid:?}")));
+    }
+    match tokio::fs::read(dir.join(format!("{cid}.car"))).await {
+        Ok(bytes) => Ok(Some(bytes.into())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(JsErrorBox::generic(format!(
+            "Cannot read the CAR file for {cid}: {err}"
+        ))),
+    }
+}
+
+struct CarVerifierResource(RefCell<CarVerifier>);
+
+impl Resource for CarVerifierResource {
+    fn name(&self) -> Cow<str> {
+        "carVerifier".into()
+    }
+}
+
+#[op2]
+#[smi]
+fn op_car_verifier_create(
+    state: &mut OpState,
+    #[string] root: &str,
+    #[string] path: &str,
+    #[string] dag_scope: &str,
+    #[serde] max_bytes: Option<u64>,
+) -> Result<ResourceId, JsErrorBox> {
+    let scope: DagScope = dag_scope
+        .parse()
+        .map_err(|err| JsErrorBox::type_error(format!("{err:#}")))?;
+    let mut verifier = CarVerifier::new(root, path, scope)
+        .map_err(|err| JsErrorBox::type_error(format!("{err:#}")))?;
+    if let Some(max_bytes) = max_bytes {
+        verifier = verifier.with_max_bytes(max_bytes);
+    }
+    Ok(state
+        .resource_table
+        .add(CarVerifierResource(RefCell::new(verifier))))
+}
+
+#[op2(fast)]
+fn op_car_verifier_write(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    #[buffer] chunk: &[u8],
+) -> Result<(), JsErrorBox> {
+    let resource = state
+        .resource_table
+        .get::<CarVerifierResource>(rid)
+        .map_err(|err| JsErrorBox::generic(err.to_string()))?;
+    let result = resource.0.borrow_mut().write(chunk);
+    result.map_err(|err| JsErrorBox::generic(format!("Invalid CAR: {err:#}")))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CarVerificationResult {
+    report: VerificationReport,
+    blocks: Vec<VerifiedBlock>,
+    file: Option<ToJsBuffer>,
+}
+
+#[derive(Serialize)]
+struct VerifiedBlock {
+    cid: String,
+    bytes: ToJsBuffer,
+}
+
+// Consumes the verifier. When `read_file` is true, the target of the path must be a UnixFS
+// file and its content is returned instead of the blocks.
+#[op2]
+#[serde]
+fn op_car_verifier_finish(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    read_file: bool,
+) -> Result<CarVerificationResult, JsErrorBox> {
+    let resource = state
+        .resource_table
+        .take::<CarVerifierResource>(rid)
+        .map_err(|err| JsErrorBox::generic(err.to_string()))?;
+    let verifier = Rc::try_unwrap(resource)
+        .map_err(|_| JsErrorBox::generic("The CAR verifier is still in use"))?
+        .0
+        .into_inner();
+    let dag = verifier
+        .finish()
+        .map_err(|err| JsErrorBox::generic(format!("Invalid CAR: {err:#}")))?;
+
+    if read_file {
+        let file = dag
+            .read_file()
+            .map_err(|err| JsErrorBox::generic(format!("Cannot read the file: {err:#}")))?;
+        return Ok(CarVerificationResult {
+            report: dag.report,
+            blocks: vec![],
+            file: Some(file.into()),
+        });
+    }
+
+    let blocks = dag
+        .blocks
+        .into_iter()
+        .map(|(cid, bytes)| VerifiedBlock {
+            cid: cid.to_string(),
+            bytes: bytes.into(),
+        })
+        .collect();
+    Ok(CarVerificationResult {
+        report: dag.report,
+        blocks,
+        file: None,
+    })
+}
+
+// Returns the entries loaded from the fixture file in the replay mode
+#[op2]
+#[serde]
+fn op_network_fixture_entries(state: &mut OpState) -> Result<Vec<Value>, JsErrorBox> {
+    let fixture = state
+        .try_borrow::<FixtureState>()
+        .ok_or_else(|| JsErrorBox::generic("The network fixture is not configured"))?;
+    Ok(fixture.entries.clone())
+}
+
+// Stores the entry describing a finished request or WebSocket connection in the record mode
+#[op2]
+fn op_network_fixture_record(
+    state: &mut OpState,
+    #[smi] index: u32,
+    #[serde] entry: Value,
+) -> Result<(), JsErrorBox> {
+    let fixture = state
+        .try_borrow_mut::<FixtureState>()
+        .ok_or_else(|| JsErrorBox::generic("The network fixture is not configured"))?;
+    fixture.record(index as usize, entry);
+    Ok(())
+}
+
+/// The random number generator backing `Math.random()` when the embedder provides a seed.
+/// `crypto.getRandomValues()` uses another generator seeded by `deno_crypto`.
+struct SeededRandom(StdRng);
+
+#[op2(fast)]
+fn op_seeded_math_random(state: &mut OpState) -> f64 {
+    state.borrow_mut::<SeededRandom>().0.gen()
+}
+
+/// The virtual clock of the fake timers in `zinnia:test` and `zinnia run --seed`. The timers of
+/// `deno_web` are scheduled on the clock in JavaScript, the runtime reports its time from
+/// `performance.now()`.
+struct VirtualClock {
+    /// The time elapsed since the time origin
+    elapsed: Duration,
+}
+
+// Install the virtual clock or move it to the given time
+#[op2(fast)]
+fn op_virtual_clock_set(state: &mut OpState, elapsed_ms: f64) -> Result<(), JsErrorBox> {
+    if !(elapsed_ms.is_finite() && elapsed_ms >= 0.0) {
+        return Err(JsErrorBox::type_error(format!(
+            "Invalid virtual clock time: {elapsed_ms}"
+        )));
+    }
+    // Whole nanoseconds, so that `performance.now()` reports whole milliseconds exactly
+    let elapsed = Duration::from_nanos((elapsed_ms * 1e6).round() as u64);
+    state.put(VirtualClock { elapsed });
+    Ok(())
+}
+
+#[op2(fast)]
+fn op_virtual_clock_clear(state: &mut OpState) {
+    state.try_take::<VirtualClock>();
+}
+
+// Replaces `op_now` from `deno_web`. The virtual clock is reported as-is, the real time is rounded
+// like `op_now` does when high-resolution time is not allowed.
+#[op2(fast)]
+fn op_zinnia_now(state: &mut OpState, #[buffer] buf: &mut [u8]) {
+    let virtual_elapsed = state
+        .try_borrow::<VirtualClock>()
+        .map(|clock| clock.elapsed);
+    let (seconds, subsec_nanos) = match virtual_elapsed {
+        Some(elapsed) => (elapsed.as_secs(), elapsed.subsec_nanos()),
+        None => {
+            let elapsed = state.borrow::<StartTime>().elapsed();
+            let mut subsec_nanos = elapsed.subsec_nanos();
+            if !state.borrow_mut::<ZinniaPermissions>().allow_hrtime() {
+                // 2ms in nanoseconds
+                subsec_nanos -= subsec_nanos % 2_000_000;
+            }
+            (elapsed.as_secs(), subsec_nanos)
+        }
+    };
+    if buf.len() >= 8 {
+        buf[0..4].copy_from_slice(&(seconds as u32).to_ne_bytes());
+        buf[4..8].copy_from_slice(&subsec_nanos.to_ne_bytes());
+    }
+}
+
 #[op2]
 #[string]
 fn op_format_test_error(#[serde] error: JsError) -> String {
@@ -202,3 +891,36 @@ pub fn op_bootstrap_stderr_no_color(_state: &mut OpState) -> bool {
 
     !deno_terminal::is_stderr_tty() || !deno_terminal::colors::use_color()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allow(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn allows_listed_hosts() {
+        let list = allow(&["example.com", "*.filstation.app"]);
+        assert!(is_host_allowed(&list, "example.com"));
+        assert!(is_host_allowed(&list, "EXAMPLE.com."));
+        assert!(is_host_allowed(&list, "api.filstation.app"));
+        assert!(!is_host_allowed(&list, "filstation.app"));
+        assert!(!is_host_allowed(&list, "www.example.com"));
+        assert!(!is_host_allowed(&list, "example.org"));
+        assert!(is_host_allowed(&allow(&["*"]), "example.org"));
+    }
+
+    #[test]
+    fn always_allows_retrieval_backend() {
+        let permissions = ZinniaPermissions {
+            allow_net: Some(vec![]),
+            retrieval_url: Some(Url::parse("http://127.0.0.1:3000/").unwrap()),
+        };
+        let lassie = Url::parse("http://127.0.0.1:3000/ipfs/bafy").unwrap();
+        assert!(permissions.check_url(&lassie).is_ok());
+        let other = Url::parse("http://127.0.0.1:3001/").unwrap();
+        assert!(permissions.check_url(&other).is_err());
+    }
+}
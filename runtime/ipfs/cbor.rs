@@ -0,0 +1,175 @@
+// A minimal DAG-CBOR decoder, good enough to read CAR headers and find links in DAG-CBOR blocks.
+// See https://ipld.io/specs/codecs/dag-cbor/spec/
+
+use deno_core::anyhow::{bail, Context, Result};
+
+use super::cid::Cid;
+
+// DAG-CBOR data is untrusted, limit the nesting to avoid stack overflows
+const MAX_DEPTH: usize = 128;
+
+// CBOR tag for CIDs
+const CID_TAG: u64 = 42;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Link(Cid),
+    /// Booleans, null and floats - we don't need their values
+    Other,
+}
+
+impl Value {
+    /// Look up a map entry with a string key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find_map(|(k, v)| match k {
+                Value::Text(k) if k == key => Some(v),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Collect all links in this value, in the order they appear.
+    pub fn links(&self, out: &mut Vec<Cid>) {
+        match self {
+            Value::Link(cid) => out.push(cid.clone()),
+            Value::Array(items) => items.iter().for_each(|item| item.links(out)),
+            Value::Map(entries) => entries.iter().for_each(|(_, value)| value.links(out)),
+            _ => {}
+        }
+    }
+}
+
+/// Decode a single DAG-CBOR value, `data` must not contain anything else.
+pub fn decode(data: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let value = decoder.value(0)?;
+    if decoder.pos != data.len() {
+        bail!("unexpected data after the DAG-CBOR value");
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("truncated DAG-CBOR data")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    // Read the initial byte, return the major type and the argument
+    fn header(&mut self) -> Result<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(self.take(2)?.try_into()?)),
+            26 => u64::from(u32::from_be_bytes(self.take(4)?.try_into()?)),
+            27 => u64::from_be_bytes(self.take(8)?.try_into()?),
+            31 => bail!("indefinite-length items are not allowed in DAG-CBOR"),
+            info => bail!("invalid CBOR additional info {info}"),
+        };
+        Ok((major, argument))
+    }
+
+    fn length(&self, argument: u64) -> Result<usize> {
+        // Every item takes at least one byte, this prevents huge allocations
+        let remaining = self.data.len() - self.pos;
+        match usize::try_from(argument) {
+            Ok(len) if len <= remaining => Ok(len),
+            _ => bail!("truncated DAG-CBOR data"),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("DAG-CBOR data is nested too deeply");
+        }
+        let (major, argument) = self.header()?;
+        let value = match major {
+            0 => Value::Integer(i128::from(argument)),
+            1 => Value::Integer(-1 - i128::from(argument)),
+            2 => {
+                let len = self.length(argument)?;
+                Value::Bytes(self.take(len)?.to_vec())
+            }
+            3 => {
+                let len = self.length(argument)?;
+                let text = std::str::from_utf8(self.take(len)?).context("invalid UTF-8 string")?;
+                Value::Text(text.to_string())
+            }
+            4 => {
+                let len = self.length(argument)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let len = self.length(argument)?;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let key = self.value(depth + 1)?;
+                    let value = self.value(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Value::Map(entries)
+            }
+            6 if argument == CID_TAG => match self.value(depth + 1)? {
+                // Binary CIDs are prefixed with the multibase identity prefix 0x00
+                Value::Bytes(bytes) if bytes.first() == Some(&0) => {
+                    Value::Link(Cid::from_bytes(&bytes[1..])?)
+                }
+                _ => bail!("invalid CID in DAG-CBOR data"),
+            },
+            6 => bail!("CBOR tag {argument} is not allowed in DAG-CBOR"),
+            _ => Value::Other,
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_car_header() {
+        let header = data_encoding::HEXLOWER
+            .decode(b"a265726f6f747381d82a58250001551220fae8d07f50dbbcd3fdd65610adcd6c3df2171b31ae97041b1e222506e78dfd946776657273696f6e01")
+            .unwrap();
+        let value = decode(&header).unwrap();
+        assert_eq!(value.get("version"), Some(&Value::Integer(1)));
+
+        let mut links = vec![];
+        value.links(&mut links);
+        assert_eq!(
+            links.iter().map(|cid| cid.to_string()).collect::<Vec<_>>(),
+            vec!["bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq"]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        // An array of 5 items with only one item
+        assert!(decode(&[0x85, 0x01]).is_err());
+        // Indefinite-length array
+        assert!(decode(&[0x9f, 0xff]).is_err());
+    }
+}
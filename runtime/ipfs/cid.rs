@@ -0,0 +1,279 @@
+use std::fmt;
+use std::str::FromStr;
+
+use data_encoding::{BASE32_NOPAD, HEXLOWER_PERMISSIVE};
+use deno_core::anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Multicodec codes of the IPLD codecs we can traverse.
+pub const RAW: u64 = 0x55;
+pub const DAG_PB: u64 = 0x70;
+pub const DAG_CBOR: u64 = 0x71;
+
+/// Multihash codes of the hash functions we can verify.
+const IDENTITY: u64 = 0x00;
+const SHA2_256: u64 = 0x12;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A content identifier, see https://github.com/multiformats/cid.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    version: u64,
+    codec: u64,
+    hash_code: u64,
+    digest: Vec<u8>,
+}
+
+impl Cid {
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// The data of an identity CID is inlined in the CID itself, such blocks don't have to be
+    /// included in the CAR file.
+    pub fn inline_data(&self) -> Option<&[u8]> {
+        (self.hash_code == IDENTITY).then_some(self.digest.as_slice())
+    }
+
+    /// Parse a binary CID from the beginning of `bytes`, return the CID and its length in bytes.
+    pub fn read_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        // CIDv0 is a bare sha2-256 multihash
+        if bytes.starts_with(&[SHA2_256 as u8, 32]) {
+            let digest = bytes.get(2..34).context("truncated CIDv0")?.to_vec();
+            let cid = Cid {
+                version: 0,
+                codec: DAG_PB,
+                hash_code: SHA2_256,
+                digest,
+            };
+            return Ok((cid, 34));
+        }
+
+        let mut pos = 0;
+        let mut next = |name: &str| -> Result<u64> {
+            let (value, len) =
+                read_varint(&bytes[pos..])?.with_context(|| format!("truncated CID {name}"))?;
+            pos += len;
+            Ok(value)
+        };
+        let version = next("version")?;
+        if version != 1 {
+            bail!("unsupported CID version {version}");
+        }
+        let codec = next("codec")?;
+        let hash_code = next("multihash code")?;
+        let digest_len = next("multihash length")? as usize;
+        let digest = bytes
+            .get(pos..pos + digest_len)
+            .context("truncated CID multihash")?
+            .to_vec();
+        let cid = Cid {
+            version,
+            codec,
+            hash_code,
+            digest,
+        };
+        Ok((cid, pos + digest_len))
+    }
+
+    /// Parse a binary CID, `bytes` must not contain any other data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (cid, len) = Self::read_bytes(bytes)?;
+        if len != bytes.len() {
+            bail!("unexpected data after CID {cid}");
+        }
+        Ok(cid)
+    }
+
+    fn multihash(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_varint(&mut bytes, self.hash_code);
+        write_varint(&mut bytes, self.digest.len() as u64);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        if self.version == 0 {
+            return self.multihash();
+        }
+        let mut bytes = vec![];
+        write_varint(&mut bytes, self.version);
+        write_varint(&mut bytes, self.codec);
+        bytes.extend(self.multihash());
+        bytes
+    }
+
+    /// Check that the block data matches this CID.
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        let matches = match self.hash_code {
+            IDENTITY => self.digest == data,
+            SHA2_256 => self.digest[..] == Sha256::digest(data)[..],
+            code => bail!("unsupported multihash function 0x{code:x} in CID {self}"),
+        };
+        if !matches {
+            bail!("block data does not match CID {self}");
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cid {
+    type Err = deno_core::anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("invalid CID {s:?}");
+        if s.len() == 46 && s.starts_with("Qm") {
+            return Cid::from_bytes(&base58_decode(s).with_context(invalid)?).with_context(invalid);
+        }
+        let mut chars = s.chars();
+        let bytes = match chars.next() {
+            Some('b') | Some('B') => BASE32_NOPAD
+                .decode(chars.as_str().to_ascii_uppercase().as_bytes())
+                .map_err(|err| anyhow!(err)),
+            Some('z') => base58_decode(chars.as_str()),
+            Some('f') | Some('F') => HEXLOWER_PERMISSIVE
+                .decode(chars.as_str().to_ascii_lowercase().as_bytes())
+                .map_err(|err| anyhow!(err)),
+            _ => Err(anyhow!("unsupported multibase encoding")),
+        }
+        .with_context(invalid)?;
+        let cid = Cid::from_bytes(&bytes).with_context(invalid)?;
+        if cid.version == 0 {
+            // CIDv0 must be encoded in base58btc without the multibase prefix
+            bail!(invalid());
+        }
+        Ok(cid)
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version == 0 {
+            write!(f, "{}", base58_encode(&self.to_bytes()))
+        } else {
+            let encoded = BASE32_NOPAD.encode(&self.to_bytes()).to_ascii_lowercase();
+            write!(f, "b{encoded}")
+        }
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({self})")
+    }
+}
+
+/// Read an unsigned varint from the beginning of `bytes`. Returns the value and its length in
+/// bytes, or `None` when `bytes` ends before the varint is complete.
+pub fn read_varint(bytes: &[u8]) -> Result<Option<(u64, usize)>> {
+    let mut value: u64 = 0;
+    for (ix, byte) in bytes.iter().enumerate() {
+        // The multiformats spec limits varints to 9 bytes (63 bits)
+        if ix >= 9 {
+            bail!("varint is too long");
+        }
+        value |= u64::from(byte & 0x7f) << (7 * ix);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, ix + 1)));
+        }
+    }
+    Ok(None)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>> {
+    // Big-endian base-256 number, the input is short so the quadratic algorithm is fine
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .with_context(|| format!("invalid base58 character {:?}", c as char))?
+            as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeroes = s.bytes().take_while(|&c| c == b'1').count();
+    let mut result = vec![0; zeroes];
+    result.extend(bytes);
+    Ok(result)
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    // Little-endian base-58 digits
+    let mut digits: Vec<u8> = vec![];
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeroes = bytes.iter().take_while(|&&b| b == 0).count();
+    std::iter::repeat_n(b'1', zeroes)
+        .chain(digits.iter().rev().map(|&d| BASE58_ALPHABET[d as usize]))
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_and_formats_cids() {
+        for s in [
+            "bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq",
+            "bafybeib36krhffuh3cupjml4re2wfxldredkir5wti3dttulyemre7xkni",
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+        ] {
+            let cid: Cid = s.parse().unwrap();
+            assert_eq!(cid.to_string(), s);
+            assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+        }
+
+        let cid: Cid = "bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq"
+            .parse()
+            .unwrap();
+        assert_eq!(cid.codec(), RAW);
+    }
+
+    #[test]
+    fn rejects_invalid_cids() {
+        assert!("".parse::<Cid>().is_err());
+        assert!("bafy".parse::<Cid>().is_err());
+        assert!("not-a-cid".parse::<Cid>().is_err());
+    }
+
+    #[test]
+    fn verifies_block_data() {
+        // sha256("hello")
+        let cid: Cid = "bafkreibm6jg3ux5qumhcn2b3flc3tyu6dmlb4xa7u5bf44yegnrjhc4yeq"
+            .parse()
+            .unwrap();
+        assert!(cid.verify(b"hello").is_ok());
+        assert!(cid.verify(b"hello!").is_err());
+    }
+}
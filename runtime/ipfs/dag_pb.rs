@@ -0,0 +1,121 @@
+// DAG-PB nodes and UnixFS metadata, see https://ipld.io/specs/codecs/dag-pb/spec/ and
+// https://github.com/ipfs/specs/blob/main/UNIXFS.md
+
+use deno_core::anyhow::{Context, Result};
+use prost::Message;
+
+use super::cid::Cid;
+
+#[derive(Clone, PartialEq, Message)]
+struct PbLink {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    hash: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "2")]
+    name: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PbNode {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    data: Option<Vec<u8>>,
+    #[prost(message, repeated, tag = "2")]
+    links: Vec<PbLink>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct UnixFsData {
+    #[prost(int32, optional, tag = "1")]
+    data_type: Option<i32>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnixFsType {
+    Raw,
+    Directory,
+    File,
+    Metadata,
+    Symlink,
+    HamtShard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub cid: Cid,
+    pub name: String,
+}
+
+/// A decoded DAG-PB node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub links: Vec<Link>,
+    /// UnixFS metadata, `None` when the node is not a valid UnixFS node.
+    pub unixfs: Option<UnixFs>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixFs {
+    pub data_type: UnixFsType,
+    /// File content stored in this node, it comes before the content of the linked nodes.
+    pub data: Vec<u8>,
+}
+
+pub fn decode(block: &[u8]) -> Result<Node> {
+    let node = PbNode::decode(block).context("invalid DAG-PB node")?;
+    let links = node
+        .links
+        .into_iter()
+        .map(|link| {
+            let hash = link.hash.context("DAG-PB link without a CID")?;
+            Ok(Link {
+                cid: Cid::from_bytes(&hash)?,
+                name: link.name.unwrap_or_default(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let unixfs = node
+        .data
+        .and_then(|data| UnixFsData::decode(data.as_slice()).ok())
+        .and_then(|data| {
+            let data_type = match data.data_type? {
+                0 => UnixFsType::Raw,
+                1 => UnixFsType::Directory,
+                2 => UnixFsType::File,
+                3 => UnixFsType::Metadata,
+                4 => UnixFsType::Symlink,
+                5 => UnixFsType::HamtShard,
+                _ => return None,
+            };
+            Some(UnixFs {
+                data_type,
+                data: data.data.unwrap_or_default(),
+            })
+        });
+    Ok(Node { links, unixfs })
+}
+
+impl Node {
+    pub fn is_file(&self) -> bool {
+        matches!(
+            self.unixfs.as_ref().map(|unixfs| unixfs.data_type),
+            Some(UnixFsType::File | UnixFsType::Raw)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_unixfs_directory() {
+        // The empty UnixFS directory QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn
+        let node = decode(&[0x0a, 0x02, 0x08, 0x01]).unwrap();
+        assert_eq!(node.links, vec![]);
+        assert_eq!(
+            node.unixfs.map(|unixfs| unixfs.data_type),
+            Some(UnixFsType::Directory)
+        );
+    }
+}
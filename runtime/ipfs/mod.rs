@@ -0,0 +1,18 @@
+// Verification of CAR files returned by IPFS retrievals, used by the `Zinnia.ipfs` API.
+// We implement only the subset of IPLD needed to verify CARs returned by Lassie and trustless
+// gateways: CIDv0 & CIDv1, sha2-256 & identity hashes, raw, DAG-PB and DAG-CBOR codecs.
+//
+// We don't use the `cid`, `multihash` and `serde_ipld_dagcbor` crates on purpose. The verifier
+// must fail closed: a block hashed with a function we don't check or encoded with a codec we
+// can't traverse is an error, not something to skip. The crates parse more than we can verify,
+// so we would need the same allow-lists on top of them, and we only need the links and UnixFS
+// metadata from the blocks. Our decoders reject indefinite-length CBOR items, limit the nesting
+// depth of untrusted data and fit in ~600 lines covered by the tests in this module and by
+// `ipfs_retrieval_tests.js`. Switch to the crates if we ever need more of IPLD.
+
+mod cbor;
+mod cid;
+mod dag_pb;
+mod verifier;
+
+pub use verifier::{CarVerifier, DagScope, VerificationReport};
@@ -0,0 +1,451 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use deno_core::anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use super::cbor;
+use super::cid::{read_varint, Cid, DAG_CBOR, DAG_PB, RAW};
+use super::dag_pb::{self, UnixFsType};
+
+// Blocks are limited to 2 MiB by the Bitswap spec, allow some room for other transports
+const MAX_FRAME_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The default limit of the CAR size, see `CarVerifier::with_max_bytes()`.
+const DEFAULT_MAX_CAR_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Which part of the DAG the CAR file contains, see the `dag-scope` parameter in
+/// https://specs.ipfs.tech/http-gateways/trustless-gateway/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DagScope {
+    /// The blocks on the path and the entire DAG of the target.
+    All,
+    /// The blocks on the path and the blocks needed to read the target file. For other
+    /// targets, only the target block.
+    Entity,
+    /// The blocks on the path and the target block.
+    Block,
+}
+
+impl DagScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DagScope::All => "all",
+            DagScope::Entity => "entity",
+            DagScope::Block => "block",
+        }
+    }
+}
+
+impl FromStr for DagScope {
+    type Err = deno_core::anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(DagScope::All),
+            "entity" => Ok(DagScope::Entity),
+            "block" => Ok(DagScope::Block),
+            _ => Err(anyhow!(
+                "Invalid DAG scope {s:?}. Supported scopes: all, entity, block."
+            )),
+        }
+    }
+}
+
+/// Verify a CAR stream as it arrives: every block must match its CID, the CAR must contain
+/// exactly the blocks needed to resolve `path` from `root` plus the blocks of the target DAG
+/// within the requested scope.
+pub struct CarVerifier {
+    root: Cid,
+    path: Vec<String>,
+    scope: DagScope,
+    buffer: Vec<u8>,
+    header_received: bool,
+    car_bytes: u64,
+    max_bytes: u64,
+    blocks: Vec<(Cid, Vec<u8>)>,
+    index: HashMap<Cid, usize>,
+    duplicate_blocks: u64,
+}
+
+impl CarVerifier {
+    pub fn new(root: &str, path: &str, scope: DagScope) -> Result<Self> {
+        let root: Cid = root.parse()?;
+        let path = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                percent_encoding::percent_decode_str(segment)
+                    .decode_utf8()
+                    .map(|s| s.into_owned())
+                    .with_context(|| format!("invalid path segment {segment:?}"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            root,
+            path,
+            scope,
+            buffer: vec![],
+            header_received: false,
+            car_bytes: 0,
+            max_bytes: DEFAULT_MAX_CAR_BYTES,
+            blocks: vec![],
+            index: HashMap::new(),
+            duplicate_blocks: 0,
+        })
+    }
+
+    /// Reject CAR streams larger than `max_bytes`. The verifier keeps all blocks in memory
+    /// until the stream ends, this limit prevents a provider from exhausting the memory.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Process the next chunk of the CAR stream.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.car_bytes += chunk.len() as u64;
+        if self.car_bytes > self.max_bytes {
+            bail!(
+                "the CAR is larger than the limit of {} bytes",
+                self.max_bytes
+            );
+        }
+
+        // Parse the frames in place, we copy only the block data we keep
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.extend_from_slice(chunk);
+
+        let mut pos = 0;
+        while let Some((len, varint_len)) = read_varint(&buffer[pos..])? {
+            if len == 0 || len > MAX_FRAME_SIZE {
+                bail!("invalid CAR section length {len}");
+            }
+            let start = pos + varint_len;
+            let end = start + len as usize;
+            if end > buffer.len() {
+                break;
+            }
+            let frame = &buffer[start..end];
+            if self.header_received {
+                self.add_block(frame)?;
+            } else {
+                self.check_header(frame)?;
+                self.header_received = true;
+            }
+            pos = end;
+        }
+        buffer.drain(..pos);
+        self.buffer = buffer;
+        Ok(())
+    }
+
+    fn check_header(&self, frame: &[u8]) -> Result<()> {
+        let header = cbor::decode(frame).context("invalid CAR header")?;
+        match header.get("version") {
+            Some(cbor::Value::Integer(1)) => {}
+            Some(cbor::Value::Integer(2)) => bail!("CARv2 is not supported"),
+            _ => bail!("invalid CAR header: unsupported version"),
+        }
+        let mut roots = vec![];
+        if let Some(value) = header.get("roots") {
+            value.links(&mut roots);
+        }
+        if !roots.contains(&self.root) {
+            bail!(
+                "CAR roots {:?} do not include the requested root {}",
+                roots.iter().map(Cid::to_string).collect::<Vec<_>>(),
+                self.root
+            );
+        }
+        Ok(())
+    }
+
+    fn add_block(&mut self, frame: &[u8]) -> Result<()> {
+        let (cid, cid_len) = Cid::read_bytes(frame).context("invalid CAR block")?;
+        let data = &frame[cid_len..];
+        cid.verify(data)?;
+        if self.index.contains_key(&cid) {
+            self.duplicate_blocks += 1;
+        } else {
+            self.index.insert(cid.clone(), self.blocks.len());
+            self.blocks.push((cid, data.to_vec()));
+        }
+        Ok(())
+    }
+
+    /// Finish the verification after the entire stream was received.
+    pub fn finish(self) -> Result<VerifiedDag> {
+        if !self.header_received {
+            bail!("the CAR stream ended before the header was received");
+        }
+        if !self.buffer.is_empty() {
+            bail!("the CAR stream ended in the middle of a block");
+        }
+
+        let blocks = BlockStore {
+            blocks: &self.blocks,
+            index: &self.index,
+        };
+        let mut expected = HashSet::new();
+
+        // Resolve the path
+        let mut target = self.root.clone();
+        for segment in &self.path {
+            expected.insert(target.clone());
+            target = resolve_segment(&blocks, &target, segment)?;
+        }
+
+        // Collect the blocks of the target DAG within the requested scope
+        let include_children = match self.scope {
+            DagScope::All => true,
+            DagScope::Entity => match target.codec() {
+                DAG_PB => dag_pb::decode(blocks.get(&target)?)?.is_file(),
+                _ => false,
+            },
+            DagScope::Block => false,
+        };
+        let mut queue = vec![target.clone()];
+        while let Some(cid) = queue.pop() {
+            if !expected.insert(cid.clone()) {
+                continue;
+            }
+            if include_children {
+                queue.extend(links(&cid, blocks.get(&cid)?)?);
+            } else {
+                // Make sure the target block is present
+                blocks.get(&cid)?;
+            }
+        }
+
+        if let Some((unexpected, _)) = self.blocks.iter().find(|(cid, _)| !expected.contains(cid)) {
+            bail!("the CAR contains block {unexpected} which is not part of the requested DAG");
+        }
+
+        let report = VerificationReport {
+            root: self.root.to_string(),
+            path: self.path.join("/"),
+            target: target.to_string(),
+            dag_scope: self.scope.as_str(),
+            block_count: self.blocks.len(),
+            block_bytes: self.blocks.iter().map(|(_, data)| data.len() as u64).sum(),
+            car_bytes: self.car_bytes,
+            duplicate_blocks: self.duplicate_blocks,
+        };
+        Ok(VerifiedDag {
+            target,
+            blocks: self.blocks,
+            index: self.index,
+            report,
+        })
+    }
+}
+
+/// Summary of a successful verification, returned to JavaScript.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationReport {
+    pub root: String,
+    pub path: String,
+    /// CID of the block the path resolves to
+    pub target: String,
+    pub dag_scope: &'static str,
+    /// Number of unique blocks in the CAR
+    pub block_count: usize,
+    /// Total size of unique blocks
+    pub block_bytes: u64,
+    /// Size of the CAR stream, including the header and duplicate blocks
+    pub car_bytes: u64,
+    pub duplicate_blocks: u64,
+}
+
+/// Blocks of a verified CAR, in the order they were received.
+pub struct VerifiedDag {
+    target: Cid,
+    pub blocks: Vec<(Cid, Vec<u8>)>,
+    index: HashMap<Cid, usize>,
+    pub report: VerificationReport,
+}
+
+impl VerifiedDag {
+    /// Read the content of the UnixFS file at the requested path.
+    pub fn read_file(&self) -> Result<Vec<u8>> {
+        let blocks = BlockStore {
+            blocks: &self.blocks,
+            index: &self.index,
+        };
+        let mut content = vec![];
+        let mut queue = vec![self.target.clone()];
+        while let Some(cid) = queue.pop() {
+            let data = blocks.get(&cid)?;
+            match cid.codec() {
+                RAW => content.extend_from_slice(data),
+                DAG_PB => {
+                    let node = dag_pb::decode(data)?;
+                    match &node.unixfs {
+                        Some(unixfs) if node.is_file() => content.extend_from_slice(&unixfs.data),
+                        Some(unixfs) if unixfs.data_type == UnixFsType::Directory => {
+                            bail!("{cid} is a UnixFS directory, not a file")
+                        }
+                        _ => bail!("{cid} is not a UnixFS file"),
+                    }
+                    // The content of the first link comes first
+                    queue.extend(node.links.into_iter().rev().map(|link| link.cid));
+                }
+                _ => bail!("{cid} is not a UnixFS file"),
+            }
+        }
+        Ok(content)
+    }
+}
+
+struct BlockStore<'a> {
+    blocks: &'a [(Cid, Vec<u8>)],
+    index: &'a HashMap<Cid, usize>,
+}
+
+impl BlockStore<'_> {
+    fn get<'b>(&'b self, cid: &'b Cid) -> Result<&'b [u8]> {
+        if let Some(data) = cid.inline_data() {
+            return Ok(data);
+        }
+        let ix = self
+            .index
+            .get(cid)
+            .with_context(|| format!("the CAR does not contain block {cid}"))?;
+        Ok(&self.blocks[*ix].1)
+    }
+}
+
+fn links(cid: &Cid, data: &[u8]) -> Result<Vec<Cid>> {
+    let links = match cid.codec() {
+        RAW => vec![],
+        DAG_PB => dag_pb::decode(data)?
+            .links
+            .into_iter()
+            .map(|link| link.cid)
+            .collect(),
+        DAG_CBOR => {
+            let mut links = vec![];
+            cbor::decode(data)?.links(&mut links);
+            links
+        }
+        codec => bail!("cannot traverse block {cid} with unsupported codec 0x{codec:x}"),
+    };
+    Ok(links)
+}
+
+fn resolve_segment(blocks: &BlockStore, cid: &Cid, segment: &str) -> Result<Cid> {
+    let data = blocks.get(cid)?;
+    let not_found = || anyhow!("path segment {segment:?} not found in {cid}");
+    match cid.codec() {
+        DAG_PB => {
+            let node = dag_pb::decode(data)?;
+            match node.unixfs.as_ref().map(|unixfs| unixfs.data_type) {
+                Some(UnixFsType::Directory) => {}
+                Some(UnixFsType::HamtShard) => {
+                    bail!("cannot resolve path in {cid}: sharded directories are not supported")
+                }
+                _ => return Err(not_found()),
+            }
+            node.links
+                .into_iter()
+                .find(|link| link.name == segment)
+                .map(|link| link.cid)
+                .ok_or_else(not_found)
+        }
+        DAG_CBOR => match cbor::decode(data)?.get(segment) {
+            Some(cbor::Value::Link(cid)) => Ok(cid.clone()),
+            _ => Err(not_found()),
+        },
+        _ => Err(not_found()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_encoding::BASE64;
+    use pretty_assertions::assert_eq;
+
+    const ROOT: &str = "bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq";
+    // The CAR file used by `ipfs_retrieval_tests.js`
+    const CAR_BASE64: &str = "OqJlcm9vdHOB2CpYJQABVRIg+ujQf1DbvNP91lYQrc1sPfIXGzGulwQbHiIlBueN/ZRndmVyc2lvbgGLAQFVEiD66NB/UNu80/3WVhCtzWw98hcbMa6XBBseIiUG5439lGxhcGlkYXJ5CmJyYXZvCnJlYWwKcGFyZXNpcwpoaWdoYm9ybgpob3JzZQpib3dlbAphc3Npc3QKY29ybmVhCnB5cmUKVU5JRk9STQpza2lpbmcKc3BpcmUKdXBoZWF2ZQpjcnVtcAo=";
+
+    fn car() -> Vec<u8> {
+        BASE64.decode(CAR_BASE64.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn verifies_car_in_chunks() {
+        let mut verifier = CarVerifier::new(ROOT, "", DagScope::All).unwrap();
+        for chunk in car().chunks(7) {
+            verifier.write(chunk).unwrap();
+        }
+        let dag = verifier.finish().unwrap();
+        assert_eq!(
+            dag.report,
+            VerificationReport {
+                root: ROOT.into(),
+                path: "".into(),
+                target: ROOT.into(),
+                dag_scope: "all",
+                block_count: 1,
+                block_bytes: 103,
+                car_bytes: 200,
+                duplicate_blocks: 0,
+            }
+        );
+        let content = dag.read_file().unwrap();
+        assert!(content.starts_with(b"lapidary\nbravo\n"));
+    }
+
+    #[test]
+    fn rejects_tampered_blocks() {
+        let mut car = car();
+        let last = car.len() - 1;
+        car[last] ^= 1;
+        let mut verifier = CarVerifier::new(ROOT, "", DagScope::All).unwrap();
+        let err = verifier.write(&car).unwrap_err();
+        assert!(err.to_string().contains("does not match CID"), "{err:#}");
+    }
+
+    #[test]
+    fn rejects_unexpected_root() {
+        let other = "bafkreibm6jg3ux5qumhcn2b3flc3tyu6dmlb4xa7u5bf44yegnrjhc4yeq";
+        let mut verifier = CarVerifier::new(other, "", DagScope::All).unwrap();
+        let err = verifier.write(&car()).unwrap_err();
+        assert!(err.to_string().contains("requested root"), "{err:#}");
+    }
+
+    #[test]
+    fn rejects_car_over_limit() {
+        let car = car();
+        let mut verifier = CarVerifier::new(ROOT, "", DagScope::All)
+            .unwrap()
+            .with_max_bytes(100);
+        verifier.write(&car[..100]).unwrap();
+        let err = verifier.write(&car[100..]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("larger than the limit of 100 bytes"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_car() {
+        let car = car();
+        let mut verifier = CarVerifier::new(ROOT, "", DagScope::All).unwrap();
+        verifier.write(&car[..car.len() - 10]).unwrap();
+        assert!(verifier.finish().is_err());
+    }
+
+    #[test]
+    fn rejects_paths_in_raw_blocks() {
+        let mut verifier = CarVerifier::new(ROOT, "/some/file", DagScope::All).unwrap();
+        verifier.write(&car()).unwrap();
+        let err = verifier.finish().err().unwrap();
+        assert!(err.to_string().contains("not found"), "{err:#}");
+    }
+}
@@ -1,11 +1,28 @@
 import { core, primordials } from "ext:core/mod.js";
-const { ObjectCreate, ObjectDefineProperties } = primordials;
+const {
+  ArrayPrototypeIncludes,
+  NumberIsFinite,
+  ObjectCreate,
+  ObjectDefineProperties,
+  ObjectEntries,
+  RegExpPrototypeTest,
+  StringPrototypeStartsWith,
+  TypeError,
+} = primordials;
 
-import { op_info_activity, op_error_activity, op_job_completed, op_zinnia_log } from "ext:core/ops";
+import {
+  op_info_activity,
+  op_error_activity,
+  op_job_completed,
+  op_report_metric,
+  op_zinnia_log,
+} from "ext:core/ops";
 
 import { inspect } from "ext:deno_console/01_console.js";
 import { versions } from "ext:zinnia_runtime/01_version.ts";
+import { ipfsApi } from "ext:zinnia_runtime/ipfs.js";
 import * as httpClient from "ext:deno_fetch/22_http_client.js";
+import { telemetry, TRACING_ENABLED } from "ext:deno_telemetry/telemetry.ts";
 
 const zinniaNs = ObjectCreate(null);
 
@@ -15,8 +32,24 @@ ObjectDefineProperties(activityApi, {
   error: core.propReadOnly(reportErrorActivity),
 });
 
+const metricsApi = ObjectCreate(null);
+ObjectDefineProperties(metricsApi, {
+  counter: core.propReadOnly((name, labels) => new Counter(name, labels)),
+  gauge: core.propReadOnly((name, labels) => new Gauge(name, labels)),
+  histogram: core.propReadOnly((name, labels) => new Histogram(name, labels)),
+});
+
+const telemetryApi = ObjectCreate(null);
+ObjectDefineProperties(telemetryApi, {
+  enabled: core.propGetterOnly(() => TRACING_ENABLED),
+  tracer: core.propReadOnly(getTracer),
+});
+
 ObjectDefineProperties(zinniaNs, {
   activity: core.propReadOnly(activityApi),
+  metrics: core.propReadOnly(metricsApi),
+  telemetry: core.propReadOnly(telemetryApi),
+  ipfs: core.propReadOnly(ipfsApi),
   jobCompleted: core.propReadOnly(reportJobCompleted),
   versions: core.propReadOnly(versions),
   inspect: core.propReadOnly(inspect),
@@ -37,6 +70,139 @@ function reportJobCompleted() {
   op_job_completed();
 }
 
+const METRIC_NAME_PATTERN = /^[a-zA-Z_][a-zA-Z0-9_]*$/;
+
+// Labels added by the daemon when exporting the metrics, `__` is reserved for internal use
+const RESERVED_LABELS = ["module", "le"];
+
+// Validate the metric name & labels, return the metric descriptor
+function defineMetric(kind, name, labels = {}) {
+  if (typeof name !== "string" || !RegExpPrototypeTest(METRIC_NAME_PATTERN, name)) {
+    throw new TypeError(
+      `Invalid metric name ${inspect(name)}. ` +
+        "Names can contain only ASCII letters, digits and underscores and must not start with a digit.",
+    );
+  }
+  if (typeof labels !== "object" || labels === null) {
+    throw new TypeError(`Metric labels must be an object, was: ${inspect(labels)}`);
+  }
+
+  const normalized = ObjectCreate(null);
+  for (const { 0: key, 1: value } of ObjectEntries(labels)) {
+    if (!RegExpPrototypeTest(METRIC_NAME_PATTERN, key)) {
+      throw new TypeError(`Invalid label name ${inspect(key)} of metric ${name}.`);
+    }
+    if (ArrayPrototypeIncludes(RESERVED_LABELS, key) || StringPrototypeStartsWith(key, "__")) {
+      throw new TypeError(`Label name ${inspect(key)} of metric ${name} is reserved.`);
+    }
+    normalized[key] = typeof value === "string" ? value : "" + value;
+  }
+
+  return { kind, name, labels: normalized };
+}
+
+function reportMetric({ kind, name, labels }, value) {
+  if (typeof value !== "number" || !NumberIsFinite(value)) {
+    throw new TypeError(`Metric value must be a finite number, was: ${inspect(value)}`);
+  }
+  op_report_metric({ kind, name, labels, value });
+}
+
+class Counter {
+  #metric;
+
+  constructor(name, labels) {
+    this.#metric = defineMetric("counter", name, labels);
+  }
+
+  inc(value = 1) {
+    if (value < 0) throw new TypeError(`Counters cannot decrease, was: ${value}`);
+    reportMetric(this.#metric, value);
+  }
+}
+
+class Gauge {
+  #metric;
+
+  constructor(name, labels) {
+    this.#metric = defineMetric("gauge", name, labels);
+  }
+
+  set(value) {
+    reportMetric(this.#metric, value);
+  }
+}
+
+class Histogram {
+  #metric;
+
+  constructor(name, labels) {
+    this.#metric = defineMetric("histogram", name, labels);
+  }
+
+  observe(value) {
+    reportMetric(this.#metric, value);
+  }
+}
+
+// Spans are exported only when the embedder enabled OpenTelemetry. Otherwise we hand out a tracer
+// that does nothing, so that modules don't have to check `Zinnia.telemetry.enabled`.
+function getTracer(name, version) {
+  if (!TRACING_ENABLED) return noopTracer;
+  return telemetry.tracerProvider.getTracer(name, version);
+}
+
+const INVALID_SPAN_CONTEXT = {
+  traceId: "00000000000000000000000000000000",
+  spanId: "0000000000000000",
+  traceFlags: 0,
+};
+
+class NoopSpan {
+  spanContext() {
+    return INVALID_SPAN_CONTEXT;
+  }
+  setAttribute() {
+    return this;
+  }
+  setAttributes() {
+    return this;
+  }
+  addEvent() {
+    return this;
+  }
+  addLink() {
+    return this;
+  }
+  addLinks() {
+    return this;
+  }
+  setStatus() {
+    return this;
+  }
+  updateName() {
+    return this;
+  }
+  end() {}
+  isRecording() {
+    return false;
+  }
+  recordException() {}
+}
+
+const noopTracer = {
+  startSpan() {
+    return new NoopSpan();
+  },
+  startActiveSpan(_name, ...args) {
+    const fn = args[args.length - 1];
+    if (typeof fn !== "function") {
+      throw new TypeError("startActiveSpan requires a function argument");
+    }
+    return fn(new NoopSpan());
+  },
+};
+
 function log(msg, level) {
   if (typeof msg !== "string") msg = "" + msg;
   op_zinnia_log(msg, level);
@@ -31,6 +31,7 @@ import * as abortSignal from "ext:deno_web/03_abort_signal.js";
 import * as globalInterfaces from "ext:deno_web/04_global_interfaces.js";
 
 import * as fetch from "ext:zinnia_runtime/fetch.js";
+import * as resourceTiming from "ext:zinnia_runtime/resource_timing.js";
 import { zinniaNs, log } from "ext:zinnia_runtime/90_zinnia_apis.js";
 
 // https://developer.mozilla.org/en-US/docs/Web/API/WindowOrWorkerGlobalScope
@@ -66,6 +67,7 @@ const windowOrWorkerGlobalScope = {
   PerformanceEntry: core.propNonEnumerable(performance.PerformanceEntry),
   PerformanceMark: core.propNonEnumerable(performance.PerformanceMark),
   PerformanceMeasure: core.propNonEnumerable(performance.PerformanceMeasure),
+  PerformanceResourceTiming: core.propNonEnumerable(resourceTiming.PerformanceResourceTiming),
   PromiseRejectionEvent: core.propNonEnumerable(event.PromiseRejectionEvent),
   ProgressEvent: core.propNonEnumerable(event.ProgressEvent),
   ReadableStream: core.propNonEnumerable(streams.ReadableStream),
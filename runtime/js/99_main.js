@@ -14,11 +14,13 @@ import {
   op_set_format_exception_callback,
   op_bootstrap_stderr_no_color,
   op_bootstrap_stdout_no_color,
+  op_seeded_math_random,
 } from "ext:core/ops";
 const {
   Error,
   ErrorPrototype,
   ObjectDefineProperties,
+  ObjectDefineProperty,
   ObjectPrototypeIsPrototypeOf,
   ObjectSetPrototypeOf,
   Symbol,
@@ -43,7 +45,9 @@ import {
   mainRuntimeGlobalProperties,
   windowOrWorkerGlobalScope,
 } from "ext:zinnia_runtime/98_global_scope.js";
-import { setLassieConfig } from "ext:zinnia_runtime/fetch.js";
+import { setRetrievalConfig } from "ext:zinnia_runtime/fetch.js";
+import { setNetworkFixtureMode } from "ext:zinnia_runtime/network_fixture.js";
+import { startVirtualClock } from "ext:zinnia_runtime/fake_timers.js";
 
 // deno-lint-ignore prefer-primordials
 if (Symbol.metadata) {
@@ -123,13 +127,36 @@ core.registerErrorBuilder(
   },
 );
 
-function runtimeStart({ zinniaVersion, v8Version, lassieUrl, lassieAuth }) {
+function runtimeStart({
+  zinniaVersion,
+  v8Version,
+  retrievalBackend,
+  retrieval,
+  networkFixture,
+  seededRandom,
+  virtualClockStart,
+}) {
   core.setWasmStreamingCallback(fetch.handleWasmStreaming);
   core.setReportExceptionCallback(event.reportException);
   op_set_format_exception_callback(formatException);
   version.setVersions(zinniaVersion, v8Version);
 
-  setLassieConfig(lassieUrl, lassieAuth);
+  setRetrievalConfig(retrievalBackend, retrieval);
+  setNetworkFixtureMode(networkFixture);
+
+  if (seededRandom) {
+    // Use the random number generator seeded by the embedder to make the runs reproducible
+    ObjectDefineProperty(
+      Math,
+      "random",
+      core.propWritable(function random() {
+        return op_seeded_math_random();
+      }),
+    );
+  }
+  if (virtualClockStart !== null) {
+    startVirtualClock(virtualClockStart);
+  }
 }
 
 let hasBootstrapped = false;
@@ -152,11 +179,8 @@ function bootstrapMainRuntime(runtimeOptions) {
   ObjectDefineProperties(globalThis, mainRuntimeGlobalProperties);
   ObjectSetPrototypeOf(globalThis, Window.prototype);
 
-  bootstrapOtel([
-    0, // tracingEnabled
-    0, // metricsEnabled
-    0, // consoleConfig: ignore
-  ]);
+  // [tracingEnabled, metricsEnabled, consoleConfig, ...propagators]
+  bootstrapOtel(runtimeOptions.otelConfig);
 
   if (runtimeOptions.inspectFlag) {
     core.wrapConsole(globalThis.console, core.v8Console);
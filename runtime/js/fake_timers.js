@@ -0,0 +1,340 @@
+// Fake timers for testing modules built around `setTimeout` and `setInterval` loops, exported by
+// `zinnia:test` as `fakeTimers`
+//
+// While installed, the virtual clock replaces `Date`, the runtime reports the virtual time from
+// `performance.now()` (see `op_virtual_clock_set`) and `deno_web` schedules new timers on the
+// virtual clock, including the timers of `AbortSignal.timeout()` (see `setVirtualClock()` in
+// vendor/deno_web). The time moves forward only when the test advances the clock.
+//
+// `startVirtualClock()` installs the same clock for `zinnia run --seed`. There, each timer runs
+// once its delay passed in real time, and the clock stays at the due time of the last timer.
+
+import { core, primordials } from "ext:core/mod.js";
+import { op_defer, op_virtual_clock_clear, op_virtual_clock_set } from "ext:core/ops";
+const {
+  Date,
+  DatePrototypeToString,
+  Error,
+  MathMax,
+  NumberIsFinite,
+  ObjectCreate,
+  ObjectDefineProperties,
+  ReflectConstruct,
+  SafeMap,
+  TypeError,
+} = primordials;
+import { setVirtualClock } from "ext:deno_web/02_timers.js";
+import { performance } from "ext:deno_web/15_performance.js";
+
+// `runAll()` gives up after running this many timers, e.g. when an interval is never cleared
+const MAX_TIMERS_PER_RUN = 1000;
+
+/**
+ * @typedef {{
+ *   id: number,
+ *   callAt: number,
+ *   seq: number,
+ *   interval: number | undefined,
+ *   refed: boolean,
+ *   callback: () => void,
+ * }} Timer
+ */
+
+/** @type {{
+ *   epoch: number,
+ *   origin: number,
+ *   elapsed: number,
+ *   timers: SafeMap<number, Timer>,
+ *   seq: number,
+ *   RealDate: DateConstructor,
+ *   Date: DateConstructor,
+ *   autoAdvance: boolean,
+ *   driver: number | undefined,
+ *   driverAt: number,
+ *   previous: object | undefined,
+ * } | undefined} */
+let clock;
+
+// Timer IDs start far above the IDs of real timers, so that `clearTimeout()` can tell them apart
+let nextTimerId = 1_000_000_000;
+
+// The timer queue of the virtual clock, `deno_web` schedules new timers here while the clock is
+// installed
+const virtualTimers = {
+  schedule(repeat, timeout, callback) {
+    return schedule(callback, timeout, repeat);
+  },
+  cancel(id) {
+    const found = clock.timers.delete(id);
+    if (found) updateDriver();
+    return found;
+  },
+  ref(id, refed) {
+    const timer = clock.timers.get(id);
+    if (timer === undefined) return false;
+    timer.refed = refed;
+    updateDriver();
+    return true;
+  },
+};
+
+/**
+ * Install the virtual clock. `Date.now()` starts at `now` (the current time by default),
+ * `performance.now()` continues from its current value.
+ *
+ * @param {{now?: number | Date}} [options]
+ */
+function install({ now = globalThis.Date.now() } = {}) {
+  if (clock !== undefined && !clock.autoAdvance) {
+    throw new Error("Fake timers are already installed");
+  }
+  const epoch = typeof now === "number" ? now : new Date(now).getTime();
+  if (!NumberIsFinite(epoch)) {
+    throw new TypeError(`"now" must be a timestamp or a Date, was: ${now}`);
+  }
+  installClock(epoch, performance.now(), false);
+}
+
+/**
+ * Run the module on the virtual clock, starting at `epoch` (milliseconds since the Unix epoch).
+ * The timers run on their own, see `autoAdvance()`. Called when the module starts, for
+ * `zinnia run --seed`.
+ *
+ * @param {number} epoch
+ */
+export function startVirtualClock(epoch) {
+  // `performance.now()` starts at zero too, so that it's the same in every run
+  installClock(epoch, 0, true);
+}
+
+// The fake timers of a test replace the clock of `zinnia run --seed` until they are uninstalled
+function installClock(epoch, origin, autoAdvance) {
+  const previous = clock;
+  if (previous?.driver !== undefined) {
+    core.cancelTimer(previous.driver);
+    previous.driver = undefined;
+  }
+  clock = {
+    epoch,
+    origin,
+    elapsed: 0,
+    timers: new SafeMap(),
+    seq: 0,
+    RealDate: previous?.RealDate ?? globalThis.Date,
+    Date: undefined,
+    autoAdvance,
+    driver: undefined,
+    driverAt: 0,
+    previous,
+  };
+  clock.Date = createDate(clock.RealDate, () => clock.epoch + clock.elapsed);
+  op_virtual_clock_set(origin);
+  setVirtualClock(virtualTimers);
+  globalThis.Date = clock.Date;
+}
+
+/**
+ * Restore the real clock, or the clock of `zinnia run --seed`. Pending fake timers are
+ * discarded. The test runner calls this after each test.
+ */
+export function uninstallFakeTimers() {
+  if (clock === undefined || clock.autoAdvance) return;
+  const { previous } = clock;
+  if (previous === undefined) {
+    globalThis.Date = clock.RealDate;
+    clock = undefined;
+    setVirtualClock(null);
+    op_virtual_clock_clear();
+    return;
+  }
+
+  // The time of the restored clock does not go back
+  previous.elapsed = MathMax(previous.elapsed, clock.origin + clock.elapsed - previous.origin);
+  clock = previous;
+  op_virtual_clock_set(clock.origin + clock.elapsed);
+  globalThis.Date = clock.Date;
+  updateDriver();
+}
+
+function requireClock() {
+  if (clock === undefined || clock.autoAdvance) {
+    throw new Error("Fake timers are not installed, call `fakeTimers.install()` first");
+  }
+  return clock;
+}
+
+function setElapsed(elapsed) {
+  clock.elapsed = elapsed;
+  op_virtual_clock_set(clock.origin + elapsed);
+}
+
+// `Date()`, `new Date()` without arguments and `Date.now()` report the time returned by `now`
+function createDate(RealDate, now) {
+  const FakeDate = function Date(...args) {
+    if (new.target === undefined) {
+      return DatePrototypeToString(new RealDate(now()));
+    }
+    return ReflectConstruct(RealDate, args.length === 0 ? [now()] : args, new.target);
+  };
+  ObjectDefineProperties(FakeDate, {
+    length: { value: RealDate.length },
+    prototype: { value: RealDate.prototype },
+    now: core.propWritable(now),
+    parse: core.propWritable(RealDate.parse),
+    UTC: core.propWritable(RealDate.UTC),
+  });
+  return FakeDate;
+}
+
+// `deno_web` validates the callback and converts the delay to an integer
+function schedule(callback, delay, repeat) {
+  delay = MathMax(0, delay);
+  const timer = {
+    id: nextTimerId++,
+    callAt: clock.elapsed + delay,
+    seq: clock.seq++,
+    // Intervals of 0 ms would keep `advance()` busy forever
+    interval: repeat ? MathMax(1, delay) : undefined,
+    refed: true,
+    callback,
+  };
+  clock.timers.set(timer.id, timer);
+  updateDriver();
+  return timer.id;
+}
+
+// Find the timer to run next: the earliest one, timers due at the same time run in the order in
+// which they were scheduled
+function nextTimer(until) {
+  let next;
+  for (const timer of clock.timers.values()) {
+    if (timer.callAt > until) continue;
+    if (
+      next === undefined ||
+      timer.callAt < next.callAt ||
+      (timer.callAt === next.callAt && timer.seq < next.seq)
+    ) {
+      next = timer;
+    }
+  }
+  return next;
+}
+
+function runTimer(timer) {
+  setElapsed(MathMax(clock.elapsed, timer.callAt));
+  if (timer.interval === undefined) {
+    clock.timers.delete(timer.id);
+  } else {
+    timer.callAt += timer.interval;
+    timer.seq = clock.seq++;
+  }
+  timer.callback();
+}
+
+// With `zinnia run --seed`, a real timer runs the next virtual timer once its delay passed in real
+// time. Timers keep racing with I/O like real timers do, while the time reported by the clock
+// depends only on the timers.
+function updateDriver() {
+  if (!clock.autoAdvance) return;
+  const next = nextTimer(Infinity);
+  if (next === undefined) {
+    if (clock.driver !== undefined) core.cancelTimer(clock.driver);
+    clock.driver = undefined;
+    return;
+  }
+  if (clock.driver === undefined || next.callAt !== clock.driverAt) {
+    if (clock.driver !== undefined) core.cancelTimer(clock.driver);
+    clock.driverAt = next.callAt;
+    clock.driver = core.queueSystemTimer(
+      undefined,
+      false,
+      next.callAt - clock.elapsed,
+      autoAdvance,
+    );
+  }
+  // Like real timers, unrefed timers (e.g. `AbortSignal.timeout()`) don't keep the module running
+  let refed = false;
+  for (const timer of clock.timers.values()) {
+    refed ||= timer.refed;
+  }
+  if (refed) {
+    core.refTimer(clock.driver);
+  } else {
+    core.unrefTimer(clock.driver);
+  }
+}
+
+function autoAdvance() {
+  clock.driver = undefined;
+  const timer = nextTimer(clock.driverAt);
+  try {
+    if (timer !== undefined) runTimer(timer);
+  } finally {
+    updateDriver();
+  }
+}
+
+// Let promises settle and pending ops make progress, e.g. a `fetch` started by a timer.
+// `op_defer` waits for the next turn of the event loop.
+function settle() {
+  return op_defer();
+}
+
+/**
+ * Move the virtual clock forward by `ms` milliseconds, running the timers that become due in the
+ * order of their due time. Promises created by a timer settle before the next timer runs.
+ *
+ * @param {number} ms
+ */
+async function advance(ms) {
+  if (typeof ms !== "number" || !(ms >= 0)) {
+    throw new TypeError(`"ms" must be a non-negative number, was: ${ms}`);
+  }
+  const current = requireClock();
+  const until = current.elapsed + ms;
+  await settle();
+  while (clock === current) {
+    const timer = nextTimer(until);
+    if (timer === undefined) break;
+    runTimer(timer);
+    await settle();
+  }
+  if (clock === current) {
+    setElapsed(until);
+    await settle();
+  }
+}
+
+/**
+ * Run all pending timers, including the timers they schedule, moving the virtual clock forward
+ * to their due time. Throws when the timers keep scheduling new timers, e.g. an interval that is
+ * never cleared.
+ */
+async function runAll() {
+  const current = requireClock();
+  await settle();
+  for (let count = 0; clock === current; count++) {
+    const timer = nextTimer(Infinity);
+    if (timer === undefined) return;
+    if (count >= MAX_TIMERS_PER_RUN) {
+      throw new Error(
+        `Aborting after running ${MAX_TIMERS_PER_RUN} timers, assuming there is an infinite ` +
+          "loop of timers. Use `fakeTimers.advance()` to test intervals.",
+      );
+    }
+    runTimer(timer);
+    await settle();
+  }
+}
+
+const fakeTimers = ObjectCreate(null);
+ObjectDefineProperties(fakeTimers, {
+  install: core.propReadOnly(install),
+  uninstall: core.propReadOnly(uninstallFakeTimers),
+  advance: core.propReadOnly(advance),
+  runAll: core.propReadOnly(runAll),
+  // The number of timers waiting to run
+  pending: core.propGetterOnly(() => requireClock().timers.size),
+});
+
+export { fakeTimers };
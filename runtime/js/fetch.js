@@ -1,44 +1,406 @@
+import {
+  op_fetch_body_ended,
+  op_fetch_direct_client,
+  op_fetch_network_timing,
+  op_fetch_started,
+  op_ipfs_credentials_id,
+  op_ipfs_read_car,
+  op_ipfs_retrieval_finished,
+  op_ipfs_retrievals_paused,
+} from "ext:core/ops";
+import { core, primordials } from "ext:core/mod.js";
+const { PromisePrototypeThen, PromiseReject, SafeFinalizationRegistry, SafeWeakMap } = primordials;
 import { fetch as fetchImpl } from "ext:deno_fetch/26_fetch.js";
-import { fromInnerResponse, toInnerResponse } from "ext:deno_fetch/23_response.js";
+import { InnerBody } from "ext:deno_fetch/22_body.js";
+import { fromInnerResponse, toInnerResponse, Response } from "ext:deno_fetch/23_response.js";
 import { toInnerRequest, fromInnerRequest, Request } from "ext:deno_fetch/23_request.js";
 import { guardFromHeaders } from "ext:deno_fetch/20_headers.js";
 import { byteLowerCase } from "ext:deno_web/00_infra.js";
+import { getReadableStreamResourceBacking, ReadableStream } from "ext:deno_web/06_streams.js";
+import { URL } from "ext:deno_url/00_url.js";
+import { startResourceTiming } from "ext:zinnia_runtime/resource_timing.js";
+import { findFetchMock } from "ext:zinnia_runtime/fetch_mock.js";
+import {
+  getNetworkFixtureMode,
+  recordFetch,
+  replayFetch,
+} from "ext:zinnia_runtime/network_fixture.js";
 
 const ipfsScheme = "ipfs://";
+// The runtime replaces this header with the credentials of the retrieval backend, see
+// `add_retrieval_credentials` in `retrieval_backend.rs`
+const credentialsMarker = "x-zinnia-retrieval-credentials";
+/** @type {string|null} */
+let credentialsId = null;
+
+/**
+ * @typedef {{kind: "http", url: string, authHeader: string|null, lassieParams: boolean}} HttpBackend
+ * @type {HttpBackend | {kind: "carDirectory"} | {kind: "disabled"}}
+ */
+let retrievalBackend = { kind: "disabled" };
 let ipfsBaseUrl = undefined;
 
-/** @type {string|null} */
-let lassieAuth = null;
+/**
+ * @type {{
+ *   maxConcurrentRetrievals: number|null,
+ *   protocols: string,
+ *   providers: string,
+ *   deniedProviders: string[],
+ * }}
+ */
+let retrievalOptions = {
+  maxConcurrentRetrievals: null,
+  protocols: "",
+  providers: "",
+  deniedProviders: [],
+};
+
+export function setRetrievalConfig(
+  /** @type {typeof retrievalBackend} */ backend,
+  /** @type {typeof retrievalOptions} */ retrieval,
+) {
+  retrievalBackend = backend;
+  credentialsId = op_ipfs_credentials_id();
+  ipfsBaseUrl = backend.kind === "http" ? backend.url + "ipfs/" : undefined;
+  retrievalOptions = retrieval;
+}
 
-export function setLassieConfig(/** @type {string} */ url, /** @type {string|null} */ auth) {
-  ipfsBaseUrl = url + "ipfs/";
-  lassieAuth = auth;
+// Limit the number of retrievals running at the same time. Retrievals above the limit wait
+// in a FIFO queue until a running retrieval finishes.
+let runningRetrievals = 0;
+/** @type {(() => void)[]} */
+const retrievalQueue = [];
+
+async function acquireRetrievalSlot(/** @type {AbortSignal} */ signal) {
+  signal.throwIfAborted();
+  const max = retrievalOptions.maxConcurrentRetrievals;
+  if (max === null || runningRetrievals < max) {
+    runningRetrievals++;
+    return;
+  }
+  // The slot is handed over by `releaseRetrievalSlot()`. Requests aborted while waiting leave
+  // the queue.
+  await new Promise((resolve, reject) => {
+    const onAbort = () => {
+      const index = retrievalQueue.indexOf(next);
+      if (index !== -1) retrievalQueue.splice(index, 1);
+      reject(signal.reason);
+    };
+    const next = () => {
+      signal.removeEventListener("abort", onAbort);
+      resolve();
+    };
+    signal.addEventListener("abort", onAbort, { once: true });
+    retrievalQueue.push(next);
+  });
+}
+
+/**
+ * Retrieval metadata of `ipfs://` responses returned by `fetch`, see `getRetrievalStats()`.
+ * @type {SafeWeakMap<Response, {stats: RetrievalStats, finished: Promise<RetrievalStats>}>}
+ */
+const retrievalStats = new SafeWeakMap();
+
+/**
+ * @typedef {{
+ *   url: string,
+ *   backend: string,
+ *   status: number | null,
+ *   startedAt: number,
+ *   ttfbMs: number | null,
+ *   durationMs: number | null,
+ *   bytesReceived: number,
+ *   success: boolean | null,
+ * }} RetrievalStats
+ */
+
+/**
+ * Resolve to the retrieval metadata of an `ipfs://` response after the response body was
+ * consumed, cancelled or failed. Returns `undefined` for other responses.
+ * @param {Response} response
+ * @returns {Promise<RetrievalStats> | undefined}
+ */
+export function getRetrievalStats(response) {
+  return retrievalStats.get(response)?.finished;
+}
+
+function releaseRetrievalSlot() {
+  const next = retrievalQueue.shift();
+  if (next) {
+    next();
+  } else {
+    runningRetrievals--;
+  }
 }
 
 export function fetch(resource, options) {
   let request = new Request(resource, options);
+  if (hasHeader(toInnerRequest(request), credentialsMarker)) {
+    return PromiseReject(new TypeError(`The ${credentialsMarker} header is reserved`));
+  }
   // The `resource` arg can be a string or any other object with a stringifier - including a URL
   // object - that provides the URL of the resource you want to fetch; or a Request object.
   // See https://developer.mozilla.org/en-US/docs/Web/API/fetch#parameters
   // Fortunately, Request's constructor handles the conversions, and Request#url is always a string.
   // See https://developer.mozilla.org/en-US/docs/Web/API/Request/url
+  const mock = findFetchMock(request);
+  if (mock !== undefined) {
+    // Mocked `ipfs://` responses report the retrieval metadata like real retrievals
+    return request.url.startsWith(ipfsScheme) ? fetchFromIpfs(request, mock) : mock(request);
+  }
+  switch (getNetworkFixtureMode()) {
+    case "record":
+      return recordFetch(request, sendRequest, getRetrievalStats);
+    case "replay":
+      return replayRequest(request);
+    default:
+      return sendRequest(request);
+  }
+}
+
+function sendRequest(request) {
   if (request.url.startsWith(ipfsScheme)) {
     return fetchFromIpfs(request);
   } else {
-    return fetchImpl(request);
+    op_fetch_started(false);
+    const timing = startResourceTiming(request.url);
+    return fetchImpl(routeRequest(request)).then(
+      (response) => {
+        timing.responseReceived(response.status, networkTiming(response));
+        whenBodyEnded(response, ({ bytesReceived, encodedBytesReceived }) => {
+          timing.bodyReceived(bytesReceived, encodedBytesReceived);
+          timing.responseEnded();
+        });
+        return response;
+      },
+      (err) => {
+        timing.responseEnded();
+        throw err;
+      },
+    );
+  }
+}
+
+// Respond from the network fixture, `ipfs://` responses come with the recorded retrieval stats
+async function replayRequest(request) {
+  const { response, retrieval } = await replayFetch(request);
+  if (retrieval !== null) {
+    retrievalStats.set(response, { stats: retrieval, finished: Promise.resolve(retrieval) });
   }
+  return response;
 }
 
-async function fetchFromIpfs(request) {
-  // Rewrite request URL to use Lassie
-  request = buildIpfsRequest(request);
+// Requests to hosts excluded from the proxy configured by the embedder, e.g. to the local Lassie
+// daemon, use a direct connection. Other requests go via the proxy.
+function routeRequest(request) {
+  const clientRid = op_fetch_direct_client(new URL(request.url).hostname);
+  if (clientRid !== null) toInnerRequest(request).clientRid = clientRid;
+  return request;
+}
+
+// When `mock` is provided, it responds to the request instead of the retrieval backend
+async function fetchFromIpfs(request, mock = undefined) {
+  const isMocked = mock !== undefined;
+  const pausedReason = isMocked ? "" : op_ipfs_retrievals_paused();
+  if (pausedReason) {
+    throw new TypeError(`IPFS retrievals are paused: ${pausedReason}`);
+  }
+  if (!isMocked && retrievalBackend.kind === "disabled") {
+    throw new TypeError("IPFS retrievals are disabled");
+  }
+
+  const ipfsUrl = request.url;
+  const isHttpBackend = !isMocked && retrievalBackend.kind === "http";
+  if (isHttpBackend) {
+    // Rewrite request URL to use the HTTP backend (e.g. Lassie)
+    request = buildIpfsRequest(request);
+  }
+
+  await acquireRetrievalSlot(request.signal);
+  op_fetch_started(true);
+  const timing = startResourceTiming(ipfsUrl);
+  const started = Date.now();
+  /** @type {RetrievalStats} */
+  const stats = {
+    url: ipfsUrl,
+    backend: isMocked ? "mock" : retrievalBackend.kind,
+    status: null,
+    startedAt: started,
+    ttfbMs: null,
+    durationMs: null,
+    bytesReceived: 0,
+    success: null,
+  };
+  let resolveFinished;
+  const finishedPromise = new Promise((resolve) => (resolveFinished = resolve));
+  const onChunk = (len) => {
+    stats.bytesReceived += len;
+    timing.bodyReceived(len);
+  };
+
+  let finished = false;
+  const reportRetrieval = (success) => {
+    if (finished) return;
+    finished = true;
+    releaseRetrievalSlot();
+    stats.durationMs = Date.now() - started;
+    stats.success = success;
+    op_ipfs_retrieval_finished(success, stats.durationMs);
+    resolveFinished(stats);
+  };
 
   // Call Deno's `fetch` using the rewritten URL to make the actual HTTP request
-  const response = await fetchImpl(request);
+  let response;
+  try {
+    if (isMocked) {
+      response = await mock(request);
+    } else if (isHttpBackend) {
+      response = await fetchImpl(routeRequest(request));
+    } else {
+      response = await readCarFile(request);
+    }
+  } catch (err) {
+    reportRetrieval(false);
+    timing.responseEnded();
+    throw err;
+  }
+
+  // The first byte arrives with the response headers. The retrieval is finished only after we
+  // receive the entire CAR stream.
+  stats.status = response.status;
+  stats.ttfbMs = Date.now() - started;
+  timing.responseReceived(response.status, isHttpBackend ? networkTiming(response) : null);
+  const ok = response.ok;
+  if (!ok) reportRetrieval(false);
+  if (isHttpBackend) {
+    // The runtime reports the end of the body natively, also when the module drops the response
+    // without reading it. The body stream is left untouched.
+    whenBodyEnded(response, ({ bytesReceived, encodedBytesReceived, success }) => {
+      if (ok) stats.bytesReceived = bytesReceived;
+      timing.bodyReceived(bytesReceived, encodedBytesReceived);
+      reportRetrieval(ok && success);
+      timing.responseEnded();
+    });
+  } else if (ok) {
+    const onFinished = (success) => {
+      reportRetrieval(success);
+      timing.responseEnded();
+    };
+    response = trackResponseBody(response, onFinished, onChunk);
+  } else {
+    response = trackResponseBody(response, timing.responseEnded, timing.bodyReceived);
+  }
 
   // Patch the response object to hide the fact that we are calling Lassie
   // We don't want to leak Lassie's URL
-  return patchIpfsResponse(response);
+  if (isHttpBackend) response = patchIpfsResponse(response);
+  retrievalStats.set(response, { stats, finished: finishedPromise });
+  return response;
+}
+
+// Providers are either peer IDs or multiaddrs ending with `/p2p/{peerId}`
+function peerIdFromProvider(/** @type {string} */ provider) {
+  if (!provider.startsWith("/")) return provider;
+  const ix = provider.lastIndexOf("/p2p/");
+  return ix === -1 ? null : provider.slice(ix + "/p2p/".length);
+}
+
+// Serve the request from the CAR directory configured by the embedder, respond like a gateway
+async function readCarFile(request) {
+  const { host: cid, pathname } = new URL(request.url);
+  let response;
+  if (pathname !== "" && pathname !== "/") {
+    response = new Response("The CAR directory does not support paths inside the DAG", {
+      status: 501,
+    });
+  } else {
+    const bytes = await op_ipfs_read_car(cid);
+    response =
+      bytes === null
+        ? new Response(`No CAR file found for ${cid}`, { status: 404 })
+        : new Response(bytes, { headers: { "content-type": "application/vnd.ipld.car" } });
+  }
+
+  const inner = toInnerResponse(response);
+  inner.urlList = [request.url];
+  return fromInnerResponse(inner, guardFromHeaders(response.headers));
+}
+
+// Call `onEnded` after the body of a response returned by Deno's `fetch` was received to the end,
+// failed, or was dropped before it was read to the end. The runtime counts the body bytes
+// natively (see `op_zinnia_fetch_send`), the body stream is left untouched.
+function whenBodyEnded(response, onEnded) {
+  const rid = responseBodyRid(response);
+  if (rid === undefined) {
+    onEnded({ bytesReceived: 0, encodedBytesReceived: 0, success: true });
+    return;
+  }
+  const ended = op_fetch_body_ended(rid);
+  // Don't keep the event loop running when the module never reads the body
+  core.unrefOpPromise(ended);
+  PromisePrototypeThen(ended, onEnded, () =>
+    onEnded({ bytesReceived: 0, encodedBytesReceived: 0, success: false }),
+  );
+}
+
+// The network timing of a response returned by Deno's `fetch`, recorded by the HTTP client (see
+// `op_fetch_network_timing`). Returns `null` when the response has no body to track.
+function networkTiming(response) {
+  const rid = responseBodyRid(response);
+  return rid === undefined ? null : op_fetch_network_timing(rid);
+}
+
+function responseBodyRid(response) {
+  const body = toInnerResponse(response).body;
+  return body === null ? undefined : getReadableStreamResourceBacking(body.stream)?.rid;
+}
+
+// Calls `onFinished(false)` for bodies garbage-collected before they were read to the end, e.g. to
+// release the retrieval slot of a response the module dropped
+const unfinishedBodies = new SafeFinalizationRegistry((onFinished) => onFinished(false));
+
+// Count the response body bytes as they are read by the caller. When `onFinished` is provided, we
+// call it with `true` after the entire body was received or with `false` when the stream failed,
+// was cancelled or garbage-collected. `onChunk` is called with the length of each chunk received.
+function trackResponseBody(response, onFinished = undefined, onChunk = undefined) {
+  const inner = toInnerResponse(response);
+  if (inner.body === null) {
+    onFinished?.(true);
+    return response;
+  }
+
+  const reader = inner.body.stream.getReader();
+  const finish = (success) => {
+    unfinishedBodies.unregister(stream);
+    onFinished?.(success);
+  };
+  const stream = new ReadableStream({
+    async pull(controller) {
+      try {
+        const { done, value } = await reader.read();
+        if (done) {
+          controller.close();
+          finish(true);
+          return;
+        }
+        onChunk?.(value.byteLength);
+        controller.enqueue(value);
+      } catch (err) {
+        finish(false);
+        controller.error(err);
+      }
+    },
+    cancel(reason) {
+      // The caller is not interested in the rest of the body, the retrieval did not finish
+      finish(false);
+      return reader.cancel(reason);
+    },
+  });
+
+  // Whoever reads the body keeps the stream alive
+  if (onFinished) unfinishedBodies.register(stream, onFinished, stream);
+  inner.body = new InnerBody(stream);
+  return fromInnerResponse(inner, guardFromHeaders(response.headers));
 }
 
 // Deno's Fetch Request is a thin immutable wrapper around InnerRequest. In order to modify the
@@ -50,23 +412,71 @@ function buildIpfsRequest(request) {
   inner.urlList = /** @type {(() => string)[]}*/ (inner.urlList).map((urlFn) => {
     const url = urlFn();
     if (!url.startsWith(ipfsScheme)) return urlFn;
-    const newUrl = ipfsBaseUrl + url.slice(ipfsScheme.length);
+    const newUrl = toBackendUrl(url);
     return () => newUrl;
   });
   inner.urlListProcessed = /** @type {string[]} */ (inner.urlListProcessed).map((url) =>
-    url.startsWith(ipfsScheme) ? ipfsBaseUrl + url.slice(ipfsScheme.length) : url,
+    url.startsWith(ipfsScheme) ? toBackendUrl(url) : url,
   );
 
-  if (inner.headerList.some(([name, _value]) => byteLowerCase(name) == "authorization")) {
-    throw new Error("IPFS retrieval requests don't support Authorization header yet");
+  // Other headers are forwarded to the backend as they are. The module cannot send the header
+  // the runtime authenticates itself with, Lassie does not use the `Authorization` header.
+  if (retrievalBackend.lassieParams && hasHeader(inner, "authorization")) {
+    throw new TypeError(
+      "IPFS retrievals via Lassie don't support the Authorization header, " +
+        "Lassie cannot forward credentials to storage providers",
+    );
   }
-  if (lassieAuth) {
-    inner.headerList.push(["authorization", lassieAuth]);
+  const { authHeader } = retrievalBackend;
+  if (authHeader && hasHeader(inner, authHeader)) {
+    throw new TypeError(
+      `IPFS retrievals don't support the ${authHeader} header, ` +
+        "the retrieval backend is authenticated by the runtime",
+    );
+  }
+  if (credentialsId !== null) {
+    inner.headerList.push([credentialsMarker, credentialsId]);
   }
 
   return fromInnerRequest(inner, request.signal, guardFromHeaders(request.headers));
 }
 
+function hasHeader(innerRequest, /** @type {string} */ header) {
+  return innerRequest.headerList.some(([name, _value]) => byteLowerCase(name) == header);
+}
+
+// Rewrite `ipfs://{cid}` URL to the backend URL, apply Lassie retrieval options configured by the
+// embedder. The configured options take precedence over the options specified by the module.
+function toBackendUrl(/** @type {string} */ url) {
+  const backendUrl = ipfsBaseUrl + url.slice(ipfsScheme.length);
+  const { protocols, providers, deniedProviders } = retrievalOptions;
+  if (!retrievalBackend.lassieParams || (!protocols && !providers && !deniedProviders.length)) {
+    return backendUrl;
+  }
+
+  const parsed = new URL(backendUrl);
+  if (protocols) parsed.searchParams.set("protocols", protocols);
+  if (providers) {
+    parsed.searchParams.set("providers", providers);
+  } else if (deniedProviders.length > 0) {
+    // This only filters the requests, Lassie can still use these providers when it finds them
+    // via IPNI
+    const requested = (parsed.searchParams.get("providers") ?? "").split(",").filter(Boolean);
+    const denied = requested.find(isDeniedProvider);
+    if (denied !== undefined) {
+      throw new TypeError(`IPFS retrievals from provider ${denied} are not allowed`);
+    }
+  }
+  return parsed.toString();
+}
+
+function isDeniedProvider(/** @type {string} */ provider) {
+  const peerId = peerIdFromProvider(provider);
+  return retrievalOptions.deniedProviders.some(
+    (denied) => denied === provider || (peerId !== null && peerIdFromProvider(denied) === peerId),
+  );
+}
+
 // Deno's Fetch Response is a thin immutable wrapper around InnerResponse. In order to modify the
 // response URL, we must convert Response to InnerResponse first, make changes on the inner object,
 // and finally convert the InnerResponse back to a new Response instance.
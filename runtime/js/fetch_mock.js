@@ -0,0 +1,209 @@
+// Fetch mocks for testing modules without a real server, exported by `zinnia:test` as `mockFetch`
+
+import { core, primordials } from "ext:core/mod.js";
+const {
+  ArrayPrototypeFilter,
+  ArrayPrototypeJoin,
+  ArrayPrototypeMap,
+  ArrayPrototypePush,
+  ArrayPrototypeSlice,
+  ErrorPrototype,
+  ObjectCreate,
+  ObjectDefineProperties,
+  ObjectFreeze,
+  ObjectPrototypeIsPrototypeOf,
+  PromiseReject,
+  PromiseResolve,
+  RegExpPrototype,
+  RegExpPrototypeTest,
+  StringPrototypeToUpperCase,
+  TypeError,
+} = primordials;
+import { guardFromHeaders } from "ext:deno_fetch/20_headers.js";
+import {
+  fromInnerResponse,
+  ResponsePrototype,
+  toInnerResponse,
+} from "ext:deno_fetch/23_response.js";
+import { URL, URLPrototype } from "ext:deno_url/00_url.js";
+import { URLPattern } from "ext:deno_url/01_urlpattern.js";
+import { AssertionError } from "ext:zinnia_runtime/vendored/asserts.bundle.js";
+
+/**
+ * @typedef {string | URL | RegExp | URLPattern} UrlPattern
+ * @typedef {Response | Error | ((request: Request) => Response | Promise<Response>)} MockResponse
+ * @typedef {(request: Request) => Promise<Response>} Responder
+ * @typedef {{method: string, pattern: UrlPattern, respond: Responder}} Route
+ * @typedef {{method: string, url: string, request: Request}} Call
+ */
+
+/** @type {Route[]} */
+let routes = [];
+/** @type {Call[]} */
+let calls = [];
+
+/**
+ * Register a route handling `fetch` requests with the given method (`*` matches all methods) and
+ * a URL matching the pattern. The pattern is a URL string matched exactly, a RegExp or a
+ * URLPattern. When multiple routes match the request, the route registered last wins.
+ *
+ * @param {string} method
+ * @param {UrlPattern} pattern
+ * @param {MockResponse} response
+ */
+function route(method, pattern, response) {
+  if (typeof method !== "string") {
+    throw new TypeError(`"method" must be a string, was: ${typeof method}`);
+  }
+  ArrayPrototypePush(routes, {
+    method: StringPrototypeToUpperCase(method),
+    pattern: parsePattern(pattern),
+    respond: toResponder(response),
+  });
+}
+
+function parsePattern(pattern) {
+  if (typeof pattern === "string") return new URL(pattern).href;
+  if (ObjectPrototypeIsPrototypeOf(URLPrototype, pattern)) return pattern.href;
+  if (ObjectPrototypeIsPrototypeOf(RegExpPrototype, pattern)) return pattern;
+  if (ObjectPrototypeIsPrototypeOf(URLPattern.prototype, pattern)) return pattern;
+  throw new TypeError(`"pattern" must be a URL string, URL, RegExp or URLPattern`);
+}
+
+function matchesUrl(pattern, url) {
+  if (typeof pattern === "string") return pattern === url;
+  if (ObjectPrototypeIsPrototypeOf(RegExpPrototype, pattern)) {
+    pattern.lastIndex = 0;
+    return RegExpPrototypeTest(pattern, url);
+  }
+  return pattern.test(url);
+}
+
+function toResponder(response) {
+  if (ObjectPrototypeIsPrototypeOf(ResponsePrototype, response)) {
+    // Every call receives a fresh copy of the response
+    return (request) => PromiseResolve(withUrl(response.clone(), request.url));
+  }
+  if (ObjectPrototypeIsPrototypeOf(ErrorPrototype, response)) {
+    return () => PromiseReject(response);
+  }
+  if (typeof response === "function") {
+    return async (request) => {
+      const result = await response(request);
+      if (!ObjectPrototypeIsPrototypeOf(ResponsePrototype, result)) {
+        throw new TypeError(
+          `The fetch mock for ${request.method} ${request.url} must return a Response`,
+        );
+      }
+      return withUrl(result, request.url);
+    };
+  }
+  throw new TypeError(`"response" must be a Response, an Error or a function`);
+}
+
+// Responses created by the handler don't have a URL, report the request URL like `fetch` does
+function withUrl(response, url) {
+  if (response.url !== "") return response;
+  const inner = toInnerResponse(response);
+  inner.urlList = [url];
+  return fromInnerResponse(inner, guardFromHeaders(response.headers));
+}
+
+/**
+ * Return the function responding to the request when fetch mocks are active, i.e. at least one
+ * route was registered. Requests not matching any route are rejected.
+ *
+ * @param {Request} request
+ * @returns {Responder | undefined}
+ */
+export function findFetchMock(request) {
+  if (routes.length === 0) return undefined;
+
+  const { method, url } = request;
+  // Keep a copy of the request, the route handler can consume the body
+  ArrayPrototypePush(calls, ObjectFreeze({ method, url, request: request.clone() }));
+  // The route registered last wins
+  for (let ix = routes.length - 1; ix >= 0; ix--) {
+    const route = routes[ix];
+    if ((route.method === "*" || route.method === method) && matchesUrl(route.pattern, url)) {
+      return route.respond;
+    }
+  }
+  return () => PromiseReject(new TypeError(`No fetch mock matches ${method} ${url}`));
+}
+
+/**
+ * Remove all routes and recorded calls. The test runner calls this after each test.
+ */
+export function resetFetchMock() {
+  routes = [];
+  calls = [];
+}
+
+/**
+ * @param {string} method
+ * @param {UrlPattern} pattern
+ * @returns {Call[]}
+ */
+function callsTo(method, pattern) {
+  method = StringPrototypeToUpperCase(method);
+  pattern = parsePattern(pattern);
+  return ArrayPrototypeFilter(
+    calls,
+    (call) => (method === "*" || call.method === method) && matchesUrl(pattern, call.url),
+  );
+}
+
+/**
+ * Throw an AssertionError when no request (or not exactly `times` requests) matched the method
+ * and the URL pattern.
+ *
+ * @param {string} method
+ * @param {UrlPattern} pattern
+ * @param {{times?: number}} [options]
+ */
+function assertCalled(method, pattern, { times } = {}) {
+  const count = callsTo(method, pattern).length;
+  if (times === undefined ? count > 0 : count === times) return;
+
+  const expected = times === undefined ? "at least once" : `${times} time(s)`;
+  throw new AssertionError(
+    `Expected fetch to be called with ${method} ${pattern} ${expected}, ` +
+      `it was called ${count} time(s).${describeCalls()}`,
+  );
+}
+
+/**
+ * Throw an AssertionError when any request matched the method and the URL pattern.
+ *
+ * @param {string} method
+ * @param {UrlPattern} pattern
+ */
+function assertNotCalled(method, pattern) {
+  assertCalled(method, pattern, { times: 0 });
+}
+
+function describeCalls() {
+  if (calls.length === 0) return "";
+  const lines = ArrayPrototypeMap(calls, ({ method, url }) => `  ${method} ${url}`);
+  return `\nRecorded calls:\n${ArrayPrototypeJoin(lines, "\n")}`;
+}
+
+const mockFetch = ObjectCreate(null);
+ObjectDefineProperties(mockFetch, {
+  route: core.propReadOnly(route),
+  get: core.propReadOnly((pattern, response) => route("GET", pattern, response)),
+  head: core.propReadOnly((pattern, response) => route("HEAD", pattern, response)),
+  post: core.propReadOnly((pattern, response) => route("POST", pattern, response)),
+  put: core.propReadOnly((pattern, response) => route("PUT", pattern, response)),
+  patch: core.propReadOnly((pattern, response) => route("PATCH", pattern, response)),
+  delete: core.propReadOnly((pattern, response) => route("DELETE", pattern, response)),
+  // The calls made since the last reset, in the order they were made
+  calls: core.propGetterOnly(() => ArrayPrototypeSlice(calls)),
+  callsTo: core.propReadOnly(callsTo),
+  assertCalled: core.propReadOnly(assertCalled),
+  assertNotCalled: core.propReadOnly(assertNotCalled),
+  reset: core.propReadOnly(resetFetchMock),
+});
+
+export { mockFetch };
@@ -0,0 +1,163 @@
+import { core, primordials } from "ext:core/mod.js";
+const {
+  ArrayBufferIsView,
+  ArrayBufferPrototype,
+  NumberIsSafeInteger,
+  ObjectCreate,
+  ObjectDefineProperties,
+  ObjectPrototypeIsPrototypeOf,
+  PromiseReject,
+  StringPrototypeIndexOf,
+  StringPrototypeSlice,
+  StringPrototypeStartsWith,
+  TypeError,
+  Uint8Array,
+} = primordials;
+
+import { op_car_verifier_create, op_car_verifier_finish, op_car_verifier_write } from "ext:core/ops";
+import { ResponsePrototype } from "ext:deno_fetch/23_response.js";
+import { fetch, getRetrievalStats } from "ext:zinnia_runtime/fetch.js";
+import { ReadableStreamPrototype } from "ext:deno_web/06_streams.js";
+import { URLSearchParams } from "ext:deno_url/00_url.js";
+
+const ipfsScheme = "ipfs://";
+
+const ipfsApi = ObjectCreate(null);
+ObjectDefineProperties(ipfsApi, {
+  verifyCar: core.propReadOnly(verifyCar),
+  readFile: core.propReadOnly(readFile),
+  retrieve: core.propReadOnly(retrieve),
+  retrievalStats: core.propReadOnly(retrievalStats),
+});
+
+/**
+ * Retrieve the CAR data for an `ipfs://` URL and return it together with the retrieval metadata.
+ *
+ * @param {string | URL | Request} resource
+ * @param {RequestInit} [init]
+ * @returns {Promise<{car: Uint8Array, stats: object}>}
+ */
+async function retrieve(resource, init) {
+  const response = await fetch(resource, init);
+  const stats = getRetrievalStats(response);
+  if (stats === undefined) {
+    await response.body?.cancel();
+    throw new TypeError("Zinnia.ipfs.retrieve() supports only ipfs:// URLs");
+  }
+  const car = new Uint8Array(await response.arrayBuffer());
+  return { car, stats: await stats };
+}
+
+/**
+ * Resolve to the retrieval metadata of a response to an `ipfs://` request, after the response
+ * body was received, cancelled or failed.
+ *
+ * @param {Response} response
+ * @returns {Promise<object>}
+ */
+function retrievalStats(response) {
+  const stats = getRetrievalStats(response);
+  if (stats === undefined) {
+    return PromiseReject(new TypeError("The response is not a response to an ipfs:// request"));
+  }
+  return stats;
+}
+
+/**
+ * Verify a CAR response and return the blocks of the requested DAG.
+ *
+ * @param {Response | ReadableStream<Uint8Array> | Uint8Array | ArrayBuffer} source
+ * @param {{root?: string, path?: string, dagScope?: "all" | "entity" | "block", maxBytes?: number}} [options]
+ * @returns {Promise<{blocks: {cid: string, bytes: Uint8Array}[], report: object}>}
+ */
+async function verifyCar(source, options) {
+  const { blocks, report } = await verify(source, options, false);
+  return { blocks, report };
+}
+
+/**
+ * Verify a CAR response and return the content of the UnixFS file at the requested path.
+ *
+ * @param {Response | ReadableStream<Uint8Array> | Uint8Array | ArrayBuffer} source
+ * @param {{root?: string, path?: string, dagScope?: "all" | "entity" | "block", maxBytes?: number}} [options]
+ * @returns {Promise<{bytes: Uint8Array, report: object}>}
+ */
+async function readFile(source, options) {
+  const { file, report } = await verify(source, options, true);
+  return { bytes: file, report };
+}
+
+async function verify(source, options = {}, returnFile) {
+  const defaults = ObjectPrototypeIsPrototypeOf(ResponsePrototype, source)
+    ? parseIpfsUrl(source.url)
+    : {};
+  const root = options.root ?? defaults.root;
+  if (typeof root !== "string") {
+    throw new TypeError(
+      "The root CID is required when the source is not a response to an ipfs:// request",
+    );
+  }
+  const path = options.path ?? defaults.path ?? "";
+  const dagScope = options.dagScope ?? defaults.dagScope ?? "all";
+  const maxBytes = options.maxBytes ?? null;
+  if (maxBytes !== null && !(NumberIsSafeInteger(maxBytes) && maxBytes > 0)) {
+    throw new TypeError("The maxBytes option must be a positive integer");
+  }
+
+  const rid = op_car_verifier_create(root, path, dagScope, maxBytes);
+  try {
+    for await (const chunk of toChunks(source)) {
+      op_car_verifier_write(rid, chunk);
+    }
+  } catch (err) {
+    core.tryClose(rid);
+    throw err;
+  }
+  // The op consumes the verifier resource
+  return op_car_verifier_finish(rid, returnFile);
+}
+
+async function* toChunks(source) {
+  if (ObjectPrototypeIsPrototypeOf(ResponsePrototype, source)) {
+    if (!source.ok) {
+      throw new TypeError(`Cannot verify a response with status ${source.status}`);
+    }
+    if (source.body === null) return;
+    source = source.body;
+  }
+  if (ObjectPrototypeIsPrototypeOf(ReadableStreamPrototype, source)) {
+    for await (const chunk of source) {
+      if (!ArrayBufferIsView(chunk)) {
+        throw new TypeError("The CAR stream must produce Uint8Array chunks");
+      }
+      yield chunk;
+    }
+  } else if (ArrayBufferIsView(source)) {
+    yield source;
+  } else if (ObjectPrototypeIsPrototypeOf(ArrayBufferPrototype, source)) {
+    yield new Uint8Array(source);
+  } else {
+    throw new TypeError(
+      "The CAR source must be a Response, a ReadableStream, a Uint8Array or an ArrayBuffer",
+    );
+  }
+}
+
+// Extract the root CID, the path and the DAG scope from `ipfs://{cid}/{path}?dag-scope={scope}`
+function parseIpfsUrl(url) {
+  if (!StringPrototypeStartsWith(url, ipfsScheme)) return {};
+  let rest = StringPrototypeSlice(url, ipfsScheme.length);
+  let query = "";
+  const queryStart = StringPrototypeIndexOf(rest, "?");
+  if (queryStart !== -1) {
+    query = StringPrototypeSlice(rest, queryStart + 1);
+    rest = StringPrototypeSlice(rest, 0, queryStart);
+  }
+  const pathStart = StringPrototypeIndexOf(rest, "/");
+  const root = pathStart === -1 ? rest : StringPrototypeSlice(rest, 0, pathStart);
+  const path = pathStart === -1 ? "" : StringPrototypeSlice(rest, pathStart + 1);
+  const dagScope = new URLSearchParams(query).get("dag-scope") ?? undefined;
+  return { root, path, dagScope };
+}
+
+export { ipfsApi };
@@ -0,0 +1,466 @@
+// Record the `fetch` and `WebSocket` traffic of the module into a fixture file and replay it later
+// without accessing the network. The runtime reads and writes the file, see `NetworkFixture`.
+//
+// The fixture entries have the following shape, bodies and binary messages are base64-encoded:
+//
+//   {type: "fetch", request: {method, url, headers, body}, response?: {status, statusText,
+//     url, headers, body}, retrieval?: RetrievalStats, error?: string}
+//   {type: "websocket", url, opened, protocol, extensions, messages: [{direction: "send"|
+//     "receive", binary, data}], close: {code, reason, wasClean, byClient}, error?: string}
+//
+// Credentials in request headers are not recorded.
+
+import { core, primordials } from "ext:core/mod.js";
+import { op_network_fixture_entries, op_network_fixture_record } from "ext:core/ops";
+const {
+  ArrayBufferIsView,
+  ArrayBufferPrototype,
+  ArrayFrom,
+  ArrayPrototypeFindIndex,
+  ArrayPrototypeMap,
+  ArrayPrototypePush,
+  ArrayPrototypeShift,
+  ArrayPrototypeSlice,
+  ArrayPrototypeSplice,
+  JSONStringify,
+  ObjectDefineProperty,
+  ObjectPrototypeIsPrototypeOf,
+  PromiseAll,
+  PromisePrototypeThen,
+  SafeArrayIterator,
+  SafeSet,
+  SetPrototypeHas,
+  String,
+  StringPrototypeToLowerCase,
+  TypeError,
+  Uint8Array,
+  queueMicrotask,
+} = primordials;
+import { forgivingBase64Decode, forgivingBase64Encode } from "ext:deno_web/00_infra.js";
+import { DOMException } from "ext:deno_web/01_dom_exception.js";
+import {
+  CloseEvent,
+  defineEventHandler,
+  ErrorEvent,
+  Event,
+  EventTarget,
+  MessageEvent,
+} from "ext:deno_web/02_event.js";
+import { Blob, BlobPrototype } from "ext:deno_web/09_file.js";
+import { guardFromHeaders } from "ext:deno_fetch/20_headers.js";
+import { fromInnerResponse, toInnerResponse, Response } from "ext:deno_fetch/23_response.js";
+import { URL } from "ext:deno_url/00_url.js";
+import { WebSocket } from "ext:deno_websocket/01_websocket.js";
+
+/** @type {"record" | "replay" | null} */
+let fixtureMode = null;
+
+export function setNetworkFixtureMode(/** @type {typeof fixtureMode} */ mode) {
+  fixtureMode = mode;
+  if (mode === null) return;
+  const webSocketClass = mode === "record" ? RecordingWebSocket : ReplayWebSocket;
+  ObjectDefineProperty(globalThis, "WebSocket", core.propNonEnumerable(webSocketClass));
+}
+
+export function getNetworkFixtureMode() {
+  return fixtureMode;
+}
+
+//
+// Record mode
+//
+
+// Entries are stored in the order the requests were made, not in the order they finished
+let nextEntryIndex = 0;
+
+const redactedHeaders = new SafeSet(["authorization", "cookie", "proxy-authorization"]);
+
+/**
+ * Send the request using `send` and record the request and the response once the response body
+ * was received. The caller receives the response as usual.
+ * @param {Request} request
+ * @param {(request: Request) => Promise<Response>} send
+ * @param {(response: Response) => Promise<object> | undefined} getRetrievalStats
+ */
+export async function recordFetch(request, send, getRetrievalStats) {
+  const index = nextEntryIndex++;
+  // Read the request body from a copy, `send` consumes the original
+  const recordedRequest = describeRequest(request.clone());
+
+  let response;
+  try {
+    response = await send(request);
+  } catch (err) {
+    PromisePrototypeThen(recordedRequest, (request) =>
+      op_network_fixture_record(index, { type: "fetch", request, error: errorMessage(err) }),
+    );
+    throw err;
+  }
+
+  const copy = response.clone();
+  const retrieval = getRetrievalStats(response);
+  PromisePrototypeThen(
+    PromiseAll([recordedRequest, describeResponse(copy), retrieval]),
+    ([request, response, retrieval]) =>
+      op_network_fixture_record(index, { type: "fetch", request, response, retrieval }),
+    // The caller receives the same error when reading the response body
+    (err) =>
+      PromisePrototypeThen(recordedRequest, (request) =>
+        op_network_fixture_record(index, { type: "fetch", request, error: errorMessage(err) }),
+      ),
+  );
+  return response;
+}
+
+async function describeRequest(/** @type {Request} */ request) {
+  return {
+    method: request.method,
+    url: request.url,
+    headers: ArrayPrototypeMap(ArrayFrom(request.headers), ([name, value]) => [
+      name,
+      SetPrototypeHas(redactedHeaders, StringPrototypeToLowerCase(name)) ? "<redacted>" : value,
+    ]),
+    body: request.body === null ? null : encodeBytes(await request.arrayBuffer()),
+  };
+}
+
+async function describeResponse(/** @type {Response} */ response) {
+  const body = await response.arrayBuffer();
+  return {
+    status: response.status,
+    statusText: response.statusText,
+    url: response.url,
+    headers: ArrayFrom(response.headers),
+    body: body.byteLength === 0 ? null : encodeBytes(body),
+  };
+}
+
+class RecordingWebSocket extends WebSocket {
+  #entry;
+  /** @type {Promise<void>[]} */
+  #pendingMessages = [];
+
+  constructor(url, protocols = []) {
+    super(url, protocols);
+    const index = nextEntryIndex++;
+    this.#entry = {
+      type: "websocket",
+      url: this.url,
+      opened: false,
+      protocol: "",
+      extensions: "",
+      messages: [],
+      close: null,
+    };
+
+    this.addEventListener("open", () => {
+      this.#entry.opened = true;
+      this.#entry.protocol = this.protocol;
+      this.#entry.extensions = this.extensions;
+    });
+    this.addEventListener("message", (event) => this.#recordMessage("receive", event.data));
+    this.addEventListener("error", (event) => {
+      this.#entry.error = event.message || "WebSocket error";
+    });
+    this.addEventListener("close", (event) => {
+      this.#entry.close = {
+        code: event.code,
+        reason: event.reason,
+        wasClean: event.wasClean,
+        byClient: this.#entry.close?.byClient ?? false,
+      };
+      PromisePrototypeThen(PromiseAll(this.#pendingMessages), () =>
+        op_network_fixture_record(index, this.#entry),
+      );
+    });
+  }
+
+  send(data) {
+    super.send(data);
+    if (this.readyState === OPEN) this.#recordMessage("send", normalizeMessage(data));
+  }
+
+  close(code = undefined, reason = undefined) {
+    super.close(code, reason);
+    this.#entry.close ??= { byClient: true };
+  }
+
+  #recordMessage(direction, data) {
+    // Keep the order of the messages when reading a Blob
+    const message = { direction, binary: typeof data !== "string", data: null };
+    ArrayPrototypePush(this.#entry.messages, message);
+    const encoded = encodeMessage(data);
+    if (typeof encoded === "string") {
+      message.data = encoded;
+    } else {
+      ArrayPrototypePush(
+        this.#pendingMessages,
+        PromisePrototypeThen(encoded, (data) => (message.data = data)),
+      );
+    }
+  }
+}
+
+// `WebSocket.send()` converts values other than binary data to strings
+function normalizeMessage(data) {
+  const binary =
+    ArrayBufferIsView(data) ||
+    ObjectPrototypeIsPrototypeOf(ArrayBufferPrototype, data) ||
+    ObjectPrototypeIsPrototypeOf(BlobPrototype, data);
+  return binary ? data : String(data);
+}
+
+// Returns a promise for Blob messages
+function encodeMessage(data) {
+  if (typeof data === "string") return data;
+  if (ObjectPrototypeIsPrototypeOf(BlobPrototype, data)) {
+    return PromisePrototypeThen(data.arrayBuffer(), encodeBytes);
+  }
+  return encodeBytes(data);
+}
+
+//
+// Replay mode
+//
+
+/** @type {object[] | undefined} */
+let replayEntries = undefined;
+
+// Remove the first entry matching the predicate from the entries not replayed yet
+function takeEntry(type, predicate) {
+  replayEntries ??= op_network_fixture_entries();
+  const ix = ArrayPrototypeFindIndex(
+    replayEntries,
+    (entry) => entry.type === type && predicate(entry),
+  );
+  return ix === -1 ? undefined : ArrayPrototypeSplice(replayEntries, ix, 1)[0];
+}
+
+/**
+ * Respond to the request from the fixture. Requests are matched by the method, the URL and the
+ * body, each recorded response is used once.
+ * @param {Request} request
+ * @returns {Promise<{response: Response, retrieval: object | null}>}
+ */
+export async function replayFetch(request) {
+  if (request.signal.aborted) throw request.signal.reason;
+
+  const { method, url, body } = await describeRequest(request);
+  const entry = takeEntry(
+    "fetch",
+    ({ request }) => request.method === method && request.url === url && request.body === body,
+  );
+  if (entry === undefined) {
+    throw new TypeError(`The network fixture has no recorded response for ${method} ${url}`);
+  }
+  if (entry.error) {
+    throw new TypeError(entry.error);
+  }
+
+  const recorded = entry.response;
+  const response = new Response(recorded.body === null ? null : decodeBytes(recorded.body), {
+    status: recorded.status,
+    statusText: recorded.statusText,
+    headers: recorded.headers,
+  });
+  const inner = toInnerResponse(response);
+  inner.urlList = recorded.url === url ? [url] : [url, recorded.url];
+  return {
+    response: fromInnerResponse(inner, guardFromHeaders(response.headers)),
+    retrieval: entry.retrieval ?? null,
+  };
+}
+
+const CONNECTING = 0;
+const OPEN = 1;
+const CLOSING = 2;
+const CLOSED = 3;
+
+// Plays back the events of the recorded connection. Messages received after a message sent by the
+// module are delivered only after the module sends the same message.
+class ReplayWebSocket extends EventTarget {
+  #url;
+  #entry;
+  #messages;
+  #readyState = CONNECTING;
+  #binaryType = "blob";
+
+  constructor(url, _protocols = []) {
+    super();
+    let wsUrl;
+    try {
+      wsUrl = new URL(String(url));
+    } catch (err) {
+      throw new DOMException(err.message, "SyntaxError");
+    }
+    if (wsUrl.protocol === "http:") wsUrl.protocol = "ws:";
+    if (wsUrl.protocol === "https:") wsUrl.protocol = "wss:";
+    if (wsUrl.protocol !== "ws:" && wsUrl.protocol !== "wss:") {
+      throw new DOMException(
+        `Only ws & wss schemes are allowed in a WebSocket URL: received ${wsUrl.protocol}`,
+        "SyntaxError",
+      );
+    }
+
+    this.#url = wsUrl.href;
+    this.#entry = takeEntry("websocket", (entry) => entry.url === this.#url);
+    if (this.#entry === undefined) {
+      throw new TypeError(
+        `The network fixture has no recorded WebSocket connection to ${this.#url}`,
+      );
+    }
+    this.#messages = ArrayPrototypeSlice(this.#entry.messages);
+    queueMicrotask(() => this.#open());
+  }
+
+  get url() {
+    return this.#url;
+  }
+  get readyState() {
+    return this.#readyState;
+  }
+  get protocol() {
+    return this.#readyState === CONNECTING ? "" : this.#entry.protocol;
+  }
+  get extensions() {
+    return this.#readyState === CONNECTING ? "" : this.#entry.extensions;
+  }
+  get bufferedAmount() {
+    return 0;
+  }
+  get binaryType() {
+    return this.#binaryType;
+  }
+  set binaryType(value) {
+    if (value === "blob" || value === "arraybuffer") this.#binaryType = value;
+  }
+
+  send(data) {
+    if (this.#readyState === CONNECTING) {
+      throw new DOMException("'readyState' not OPEN", "InvalidStateError");
+    }
+    if (this.#readyState !== OPEN) return;
+
+    data = normalizeMessage(data);
+    const encoded = encodeMessage(data);
+    if (typeof encoded === "string") {
+      this.#expectSent(typeof data !== "string", encoded);
+    } else {
+      PromisePrototypeThen(encoded, (encoded) => {
+        try {
+          this.#expectSent(true, encoded);
+        } catch {
+          // The error was reported via the error event
+        }
+      });
+    }
+  }
+
+  close(code = undefined, reason = undefined) {
+    if (this.#readyState === CLOSING || this.#readyState === CLOSED) return;
+    this.#readyState = CLOSING;
+    const { close } = this.#entry;
+    queueMicrotask(() =>
+      this.#finish({
+        code: close?.byClient ? close.code : (code ?? 1005),
+        reason: close?.byClient ? close.reason : (reason ?? ""),
+        wasClean: true,
+      }),
+    );
+  }
+
+  #open() {
+    if (!this.#entry.opened) {
+      this.#fail(this.#entry.error ?? "WebSocket error", this.#entry.close);
+      return;
+    }
+    if (this.#readyState !== CONNECTING) return;
+    this.#readyState = OPEN;
+    this.dispatchEvent(new Event("open"));
+    this.#deliverReceived();
+  }
+
+  #expectSent(binary, data) {
+    // The module can send the next message before it processed the messages received earlier
+    const ix = ArrayPrototypeFindIndex(this.#messages, (message) => message.direction === "send");
+    const expected = this.#messages[ix];
+    if (expected === undefined || expected.binary !== binary || expected.data !== data) {
+      const message =
+        `The network fixture has no recorded WebSocket message ${JSONStringify(data)} ` +
+        `sent to ${this.#url}`;
+      this.#fail(message, { code: 1006, reason: "", wasClean: false });
+      throw new TypeError(message);
+    }
+    ArrayPrototypeSplice(this.#messages, ix, 1);
+    queueMicrotask(() => this.#deliverReceived());
+  }
+
+  // Deliver the messages received before the module sent the next message
+  #deliverReceived() {
+    while (this.#readyState === OPEN && this.#messages[0]?.direction === "receive") {
+      const { binary, data } = ArrayPrototypeShift(this.#messages);
+      this.dispatchEvent(new MessageEvent("message", { data: this.#decodeMessage(binary, data) }));
+    }
+    if (this.#readyState !== OPEN || this.#messages.length > 0) return;
+
+    // All messages were exchanged, replay the end of the connection unless the module closed it
+    const { close, error } = this.#entry;
+    if (error) {
+      this.#fail(error, close);
+    } else if (close !== null && !close.byClient) {
+      this.#readyState = CLOSING;
+      queueMicrotask(() => this.#finish(close));
+    }
+  }
+
+  #decodeMessage(binary, data) {
+    if (!binary) return data;
+    const bytes = decodeBytes(data);
+    return this.#binaryType === "blob" ? new Blob([bytes]) : bytes.buffer;
+  }
+
+  #fail(message, close) {
+    if (this.#readyState === CLOSED) return;
+    this.dispatchEvent(new ErrorEvent("error", { message }));
+    this.#finish({ code: close?.code ?? 1006, reason: close?.reason ?? "", wasClean: false });
+  }
+
+  #finish({ code, reason, wasClean }) {
+    if (this.#readyState === CLOSED) return;
+    this.#readyState = CLOSED;
+    this.dispatchEvent(new CloseEvent("close", { code, reason, wasClean }));
+  }
+}
+
+for (const [name, value] of new SafeArrayIterator([
+  ["CONNECTING", CONNECTING],
+  ["OPEN", OPEN],
+  ["CLOSING", CLOSING],
+  ["CLOSED", CLOSED],
+])) {
+  ObjectDefineProperty(ReplayWebSocket, name, { value, enumerable: true });
+  ObjectDefineProperty(ReplayWebSocket.prototype, name, { value, enumerable: true });
+}
+defineEventHandler(ReplayWebSocket.prototype, "message");
+defineEventHandler(ReplayWebSocket.prototype, "error");
+defineEventHandler(ReplayWebSocket.prototype, "close");
+defineEventHandler(ReplayWebSocket.prototype, "open");
+
+//
+// Helpers
+//
+
+function encodeBytes(/** @type {ArrayBuffer | ArrayBufferView} */ data) {
+  const bytes = ArrayBufferIsView(data)
+    ? new Uint8Array(data.buffer, data.byteOffset, data.byteLength)
+    : new Uint8Array(data);
+  return forgivingBase64Encode(bytes);
+}
+
+function decodeBytes(/** @type {string} */ data) {
+  return forgivingBase64Decode(data);
+}
+
+function errorMessage(err) {
+  return err?.message ?? String(err);
+}
@@ -0,0 +1,352 @@
+// Resource Timing for `fetch`, see https://w3c.github.io/resource-timing/
+//
+// Deno's Performance implementation keeps its entry buffer private, we keep resource entries in
+// our own buffer and extend `performance.getEntries*()` to include them.
+//
+// Timestamps come from `performance.now()`, which is coarse because `allow_hrtime` is disabled.
+// The HTTP client reports when the DNS lookup, TCP connect and TLS handshake of the connection
+// happened (see `op_fetch_network_timing`). Phases of connections established before the fetch
+// started are reported as `fetchStart`, like the spec does for reused connections. Timings and
+// sizes that are not available (e.g. for IPFS retrievals not made over HTTP) are 0.
+//
+// Entries are added when the response headers arrive, `responseEnd` and the body sizes are
+// updated when the body ends. This way responses with bodies the module never reads are recorded
+// too. Failed requests are recorded when they fail, with `responseStart` and `responseStatus` 0.
+
+import { core, primordials } from "ext:core/mod.js";
+const {
+  ArrayPrototypeFilter,
+  ArrayPrototypePush,
+  ArrayPrototypeSort,
+  FunctionPrototypeCall,
+  MathMax,
+  ObjectDefineProperties,
+  ObjectPrototypeIsPrototypeOf,
+  ObjectSetPrototypeOf,
+  Symbol,
+  SymbolFor,
+} = primordials;
+
+import * as webidl from "ext:deno_webidl/00_webidl.js";
+import { createFilteredInspectProxy } from "ext:deno_console/01_console.js";
+import { Performance, PerformanceEntry, performance } from "ext:deno_web/15_performance.js";
+
+// The default buffer size recommended by the spec
+const DEFAULT_BUFFER_SIZE = 250;
+
+const ENTRY_KEYS = [
+  "name",
+  "entryType",
+  "startTime",
+  "duration",
+  "initiatorType",
+  "nextHopProtocol",
+  "workerStart",
+  "redirectStart",
+  "redirectEnd",
+  "fetchStart",
+  "domainLookupStart",
+  "domainLookupEnd",
+  "connectStart",
+  "connectEnd",
+  "secureConnectionStart",
+  "requestStart",
+  "responseStart",
+  "responseEnd",
+  "transferSize",
+  "encodedBodySize",
+  "decodedBodySize",
+  "responseStatus",
+];
+
+const illegalConstructorKey = Symbol("illegalConstructorKey");
+const _name = Symbol("[[name]]");
+const _timing = Symbol("[[timing]]");
+
+/** @type {PerformanceResourceTiming[]} */
+let resourceEntries = [];
+let bufferSize = DEFAULT_BUFFER_SIZE;
+
+class PerformanceResourceTiming {
+  [_name] = "";
+  /** @type {ResourceTimingInfo} */
+  [_timing];
+
+  constructor(name = null, timing = null, key = undefined) {
+    if (key !== illegalConstructorKey) {
+      webidl.illegalConstructor();
+    }
+    this[webidl.brand] = webidl.brand;
+    this[_name] = name;
+    this[_timing] = timing;
+  }
+
+  get name() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_name];
+  }
+  get entryType() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return "resource";
+  }
+  get startTime() {
+    return this.fetchStart;
+  }
+  get duration() {
+    return this.responseEnd - this.fetchStart;
+  }
+  get initiatorType() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return "fetch";
+  }
+  get nextHopProtocol() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].nextHopProtocol;
+  }
+  get workerStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return 0;
+  }
+  get redirectStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return 0;
+  }
+  get redirectEnd() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return 0;
+  }
+  get fetchStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].fetchStart;
+  }
+  get domainLookupStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].domainLookupStart;
+  }
+  get domainLookupEnd() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].domainLookupEnd;
+  }
+  get connectStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].connectStart;
+  }
+  get connectEnd() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].connectEnd;
+  }
+  get secureConnectionStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].secureConnectionStart;
+  }
+  get requestStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].requestStart;
+  }
+  get responseStart() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].responseStart;
+  }
+  get responseEnd() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].responseEnd;
+  }
+  // The size of HTTP/2 response headers is not known, they are compressed
+  get transferSize() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    const { headSize, encodedBodySize } = this[_timing];
+    return headSize === 0 ? 0 : headSize + encodedBodySize;
+  }
+  get encodedBodySize() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].encodedBodySize;
+  }
+  get decodedBodySize() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].bodySize;
+  }
+  get responseStatus() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    return this[_timing].responseStatus;
+  }
+
+  toJSON() {
+    webidl.assertBranded(this, PerformanceResourceTimingPrototype);
+    const json = {};
+    for (const key of ENTRY_KEYS) json[key] = this[key];
+    return json;
+  }
+
+  [SymbolFor("Deno.privateCustomInspect")](inspect, inspectOptions) {
+    return inspect(
+      createFilteredInspectProxy({
+        object: this,
+        evaluate: ObjectPrototypeIsPrototypeOf(PerformanceResourceTimingPrototype, this),
+        keys: ENTRY_KEYS,
+      }),
+      inspectOptions,
+    );
+  }
+}
+// PerformanceResourceTiming extends PerformanceEntry, but PerformanceEntry's constructor cannot
+// be called from outside of Deno's module
+ObjectSetPrototypeOf(PerformanceResourceTiming.prototype, PerformanceEntry.prototype);
+ObjectSetPrototypeOf(PerformanceResourceTiming, PerformanceEntry);
+webidl.configureInterface(PerformanceResourceTiming);
+const PerformanceResourceTimingPrototype = PerformanceResourceTiming.prototype;
+
+/**
+ * @typedef {object} ResourceTimingInfo
+ * @property {string} nextHopProtocol
+ * @property {number} fetchStart
+ * @property {number} domainLookupStart
+ * @property {number} domainLookupEnd
+ * @property {number} connectStart
+ * @property {number} connectEnd
+ * @property {number} secureConnectionStart
+ * @property {number} requestStart
+ * @property {number} responseStart
+ * @property {number} responseEnd
+ * @property {number} headSize
+ * @property {number} encodedBodySize
+ * @property {number} bodySize
+ * @property {number} responseStatus
+ */
+
+/**
+ * The network timing reported by `op_fetch_network_timing`
+ * @typedef {object} NetworkTiming
+ * @property {string} nextHopProtocol
+ * @property {number} headSize
+ * @property {ConnectionPhases | null} connection
+ */
+
+/**
+ * How many milliseconds before the call of `op_fetch_network_timing` the phases happened
+ * @typedef {object} ConnectionPhases
+ * @property {number} domainLookupStart
+ * @property {number} domainLookupEnd
+ * @property {number} connectStart
+ * @property {number | null} secureConnectionStart
+ * @property {number} connectEnd
+ */
+
+/**
+ * Start measuring a fetch of the given URL. Call the returned functions when the response
+ * headers arrive, when a body chunk is received and when the response ends or the request fails.
+ */
+function startResourceTiming(/** @type {string} */ url) {
+  const fetchStart = performance.now();
+  /** @type {ResourceTimingInfo} */
+  const timing = {
+    nextHopProtocol: "",
+    fetchStart,
+    domainLookupStart: 0,
+    domainLookupEnd: 0,
+    connectStart: 0,
+    connectEnd: 0,
+    secureConnectionStart: 0,
+    requestStart: 0,
+    responseStart: 0,
+    responseEnd: 0,
+    headSize: 0,
+    encodedBodySize: 0,
+    bodySize: 0,
+    responseStatus: 0,
+  };
+  let recorded = false;
+  let ended = false;
+  const record = () => {
+    if (recorded) return;
+    recorded = true;
+    // When the buffer is full, the spec drops new entries
+    if (resourceEntries.length >= bufferSize) return;
+    ArrayPrototypePush(
+      resourceEntries,
+      new PerformanceResourceTiming(url, timing, illegalConstructorKey),
+    );
+  };
+  return {
+    responseReceived(/** @type {number} */ status, /** @type {NetworkTiming?} */ network = null) {
+      timing.responseStart = performance.now();
+      timing.responseEnd = timing.responseStart;
+      timing.responseStatus = status;
+      if (network !== null) recordNetworkTiming(timing, network);
+      record();
+    },
+    bodyReceived(/** @type {number} */ len, /** @type {number} */ encodedLen = 0) {
+      timing.bodySize += len;
+      timing.encodedBodySize += encodedLen;
+    },
+    // Called without `responseReceived()` when the request failed
+    responseEnded() {
+      if (ended) return;
+      ended = true;
+      timing.responseEnd = performance.now();
+      record();
+    },
+  };
+}
+
+function recordNetworkTiming(
+  /** @type {ResourceTimingInfo} */ timing,
+  /** @type {NetworkTiming} */ { nextHopProtocol, headSize, connection },
+) {
+  timing.nextHopProtocol = nextHopProtocol;
+  timing.headSize = headSize;
+  if (connection === null) return;
+  // The connection may have been established before this fetch started
+  const at = (msAgo) => MathMax(timing.responseStart - msAgo, timing.fetchStart);
+  timing.domainLookupStart = at(connection.domainLookupStart);
+  timing.domainLookupEnd = at(connection.domainLookupEnd);
+  timing.connectStart = at(connection.connectStart);
+  if (connection.secureConnectionStart !== null) {
+    timing.secureConnectionStart = at(connection.secureConnectionStart);
+  }
+  timing.connectEnd = at(connection.connectEnd);
+  // The request is sent as soon as the connection is ready
+  timing.requestStart = timing.connectEnd;
+}
+
+// Extend Deno's Performance with resource entries and the buffer management methods
+const { getEntries, getEntriesByName, getEntriesByType } = Performance.prototype;
+
+function mergeEntries(entries, resources) {
+  if (resources.length === 0) return entries;
+  return ArrayPrototypeSort([...entries, ...resources], (a, b) => a.startTime - b.startTime);
+}
+
+ObjectDefineProperties(Performance.prototype, {
+  getEntries: core.propNonEnumerable(function getEntriesWithResources() {
+    return mergeEntries(FunctionPrototypeCall(getEntries, this), resourceEntries);
+  }),
+  getEntriesByName: core.propNonEnumerable(function getEntriesByNameWithResources(
+    name,
+    type = undefined,
+  ) {
+    const entries = FunctionPrototypeCall(getEntriesByName, this, name, type);
+    const resources = ArrayPrototypeFilter(
+      resourceEntries,
+      (entry) => entry.name === `${name}` && (type === undefined || `${type}` === "resource"),
+    );
+    return mergeEntries(entries, resources);
+  }),
+  getEntriesByType: core.propNonEnumerable(function getEntriesByTypeWithResources(type) {
+    const entries = FunctionPrototypeCall(getEntriesByType, this, type);
+    return `${type}` === "resource" ? [...resourceEntries] : entries;
+  }),
+  clearResourceTimings: core.propNonEnumerable(function clearResourceTimings() {
+    webidl.assertBranded(this, Performance.prototype);
+    resourceEntries = [];
+  }),
+  setResourceTimingBufferSize: core.propNonEnumerable(function setResourceTimingBufferSize(
+    maxSize,
+  ) {
+    webidl.assertBranded(this, Performance.prototype);
+    const prefix = "Failed to execute 'setResourceTimingBufferSize' on 'Performance'";
+    webidl.requiredArguments(arguments.length, 1, prefix);
+    bufferSize = webidl.converters["unsigned long"](maxSize, prefix, "Argument 1");
+  }),
+});
+
+export { PerformanceResourceTiming, startResourceTiming };
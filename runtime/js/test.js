@@ -1,7 +1,14 @@
 // A minimalistic test framework for testing Zinnia modules
 // Inspired by `node:test`, `Deno.test`, `mocha` and others
 
+import { primordials } from "ext:core/mod.js";
+const { DateNow } = primordials;
+import { setTimeout } from "ext:deno_web/02_timers.js";
 import { DenoCore, format_test_error } from "ext:zinnia_runtime/internals.js";
+import { fakeTimers, uninstallFakeTimers } from "ext:zinnia_runtime/fake_timers.js";
+import { mockFetch, resetFetchMock } from "ext:zinnia_runtime/fetch_mock.js";
+
+export { fakeTimers, mockFetch };
 
 /** @type {{
    pendingTests: TestCase[];
@@ -17,7 +24,8 @@ let globalRoot;
 function getGlobalRoot() {
   if (!globalRoot) {
     globalRoot = {
-      started: Date.now(),
+      // Use the real clock and timers, tests can install fake timers
+      started: DateNow(),
       pendingTests: [],
       result: {
         passed: 0,
@@ -51,7 +59,7 @@ class TestCase {
   }
 
   async execute() {
-    this.started = Date.now();
+    this.started = DateNow();
     let maybePromise;
     try {
       maybePromise = this.fn();
@@ -80,7 +88,7 @@ class TestCase {
       globalRoot.lastReportedTest = this;
     }
 
-    let duration = Date.now() - this.started;
+    let duration = DateNow() - this.started;
     console.log(
       "  %s %s %s",
       passed ? green("\u2714" /* check mark */) : red("\u2716" /* heavy multiplication */),
@@ -130,7 +138,12 @@ function runNextTest() {
   const nextTest = globalRoot.pendingTests.shift();
 
   if (nextTest) {
-    nextTest.execute(nextTest).then(runNextTest);
+    nextTest.execute(nextTest).then(() => {
+      // Fetch mocks and fake timers installed by the test don't leak to the next test
+      resetFetchMock();
+      uninstallFakeTimers();
+      runNextTest();
+    });
     return;
   }
 
@@ -138,7 +151,7 @@ function runNextTest() {
 }
 
 function reportTestResults() {
-  let duration = Date.now() - globalRoot.started;
+  let duration = DateNow() - globalRoot.started;
   let failed = globalRoot.result.failed.length;
   let passed = globalRoot.result.passed;
 
@@ -15,11 +15,31 @@ pub use deno_core::anyhow;
 pub use deno_core::error::CoreError;
 pub use deno_core::resolve_path;
 
+mod activity_throttle;
 mod console_reporter;
+mod ipfs;
+mod metrics;
+mod network_fixture;
+mod proxy_config;
 mod reporter;
+mod retrieval_backend;
+mod retrieval_config;
+mod retrieval_gate;
+mod telemetry;
+mod tls_config;
+pub use activity_throttle::*;
 pub use console_reporter::*;
+pub use metrics::*;
+pub use network_fixture::NetworkFixture;
+pub use proxy_config::ProxyConfig;
 pub use reporter::*;
+pub use retrieval_backend::*;
+pub use retrieval_config::{RetrievalConfig, RETRIEVAL_PROTOCOLS};
+pub use retrieval_gate::RetrievalGate;
+pub use telemetry::{flush_telemetry, TelemetryOptions};
+pub use tls_config::{ClientCertConfig, TlsConfig};
 
+#[cfg(feature = "lassie")]
 pub use lassie;
 
 mod ext;
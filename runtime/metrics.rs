@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A monotonically increasing counter.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down. The value is stored as bits of `f64`.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: f64) {
+        // `fetch_update` returns Err only when the closure returns None, which never happens here
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Default histogram buckets (in seconds) for measuring durations of network operations.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// A histogram with fixed bucket boundaries.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    // Non-cumulative count of observations in each bucket, the last item is the `+Inf` bucket
+    buckets: Vec<AtomicU64>,
+    sum: Gauge,
+    count: Counter,
+}
+
+impl Histogram {
+    /// Create a new histogram. `bounds` are the upper bounds of the buckets (inclusive),
+    /// the `+Inf` bucket is added automatically.
+    pub fn new(bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.retain(|b| b.is_finite());
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Self {
+            bounds,
+            buckets,
+            sum: Gauge::default(),
+            count: Counter::default(),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let ix = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        self.buckets[ix].fetch_add(1, Ordering::Relaxed);
+        self.sum.add(value);
+        self.count.inc();
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    /// Cumulative bucket counts as `(upper_bound, count)` pairs, including the `+Inf` bucket.
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(self.buckets.iter())
+            .map(|(bound, count)| {
+                total += count.load(Ordering::Relaxed);
+                (bound, total)
+            })
+            .collect()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum.get()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.get()
+    }
+}
+
+/// Metrics describing the runtime and the platform APIs used by the module.
+///
+/// The embedder can share the same instance with other threads (e.g. an HTTP server exposing
+/// the metrics) by cloning the `Arc` stored in [`crate::BootstrapOptions`].
+#[derive(Debug)]
+pub struct RuntimeMetrics {
+    /// Number of HTTP(S) requests made via `fetch`
+    pub http_requests: Counter,
+    /// Number of IPFS retrieval requests made via `fetch("ipfs://...")`
+    pub ipfs_requests: Counter,
+    /// Number of response body bytes received from the network by `fetch`, including IPFS
+    /// retrievals via HTTP backends
+    pub fetch_bytes_received: Counter,
+    /// Number of IPFS retrievals that finished successfully
+    pub ipfs_retrievals_succeeded: Counter,
+    /// Number of IPFS retrievals that failed
+    pub ipfs_retrievals_failed: Counter,
+    /// How long it took to retrieve the full response body of IPFS requests
+    pub ipfs_retrieval_duration: Histogram,
+    /// V8 heap usage as reported by the last heap statistics sample
+    pub heap_used_bytes: Gauge,
+    /// V8 heap size as reported by the last heap statistics sample
+    pub heap_total_bytes: Gauge,
+    /// How late the last event-loop sampling timer fired, in seconds
+    pub event_loop_lag_seconds: Gauge,
+}
+
+impl Default for RuntimeMetrics {
+    fn default() -> Self {
+        Self {
+            http_requests: Counter::default(),
+            ipfs_requests: Counter::default(),
+            fetch_bytes_received: Counter::default(),
+            ipfs_retrievals_succeeded: Counter::default(),
+            ipfs_retrievals_failed: Counter::default(),
+            ipfs_retrieval_duration: Histogram::new(DURATION_BUCKETS),
+            heap_used_bytes: Gauge::default(),
+            heap_total_bytes: Gauge::default(),
+            event_loop_lag_seconds: Gauge::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn histogram_reports_cumulative_buckets() {
+        let histogram = Histogram::new(&[1.0, 0.5, 1.0]);
+        histogram.observe(0.2);
+        histogram.observe(0.5);
+        histogram.observe(0.7);
+        histogram.observe(3.0);
+
+        assert_eq!(
+            histogram.cumulative_buckets(),
+            vec![(0.5, 2), (1.0, 3), (f64::INFINITY, 4)]
+        );
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum(), 4.4);
+    }
+
+    #[test]
+    fn gauge_supports_fractions() {
+        let gauge = Gauge::default();
+        gauge.set(1.5);
+        gauge.add(-0.25);
+        assert_eq!(gauge.get(), 1.25);
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+
+use deno_core::anyhow::{bail, Context, Result};
+use deno_core::serde_json;
+use serde::{Deserialize, Serialize};
+
+/// Version of the fixture file format written by the runtime.
+const FIXTURE_VERSION: u32 = 1;
+
+/// Record the network traffic of the module into a fixture file or replay it from the file,
+/// e.g. to test a module without depending on live services.
+///
+/// The fixture includes `fetch` requests and responses (`ipfs://` retrievals are recorded at the
+/// `ipfs://` URL) and the messages exchanged over `WebSocket` connections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkFixture {
+    /// Record the traffic into the file. The file is written when the module finishes, also
+    /// when the module fails.
+    Record(PathBuf),
+
+    /// Respond to `fetch` requests and `WebSocket` connections using the traffic recorded in the
+    /// file, without accessing the network. Requests not found in the file fail.
+    Replay(PathBuf),
+}
+
+impl NetworkFixture {
+    pub fn path(&self) -> &Path {
+        match self {
+            NetworkFixture::Record(path) | NetworkFixture::Replay(path) => path,
+        }
+    }
+
+    // The mode in the format expected by `network_fixture.js`
+    pub(crate) fn mode(&self) -> &'static str {
+        match self {
+            NetworkFixture::Record(_) => "record",
+            NetworkFixture::Replay(_) => "replay",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FixtureFile {
+    version: u32,
+    entries: Vec<serde_json::Value>,
+}
+
+/// Entries loaded from the fixture file or recorded by the module. The format of the entries is
+/// owned by `network_fixture.js`.
+pub(crate) struct FixtureState {
+    pub fixture: NetworkFixture,
+    pub entries: Vec<serde_json::Value>,
+}
+
+impl FixtureState {
+    pub fn load(fixture: &NetworkFixture) -> Result<Self> {
+        let path = fixture.path();
+        let entries = match fixture {
+            NetworkFixture::Record(_) => {
+                // Find out that the file cannot be written before running the module
+                std::fs::write(path, fixture_json(&[])?).with_context(|| {
+                    format!("Cannot write the network fixture {}", path.display())
+                })?;
+                vec![]
+            }
+            NetworkFixture::Replay(_) => {
+                let data = std::fs::read_to_string(path).with_context(|| {
+                    format!("Cannot read the network fixture {}", path.display())
+                })?;
+                let file: FixtureFile = serde_json::from_str(&data)
+                    .with_context(|| format!("Invalid network fixture {}", path.display()))?;
+                if file.version != FIXTURE_VERSION {
+                    bail!(
+                        "Unsupported version {} of the network fixture {}, expected {}",
+                        file.version,
+                        path.display(),
+                        FIXTURE_VERSION
+                    );
+                }
+                file.entries
+            }
+        };
+
+        Ok(Self {
+            fixture: fixture.clone(),
+            entries,
+        })
+    }
+
+    /// Store the entry at the given position. The module assigns positions in the order the
+    /// requests were made, the entries are recorded when the responses finish.
+    pub fn record(&mut self, index: usize, entry: serde_json::Value) {
+        if self.entries.len() <= index {
+            self.entries.resize(index + 1, serde_json::Value::Null);
+        }
+        self.entries[index] = entry;
+    }
+
+    /// Write the recorded entries to the fixture file. This is a no-op in the replay mode.
+    pub fn save(&self) -> Result<()> {
+        let NetworkFixture::Record(path) = &self.fixture else {
+            return Ok(());
+        };
+        // Requests that did not finish before the module exited are not recorded
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| !entry.is_null())
+            .cloned()
+            .collect();
+        std::fs::write(path, fixture_json(&entries)?)
+            .with_context(|| format!("Cannot write the network fixture {}", path.display()))
+    }
+}
+
+fn fixture_json(entries: &[serde_json::Value]) -> Result<String> {
+    let file = FixtureFile {
+        version: FIXTURE_VERSION,
+        entries: entries.to_vec(),
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn saves_and_loads_entries() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.child("fixture.json").to_path_buf();
+
+        let mut recorder = FixtureState::load(&NetworkFixture::Record(path.clone())).unwrap();
+        recorder.record(1, serde_json::json!({ "type": "fetch", "id": 2 }));
+        recorder.record(0, serde_json::json!({ "type": "fetch", "id": 1 }));
+        recorder.record(3, serde_json::json!({ "type": "websocket", "id": 3 }));
+        recorder.save().unwrap();
+
+        let replay = FixtureState::load(&NetworkFixture::Replay(path)).unwrap();
+        let ids: Vec<_> = replay
+            .entries
+            .iter()
+            .map(|entry| entry["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_invalid_fixtures() {
+        let dir = assert_fs::TempDir::new().unwrap();
+
+        let err = FixtureState::load(&NetworkFixture::Replay(
+            dir.child("missing.json").to_path_buf(),
+        ))
+        .err()
+        .unwrap();
+        assert!(
+            format!("{err:#}").contains("Cannot read the network fixture"),
+            "{err:#}"
+        );
+
+        let file = dir.child("invalid.json");
+        file.write_str(r#"{"entries": "none"}"#).unwrap();
+        let err = FixtureState::load(&NetworkFixture::Replay(file.to_path_buf()))
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Invalid network fixture"),
+            "{err:#}"
+        );
+
+        file.write_str(r#"{"version": 2, "entries": []}"#).unwrap();
+        let err = FixtureState::load(&NetworkFixture::Replay(file.to_path_buf()))
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Unsupported version"),
+            "{err:#}"
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
+use deno_core::ModuleSpecifier;
+use serde::Deserialize;
 use serde_repr::Deserialize_repr;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize_repr)]
@@ -47,6 +50,57 @@ impl From<i32> for LogLevel {
     }
 }
 
+/// The type of a custom metric defined by the module via `Zinnia.metrics` APIs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// A value that only goes up, the update value is the increment.
+    Counter,
+    /// A value that can go up and down, the update value is the new value.
+    Gauge,
+    /// A distribution of observed values, the update value is the new observation.
+    Histogram,
+}
+
+impl Display for MetricKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        };
+        f.write_str(str)
+    }
+}
+
+/// An update of a custom metric reported by the module.
+///
+/// The runtime validates that `name` and label names contain only ASCII letters, digits and
+/// underscores and do not start with a digit. Counter increments are never negative.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct MetricUpdate {
+    pub kind: MetricKind,
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+impl Display for MetricUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.name)?;
+        if !self.labels.is_empty() {
+            let labels = self
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{k}={v:?}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            write!(f, "{{{labels}}}")?;
+        }
+        write!(f, " {}", self.value)
+    }
+}
+
 // Report events, activities and messages from the running module
 pub trait Reporter {
     /// Print a debug log message. This is typically triggered by Console APIs like `console.log`.
@@ -63,6 +117,23 @@ pub trait Reporter {
 
     /// Report that module completed another job.
     fn job_completed(&self);
+
+    /// Report an update of a custom metric defined by the module.
+    /// The default implementation does nothing.
+    fn metric(&self, _update: &MetricUpdate) {}
+
+    /// Report that the main module and all its dependencies were loaded and compiled.
+    /// The default implementation does nothing.
+    fn module_loaded(&self, _specifier: &ModuleSpecifier) {}
+
+    /// Report that the runtime started evaluating the main module.
+    /// The default implementation does nothing.
+    fn module_started(&self) {}
+
+    /// Report the activities held back by rate limiting once their interval has elapsed,
+    /// e.g. the number of repetitions of the last message. Call this periodically.
+    /// The default implementation does nothing.
+    fn flush_activities(&self) {}
 }
 
 /// Reporter that collects all recorded events, useful for testing.
@@ -108,4 +179,9 @@ impl Reporter for RecordingReporter {
         println!("JOB-COMPLETED");
         self.record("JOB-COMPLETED".into());
     }
+
+    fn metric(&self, update: &MetricUpdate) {
+        println!("METRIC: {update}");
+        self.record(format!("METRIC: {update}"));
+    }
 }
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "lassie")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use deno_core::anyhow::{Context, Result};
+use deno_core::url::{Origin, Url};
+use deno_error::JsErrorBox;
+use deno_fetch::ReqBody;
+use hyper::header::{HeaderName, HeaderValue};
+
+/// Where `fetch("ipfs://...")` retrieves content from.
+///
+/// Zinnia provides a backend for the embedded Lassie daemon (requires the `lassie` feature),
+/// trustless HTTP gateways, a directory with CAR files for offline testing and a backend
+/// disabling IPFS retrievals. Embedders can implement this trait to describe other services.
+pub trait RetrievalBackend: Send + Sync {
+    /// How the runtime should send retrieval requests to this backend.
+    fn endpoint(&self) -> RetrievalEndpoint;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetrievalEndpoint {
+    /// An HTTP server implementing the
+    /// [Trustless Gateway](https://specs.ipfs.tech/http-gateways/trustless-gateway/) API.
+    /// Requests for `ipfs://{cid}/{path}` are sent to `{url}ipfs/{cid}/{path}`.
+    Http {
+        /// Base URL of the server, must end with `/`.
+        url: Url,
+        /// The header (name and value) the runtime sends with each request to authenticate itself,
+        /// e.g. `("authorization", "Bearer {token}")`. The header is added natively, modules
+        /// cannot read it. When set, modules cannot send their own header with the same name.
+        auth_header: Option<(String, String)>,
+        /// Whether the server is Lassie. Lassie understands the `protocols` and `providers` query
+        /// string parameters configured via [`crate::RetrievalConfig`], but it does not forward
+        /// the `Authorization` header to storage providers.
+        lassie_params: bool,
+    },
+    /// A directory with CAR files named `{cid}.car`.
+    CarDirectory(PathBuf),
+    /// IPFS retrievals are not available, `fetch("ipfs://...")` rejects with a `TypeError`.
+    Disabled,
+}
+
+impl RetrievalEndpoint {
+    /// The URL of the HTTP server handling retrievals. The module can always connect to it,
+    /// even when `allow_net` does not include it.
+    pub(crate) fn http_url(&self) -> Option<&Url> {
+        match self {
+            RetrievalEndpoint::Http { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+
+    /// The name of the header the runtime sends to authenticate itself, lowercased.
+    pub(crate) fn auth_header_name(&self) -> Option<String> {
+        match self {
+            RetrievalEndpoint::Http {
+                auth_header: Some((name, _)),
+                ..
+            } => Some(name.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+}
+
+/// `fetch.js` marks requests for the retrieval backend with this header, the value is the id of
+/// the credentials registered by the runtime. The request builder hook replaces the marker with
+/// the credentials, so that the secret never reaches JavaScript.
+const CREDENTIALS_MARKER: &str = "x-zinnia-retrieval-credentials";
+
+struct Credentials {
+    origin: Origin,
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+// The request builder hook of `deno_fetch` is a plain function without access to the `OpState`,
+// runtimes running in the same process register their credentials here
+static CREDENTIALS: Mutex<BTreeMap<u64, Credentials>> = Mutex::new(BTreeMap::new());
+static NEXT_CREDENTIALS_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Credentials of the retrieval backend registered for the lifetime of a runtime.
+pub(crate) struct RetrievalCredentials {
+    id: u64,
+}
+
+impl RetrievalCredentials {
+    /// Returns `None` when the endpoint does not use credentials.
+    pub(crate) fn register(endpoint: &RetrievalEndpoint) -> Result<Option<Self>> {
+        let RetrievalEndpoint::Http {
+            url,
+            auth_header: Some((name, value)),
+            ..
+        } = endpoint
+        else {
+            return Ok(None);
+        };
+        let mut value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value of the {name} header"))?;
+        value.set_sensitive(true);
+        let credentials = Credentials {
+            origin: url.origin(),
+            name: HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name: {name}"))?,
+            value,
+        };
+
+        let id = NEXT_CREDENTIALS_ID.fetch_add(1, Ordering::Relaxed);
+        CREDENTIALS.lock().unwrap().insert(id, credentials);
+        Ok(Some(Self { id }))
+    }
+
+    /// The value `fetch.js` puts in the marker header.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for RetrievalCredentials {
+    fn drop(&mut self) {
+        CREDENTIALS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// The `request_builder_hook` of `deno_fetch`. Replaces the marker header with the credentials
+/// it refers to. Credentials are added only to requests sent to the retrieval backend, e.g. they
+/// are not sent when the backend redirects to a different origin.
+pub(crate) fn add_retrieval_credentials(
+    request: &mut hyper::Request<ReqBody>,
+) -> Result<(), JsErrorBox> {
+    let Some(marker) = request.headers_mut().remove(CREDENTIALS_MARKER) else {
+        return Ok(());
+    };
+    let id = marker.to_str().ok().and_then(|id| id.parse::<u64>().ok());
+    let registry = CREDENTIALS.lock().unwrap();
+    let Some(credentials) = id.and_then(|id| registry.get(&id)) else {
+        return Err(JsErrorBox::type_error(
+            "The retrieval backend credentials are not available",
+        ));
+    };
+    let url = Url::parse(&request.uri().to_string());
+    if url.is_ok_and(|url| url.origin() == credentials.origin) {
+        request
+            .headers_mut()
+            .insert(credentials.name.clone(), credentials.value.clone());
+    }
+    Ok(())
+}
+
+/// Retrieve content via the embedded Lassie daemon.
+#[cfg(feature = "lassie")]
+pub struct LassieBackend {
+    daemon: Arc<lassie::Daemon>,
+}
+
+#[cfg(feature = "lassie")]
+impl LassieBackend {
+    /// We use Arc here to allow sharing of the singleton Lassie instance between multiple
+    /// threads spawned by Rust's test runner.
+    pub fn new(daemon: Arc<lassie::Daemon>) -> Self {
+        Self { daemon }
+    }
+}
+
+#[cfg(feature = "lassie")]
+impl RetrievalBackend for LassieBackend {
+    fn endpoint(&self) -> RetrievalEndpoint {
+        let url = format!("http://127.0.0.1:{}/", self.daemon.port());
+        RetrievalEndpoint::Http {
+            url: Url::parse(&url).expect("Lassie URL should be valid"),
+            auth_header: self
+                .daemon
+                .access_token()
+                .as_ref()
+                .map(|token| ("authorization".to_string(), format!("Bearer {token}"))),
+            lassie_params: true,
+        }
+    }
+}
+
+/// Retrieve content from a trustless HTTP gateway, e.g. `https://trustless-gateway.link`.
+///
+/// The `Authorization` header set by the module is forwarded to the gateway, unless the embedder
+/// configured the gateway credentials via [`GatewayBackend::with_authorization`]. Credentials
+/// configured via [`GatewayBackend::with_auth_header`] use a different header.
+#[derive(Debug, Clone)]
+pub struct GatewayBackend {
+    url: Url,
+    auth_header: Option<(String, String)>,
+}
+
+impl GatewayBackend {
+    pub fn new(mut url: Url) -> Self {
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Self {
+            url,
+            auth_header: None,
+        }
+    }
+
+    /// Send the given `Authorization` header value with every request to the gateway.
+    pub fn with_authorization(self, authorization: impl Into<String>) -> Self {
+        self.with_auth_header("authorization", authorization)
+    }
+
+    /// Send the given header with every request to the gateway, e.g. an `X-Api-Key` header.
+    /// Modules can still send their own `Authorization` header when the name is different.
+    pub fn with_auth_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.auth_header = Some((name.into(), value.into()));
+        self
+    }
+}
+
+impl RetrievalBackend for GatewayBackend {
+    fn endpoint(&self) -> RetrievalEndpoint {
+        RetrievalEndpoint::Http {
+            url: self.url.clone(),
+            auth_header: self.auth_header.clone(),
+            lassie_params: false,
+        }
+    }
+}
+
+/// Serve `ipfs://{cid}` requests from `{dir}/{cid}.car` files, e.g. to test modules offline.
+/// Requests for paths inside the DAG are not supported.
+#[derive(Debug, Clone)]
+pub struct CarDirectoryBackend {
+    dir: PathBuf,
+}
+
+impl CarDirectoryBackend {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl RetrievalBackend for CarDirectoryBackend {
+    fn endpoint(&self) -> RetrievalEndpoint {
+        RetrievalEndpoint::CarDirectory(self.dir.clone())
+    }
+}
+
+/// Reject all IPFS retrievals. This is the default backend of [`crate::BootstrapOptions`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisabledBackend;
+
+impl RetrievalBackend for DisabledBackend {
+    fn endpoint(&self) -> RetrievalEndpoint {
+        RetrievalEndpoint::Disabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_url_ends_with_slash() {
+        let backend = GatewayBackend::new(Url::parse("https://example.com/gw").unwrap());
+        assert_eq!(
+            backend.endpoint().http_url().map(Url::as_str),
+            Some("https://example.com/gw/")
+        );
+
+        let backend = GatewayBackend::new(Url::parse("https://example.com").unwrap());
+        assert_eq!(
+            backend.endpoint().http_url().map(Url::as_str),
+            Some("https://example.com/")
+        );
+    }
+
+    #[test]
+    fn adds_credentials_to_requests_for_the_backend() {
+        let backend = GatewayBackend::new(Url::parse("https://example.com/gw").unwrap())
+            .with_auth_header("X-Api-Key", "secret");
+        let credentials = RetrievalCredentials::register(&backend.endpoint())
+            .unwrap()
+            .expect("credentials should be registered");
+
+        let request = |url: &str| {
+            let mut request = hyper::Request::builder()
+                .uri(url)
+                .header(CREDENTIALS_MARKER, credentials.id().to_string())
+                .body(ReqBody::empty())
+                .unwrap();
+            add_retrieval_credentials(&mut request).unwrap();
+            request
+        };
+
+        let headers = request("https://example.com/gw/ipfs/bafy")
+            .headers()
+            .clone();
+        assert_eq!(headers.get("x-api-key").unwrap(), "secret");
+        assert!(!headers.contains_key(CREDENTIALS_MARKER));
+
+        // e.g. after a redirect to a different origin
+        let headers = request("https://other.example.com/ipfs/bafy")
+            .headers()
+            .clone();
+        assert!(!headers.contains_key("x-api-key"));
+        assert!(!headers.contains_key(CREDENTIALS_MARKER));
+
+        let id = credentials.id();
+        drop(credentials);
+        let mut request = hyper::Request::builder()
+            .uri("https://example.com/gw/ipfs/bafy")
+            .header(CREDENTIALS_MARKER, id.to_string())
+            .body(ReqBody::empty())
+            .unwrap();
+        assert!(add_retrieval_credentials(&mut request).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use deno_core::anyhow::{anyhow, Result};
+use serde::Serialize;
+
+const ONE_DAY: Duration = Duration::from_secs(24 * 3600);
+
+/// Retrieval protocols supported by Lassie.
+pub const RETRIEVAL_PROTOCOLS: &[&str] = &["bitswap", "graphsync", "http"];
+
+/// How to retrieve content requested via `fetch("ipfs://...")`.
+///
+/// `provider_timeout`, `global_timeout` and `max_blocks` configure the Lassie daemon, the embedder
+/// must pass the same config to `lassie_config()` when starting the daemon. `protocols`,
+/// `providers` and `denied_providers` apply to the Lassie backend only. The remaining options are
+/// applied to each retrieval request regardless of the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetrievalConfig {
+    /// How long to wait for the next block from a provider. At the moment, this applies to
+    /// Bitswap retrievals only.
+    pub provider_timeout: Option<Duration>,
+
+    /// Timeout for the entire retrieval.
+    pub global_timeout: Option<Duration>,
+
+    /// Maximum number of blocks to fetch in a single retrieval.
+    pub max_blocks: Option<u64>,
+
+    /// How many retrievals can run at the same time, further retrievals wait until one of the
+    /// running retrievals finishes. Unlimited when not set.
+    pub max_concurrent_retrievals: Option<u32>,
+
+    /// Protocols to retrieve the content with, see [`RETRIEVAL_PROTOCOLS`]. Lassie uses all
+    /// protocols when empty.
+    pub protocols: Vec<String>,
+
+    /// Multiaddrs of the only providers to retrieve the content from, e.g.
+    /// `/dns4/frisbii.fly.dev/https`. Lassie finds providers via IPNI when empty.
+    pub providers: Vec<String>,
+
+    /// Multiaddrs of providers modules cannot request via the `providers` query string parameter.
+    /// This only filters the requests made by modules, Lassie can still retrieve the content from
+    /// these providers when it finds them via IPNI. Ignored when `providers` is set.
+    pub denied_providers: Vec<String>,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            provider_timeout: Some(ONE_DAY),
+            global_timeout: Some(ONE_DAY),
+            max_blocks: None,
+            max_concurrent_retrievals: None,
+            protocols: vec![],
+            providers: vec![],
+            denied_providers: vec![],
+        }
+    }
+}
+
+impl RetrievalConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, timeout) in [
+            ("provider timeout", self.provider_timeout),
+            ("global timeout", self.global_timeout),
+        ] {
+            if timeout == Some(Duration::ZERO) {
+                return Err(anyhow!(
+                    "Invalid retrieval {name}: the timeout must be greater than zero."
+                ));
+            }
+        }
+        if self.max_blocks == Some(0) {
+            return Err(anyhow!(
+                "Invalid maximum number of blocks: the value must be greater than zero."
+            ));
+        }
+        if self.max_concurrent_retrievals == Some(0) {
+            return Err(anyhow!(
+                "Invalid maximum number of concurrent retrievals: \
+                the value must be greater than zero."
+            ));
+        }
+        for protocol in &self.protocols {
+            if !RETRIEVAL_PROTOCOLS.contains(&protocol.as_str()) {
+                return Err(anyhow!(
+                    "Invalid retrieval protocol {protocol:?}. Supported protocols: {}.",
+                    RETRIEVAL_PROTOCOLS.join(", ")
+                ));
+            }
+        }
+        for provider in self.providers.iter().chain(&self.denied_providers) {
+            if !provider.starts_with('/') || provider.contains(',') {
+                return Err(anyhow!(
+                    "Invalid retrieval provider {provider:?}. \
+                    Providers must be multiaddrs like \"/dns4/example.com/https\"."
+                ));
+            }
+        }
+        if let Some(provider) = self
+            .providers
+            .iter()
+            .find(|provider| self.denied_providers.contains(provider))
+        {
+            return Err(anyhow!(
+                "Invalid retrieval provider {provider:?}. The provider is both allowed and denied."
+            ));
+        }
+        Ok(())
+    }
+
+    /// Options applied to each retrieval request, in the format expected by `fetch.js`.
+    pub(crate) fn request_options(&self) -> RequestOptions {
+        RequestOptions {
+            max_concurrent_retrievals: self.max_concurrent_retrievals,
+            protocols: self.protocols.join(","),
+            providers: self.providers.join(","),
+            denied_providers: self.denied_providers.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RequestOptions {
+    max_concurrent_retrievals: Option<u32>,
+    protocols: String,
+    providers: String,
+    denied_providers: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_default_config() {
+        assert!(RetrievalConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_protocols() {
+        let config = RetrievalConfig {
+            protocols: vec!["http".into(), "ftp".into()],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid retrieval protocol \"ftp\". Supported protocols: bitswap, graphsync, http."
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_providers() {
+        let config = RetrievalConfig {
+            providers: vec!["frisbii.fly.dev".into()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = RetrievalConfig {
+            providers: vec!["/dns4/frisbii.fly.dev/https".into()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = RetrievalConfig {
+            denied_providers: vec!["frisbii.fly.dev".into()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = RetrievalConfig {
+            providers: vec!["/dns4/frisbii.fly.dev/https".into()],
+            denied_providers: vec!["/dns4/frisbii.fly.dev/https".into()],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid retrieval provider \"/dns4/frisbii.fly.dev/https\". \
+            The provider is both allowed and denied."
+        );
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        let config = RetrievalConfig {
+            global_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = RetrievalConfig {
+            max_concurrent_retrievals: Some(0),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::{Arc, Mutex};
+
+/// Allows the embedder to pause IPFS retrievals, e.g. when the disk is nearly full. While paused,
+/// `fetch("ipfs://...")` rejects immediately.
+///
+/// Clones share the same state, the embedder keeps one clone and passes another one to
+/// [`crate::BootstrapOptions`].
+#[derive(Debug, Default, Clone)]
+pub struct RetrievalGate {
+    paused: Arc<Mutex<Option<String>>>,
+}
+
+impl RetrievalGate {
+    /// Reject new retrievals, `reason` is included in the error reported to the module.
+    pub fn pause(&self, reason: &str) {
+        *self.paused.lock().unwrap() = Some(reason.to_string());
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = None;
+    }
+
+    /// The reason why retrievals are paused, `None` when retrievals are allowed.
+    pub fn paused_reason(&self) -> Option<String> {
+        self.paused.lock().unwrap().clone()
+    }
+}
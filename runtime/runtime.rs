@@ -1,21 +1,31 @@
+use std::future::poll_fn;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::time::Duration;
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use deno_core::error::CoreError;
 use deno_core::{located_script_name, serde_json, JsRuntime, ModuleSpecifier, RuntimeOptions};
+use tokio::time::MissedTickBehavior;
 
 use deno_web::BlobStore;
 
 use {once_cell::sync::Lazy, regex::Regex};
 
 use crate::module_loader::ZinniaModuleLoader;
-use crate::Reporter;
+use crate::network_fixture::FixtureState;
+use crate::retrieval_backend::{add_retrieval_credentials, RetrievalCredentials};
+use crate::telemetry::{flush_telemetry, init_telemetry, otel_config};
+use crate::{
+    DisabledBackend, NetworkFixture, ProxyConfig, Reporter, RetrievalBackend, RetrievalConfig,
+    RetrievalEndpoint, RetrievalGate, RuntimeMetrics, TelemetryOptions, TlsConfig,
+};
 
 use crate::ext::ZinniaPermissions;
 
 pub type AnyError = deno_core::anyhow::Error;
-use deno_core::anyhow::{anyhow, Result};
+use deno_core::anyhow::{anyhow, Context, Result};
 
 /// Common bootstrap options for MainWorker & WebWorker
 #[derive(Clone)]
@@ -23,9 +33,16 @@ pub struct BootstrapOptions {
     /// The user agent version string to use for Fetch API requests
     pub agent_version: String,
 
-    /// Seed value for initializing the random number generator
+    /// Seed value for initializing the random number generator used by `Math.random()` and
+    /// `crypto.getRandomValues()`, e.g. to reproduce a run of the module
     pub rng_seed: Option<u64>,
 
+    /// Run the module on a virtual clock starting at this time, e.g. to reproduce a run of the
+    /// module. `Date` and `performance.now()` report the due time of the last timer that ran,
+    /// timers run once their delay passed in real time. The module uses the real clock when not
+    /// set.
+    pub virtual_clock_start: Option<SystemTime>,
+
     /// Module root if you want to sandbox `import` of ES modules
     pub module_root: Option<PathBuf>,
 
@@ -38,9 +55,40 @@ pub struct BootstrapOptions {
     /// Report activities
     pub reporter: Rc<dyn Reporter>,
 
-    /// Lassie daemon to use as the IPFS retrieval client. We must use Arc here to allow sharing of
-    /// the singleton Lassie instance between multiple threads spawned by Rust's test runner.
-    pub lassie_daemon: Arc<lassie::Daemon>,
+    /// Where to retrieve content requested via `fetch("ipfs://...")` from. IPFS retrievals are
+    /// disabled by default.
+    pub retrieval_backend: Arc<dyn RetrievalBackend>,
+
+    /// Metrics describing the runtime, e.g. the number of fetch requests or the heap usage.
+    /// The embedder can keep a clone of this Arc to read the values from another thread.
+    pub metrics: Arc<RuntimeMetrics>,
+
+    /// Export OpenTelemetry traces of `fetch` requests and custom spans (and optionally console
+    /// logs) to an OTLP collector. Telemetry is disabled when not set.
+    pub telemetry: Option<TelemetryOptions>,
+
+    /// Hostnames the module can connect to via `fetch` and `WebSocket`, e.g. `example.com` or
+    /// `*.example.com`. All hosts are allowed when not set.
+    pub allow_net: Option<Vec<String>>,
+
+    /// Allows the embedder to pause IPFS retrievals, e.g. when the disk is nearly full.
+    pub retrieval_gate: RetrievalGate,
+
+    /// How to retrieve content requested via `fetch("ipfs://...")`.
+    pub retrieval: RetrievalConfig,
+
+    /// Extra CA certificates, client certificate and other TLS settings for `fetch` and
+    /// `WebSocket` connections.
+    pub tls: TlsConfig,
+
+    /// Send `fetch` requests, `WebSocket` connections and OpenTelemetry exports via this proxy.
+    /// `WebSocket` connections cannot use `https` proxies. All connections are direct when not
+    /// set.
+    pub proxy: Option<ProxyConfig>,
+
+    /// Record the `fetch` and `WebSocket` traffic of the module into a fixture file or replay it
+    /// from the file without accessing the network.
+    pub network_fixture: Option<NetworkFixture>,
 
     /// Zinnia version reported by `Zinnia.versions.zinnia` API.
     /// Embedders can customize this value.
@@ -51,12 +99,12 @@ impl BootstrapOptions {
     pub fn new(
         agent_version: String,
         reporter: Rc<dyn Reporter>,
-        lassie_daemon: Arc<lassie::Daemon>,
         module_root: Option<PathBuf>,
     ) -> Self {
         Self {
             agent_version,
             rng_seed: None,
+            virtual_clock_start: None,
             module_root,
             wallet_address: String::from("0x000000000000000000000000000000000000dEaD"),
             // Station ID must look like a public key - 88 hexadecimal characters.
@@ -64,7 +112,15 @@ impl BootstrapOptions {
             // from non-production systems (dev, CI).
             station_id: "0".repeat(88),
             reporter,
-            lassie_daemon,
+            retrieval_backend: Arc::new(DisabledBackend),
+            metrics: Arc::new(RuntimeMetrics::default()),
+            telemetry: None,
+            allow_net: None,
+            retrieval_gate: RetrievalGate::default(),
+            retrieval: RetrievalConfig::default(),
+            tls: TlsConfig::default(),
+            proxy: None,
+            network_fixture: None,
             zinnia_version: env!("CARGO_PKG_VERSION"),
         }
     }
@@ -73,13 +129,14 @@ impl BootstrapOptions {
         let payload = serde_json::json!({
           "walletAddress": self.wallet_address,
           "stationId": self.station_id,
-          "lassieUrl": format!("http://127.0.0.1:{}/", self.lassie_daemon.port()),
-          "lassieAuth": match self.lassie_daemon.access_token() {
-            Some(token) => serde_json::Value::String(format!("Bearer {token}")),
-            None => serde_json::Value::Null,
-          },
+          "retrievalBackend": retrieval_backend_json(&self.retrieval_backend.endpoint()),
+          "retrieval": self.retrieval.request_options(),
           "zinniaVersion": self.zinnia_version,
           "v8Version": deno_core::v8::VERSION_STRING,
+          "otelConfig": otel_config(self.telemetry.as_ref()).as_v8(),
+          "networkFixture": self.network_fixture.as_ref().map(NetworkFixture::mode),
+          "seededRandom": self.rng_seed.is_some(),
+          "virtualClockStart": self.virtual_clock_start.map(unix_time_ms),
         });
         serde_json::to_string_pretty(&payload).unwrap()
     }
@@ -92,9 +149,53 @@ pub async fn run_js_module(
     if !validate_station_id(&bootstrap_options.station_id) {
         return Err(anyhow!("Invalid station_id format"));
     }
+    bootstrap_options.retrieval.validate()?;
+
+    if let Some(telemetry) = &bootstrap_options.telemetry {
+        init_telemetry(
+            telemetry,
+            &bootstrap_options.tls,
+            bootstrap_options.proxy.as_ref(),
+            bootstrap_options.zinnia_version,
+        )?;
+    }
 
+    let result = run_js_module_impl(module_specifier, bootstrap_options).await;
+
+    if bootstrap_options.telemetry.is_some() {
+        // Don't lose spans & logs recorded at the end of the module execution
+        flush_telemetry();
+    }
+
+    result
+}
+
+async fn run_js_module_impl(
+    module_specifier: &ModuleSpecifier,
+    bootstrap_options: &BootstrapOptions,
+) -> Result<(), AnyError> {
     let blob_store = Arc::new(BlobStore::default());
     let reporter = Rc::clone(&bootstrap_options.reporter);
+    let tls = bootstrap_options
+        .tls
+        .load()
+        .context("Invalid TLS configuration")?;
+    if let Some(proxy) = &bootstrap_options.proxy {
+        proxy.validate().context("Invalid proxy configuration")?;
+    }
+    let websocket_proxy = bootstrap_options
+        .proxy
+        .as_ref()
+        .map(ProxyConfig::to_websocket_proxy)
+        .transpose()?;
+    let network_fixture = bootstrap_options
+        .network_fixture
+        .as_ref()
+        .map(FixtureState::load)
+        .transpose()?;
+    let retrieval_endpoint = bootstrap_options.retrieval_backend.endpoint();
+    let retrieval_credentials = RetrievalCredentials::register(&retrieval_endpoint)
+        .context("Invalid retrieval backend credentials")?;
 
     // Initialize a runtime instance
     let mut runtime = JsRuntime::new(RuntimeOptions {
@@ -110,18 +211,46 @@ pub async fn run_js_module(
             ),
             deno_fetch::deno_fetch::init_ops_and_esm::<ZinniaPermissions>(deno_fetch::Options {
                 user_agent: bootstrap_options.agent_version.clone(),
+                root_cert_store_provider: tls.root_cert_store_provider.clone(),
+                unsafely_ignore_certificate_errors: bootstrap_options
+                    .tls
+                    .unsafely_ignore_certificate_errors
+                    .clone(),
+                client_cert_chain_and_key: tls.client_cert_chain_and_key,
+                proxy: bootstrap_options
+                    .proxy
+                    .as_ref()
+                    .map(ProxyConfig::to_deno_proxy),
+                // The embedder resolves the proxy configuration, e.g. via
+                // `ProxyConfig::with_env_defaults()`
+                proxy_from_env: false,
+                request_builder_hook: Some(add_retrieval_credentials),
                 ..Default::default()
             }),
             deno_websocket::deno_websocket::init_ops_and_esm::<ZinniaPermissions>(
                 bootstrap_options.agent_version.clone(),
-                None, // root_cert_store_provider
-                None, // unsafely_ignore_certificate_errors:
+                tls.root_cert_store_provider,
+                bootstrap_options
+                    .tls
+                    .unsafely_ignore_certificate_errors
+                    .clone(),
+                websocket_proxy,
             ),
             deno_crypto::deno_crypto::init_ops_and_esm(bootstrap_options.rng_seed),
             deno_net::deno_net::init_ops_and_esm::<ZinniaPermissions>(None, None),
             deno_tls::deno_tls::init_ops_and_esm(),
             // Zinnia-specific APIs
-            crate::ext::zinnia_runtime::init_ops_and_esm(reporter),
+            crate::ext::zinnia_runtime::init_ops_and_esm(
+                reporter,
+                Arc::clone(&bootstrap_options.metrics),
+                bootstrap_options.allow_net.clone(),
+                bootstrap_options.proxy.clone(),
+                network_fixture,
+                bootstrap_options.rng_seed,
+                retrieval_endpoint,
+                retrieval_credentials,
+                bootstrap_options.retrieval_gate.clone(),
+            ),
         ],
         extension_transpiler: Some(Rc::new(|specifier, source| {
             crate::vendored::transpile::maybe_transpile_source(specifier, source)
@@ -136,21 +265,91 @@ pub async fn run_js_module(
     let script = format!("bootstrap.mainRuntime({})", bootstrap_options.as_json());
     runtime.execute_script(located_script_name!(), script)?;
 
+    let result = evaluate_main_module(&mut runtime, module_specifier, bootstrap_options).await;
+
+    // Save the traffic recorded before the module finished, also when the module failed
+    if let Some(fixture) = runtime.op_state().borrow().try_borrow::<FixtureState>() {
+        fixture.save()?;
+    }
+
+    result
+}
+
+async fn evaluate_main_module(
+    runtime: &mut JsRuntime,
+    module_specifier: &ModuleSpecifier,
+    bootstrap_options: &BootstrapOptions,
+) -> Result<(), AnyError> {
     // Load and run the module
     let main_module_id = runtime.load_main_es_module(module_specifier).await?;
+    bootstrap_options.reporter.module_loaded(module_specifier);
     let res = runtime.mod_evaluate(main_module_id);
-    runtime.run_event_loop(Default::default()).await?;
+    bootstrap_options.reporter.module_started();
+    run_event_loop_with_metrics(runtime, &bootstrap_options.metrics).await?;
     res.await?;
 
     Ok(())
 }
 
-use deno_crypto::rand::{self, distributions::Alphanumeric, Rng};
+const METRICS_SAMPLING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run the event loop to completion while periodically sampling the heap usage and measuring
+/// how late the sampling timer fired (the event loop lag).
+async fn run_event_loop_with_metrics(
+    runtime: &mut JsRuntime,
+    metrics: &RuntimeMetrics,
+) -> Result<(), CoreError> {
+    let mut sampler = tokio::time::interval(METRICS_SAMPLING_INTERVAL);
+    sampler.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-const ONE_DAY: Duration = Duration::from_secs(24 * 3600);
+    poll_fn(|cx| {
+        while let Poll::Ready(scheduled) = sampler.poll_tick(cx) {
+            metrics
+                .event_loop_lag_seconds
+                .set(scheduled.elapsed().as_secs_f64());
+
+            let stats = runtime.v8_isolate().get_heap_statistics();
+            metrics.heap_used_bytes.set(stats.used_heap_size() as f64);
+            metrics.heap_total_bytes.set(stats.total_heap_size() as f64);
+        }
+
+        // The sampler does not keep the event loop alive, we finish as soon as the module is done
+        runtime.poll_event_loop(cx, Default::default())
+    })
+    .await
+}
+
+// Milliseconds since the Unix epoch, the timestamp format used by `Date`
+fn unix_time_ms(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as f64,
+        Err(err) => -(err.duration().as_millis() as f64),
+    }
+}
+
+// The backend configuration in the format expected by `fetch.js`
+fn retrieval_backend_json(endpoint: &RetrievalEndpoint) -> serde_json::Value {
+    match endpoint {
+        RetrievalEndpoint::Http {
+            url, lassie_params, ..
+        } => serde_json::json!({
+            "kind": "http",
+            "url": url.as_str(),
+            // The runtime adds the header natively, the module learns only its name
+            "authHeader": endpoint.auth_header_name(),
+            "lassieParams": lassie_params,
+        }),
+        RetrievalEndpoint::CarDirectory(_) => serde_json::json!({ "kind": "carDirectory" }),
+        RetrievalEndpoint::Disabled => serde_json::json!({ "kind": "disabled" }),
+    }
+}
+
+#[cfg(feature = "lassie")]
+use deno_crypto::rand::{self, distributions::Alphanumeric, Rng};
 
 /// A baseline configuration for Lassie shared by `zinnia` and `zinniad` CLIs.
-pub fn lassie_config() -> lassie::DaemonConfig {
+#[cfg(feature = "lassie")]
+pub fn lassie_config(retrieval: &RetrievalConfig) -> lassie::DaemonConfig {
     let access_token = rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(24)
@@ -160,8 +359,9 @@ pub fn lassie_config() -> lassie::DaemonConfig {
     lassie::DaemonConfig {
         port: 0,
         access_token: Some(access_token),
-        provider_timeout: Some(ONE_DAY),
-        global_timeout: Some(ONE_DAY),
+        provider_timeout: retrieval.provider_timeout,
+        global_timeout: retrieval.global_timeout,
+        max_blocks: retrieval.max_blocks,
         ..Default::default()
     }
 }
@@ -172,7 +372,7 @@ fn validate_station_id(station_id: &str) -> bool {
 }
 
 pub fn exit(code: i32) -> ! {
-    deno_telemetry::flush();
+    flush_telemetry();
     #[allow(clippy::disallowed_methods)]
     std::process::exit(code);
 }
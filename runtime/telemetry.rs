@@ -0,0 +1,323 @@
+use std::sync::Mutex;
+
+use deno_core::anyhow::{anyhow, Context, Result};
+use deno_telemetry::{
+    DenoIdGenerator, OtelConfig, OtelConsoleConfig, OtelGlobals, OtelPropagators,
+    OtelSharedRuntime, OTEL_GLOBALS,
+};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_otlp::{HttpExporterBuilder, Protocol, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::logs::{BatchLogProcessor, LogProcessor};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::{BatchSpanProcessor, RandomIdGenerator, SpanProcessor};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource::{
+    PROCESS_RUNTIME_NAME, PROCESS_RUNTIME_VERSION, SERVICE_NAME,
+};
+
+use crate::{ProxyConfig, TlsConfig};
+
+/// Configuration for exporting OpenTelemetry traces and logs to an OTLP/HTTP collector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryOptions {
+    /// Base URL of the collector, e.g. `http://localhost:4318`. The signal-specific paths
+    /// like `/v1/traces` and `/v1/logs` are appended automatically. The environment variables
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` and `OTEL_EXPORTER_OTLP_{TRACES,LOGS}_ENDPOINT` take
+    /// precedence when set.
+    pub endpoint: String,
+
+    /// The value of the `service.name` resource attribute, typically the module name.
+    /// The environment variable `OTEL_SERVICE_NAME` takes precedence when set.
+    pub service_name: String,
+
+    /// Export logs written via `console.*` APIs in addition to printing them.
+    pub export_console: bool,
+}
+
+impl TelemetryOptions {
+    pub fn new(endpoint: String, service_name: String) -> Self {
+        Self {
+            endpoint,
+            service_name,
+            export_console: false,
+        }
+    }
+}
+
+/// Convert the (optional) telemetry options to the config consumed by `bootstrapOtel()`.
+pub(crate) fn otel_config(options: Option<&TelemetryOptions>) -> OtelConfig {
+    match options {
+        None => OtelConfig::default(),
+        Some(options) => OtelConfig {
+            tracing_enabled: true,
+            metrics_enabled: false,
+            console: match options.export_console {
+                true => OtelConsoleConfig::Capture,
+                false => OtelConsoleConfig::Ignore,
+            },
+            deterministic_prefix: None,
+            propagators: [OtelPropagators::TraceContext, OtelPropagators::Baggage].into(),
+        },
+    }
+}
+
+/// Start the OpenTelemetry exporters. The exporters connect to the collector using the same TLS
+/// settings and proxy as `fetch`.
+///
+/// The exporters are shared by all runtimes created in the process. Subsequent calls with the
+/// same options are no-ops, calls with different options fail.
+pub(crate) fn init_telemetry(
+    options: &TelemetryOptions,
+    tls: &TlsConfig,
+    proxy: Option<&ProxyConfig>,
+    zinnia_version: &'static str,
+) -> Result<()> {
+    // Serialize the initialization of runtimes started in parallel (e.g. by the test runner)
+    static INITIALIZED_WITH: Mutex<Option<ExporterConfig>> = Mutex::new(None);
+    let mut initialized_with = INITIALIZED_WITH
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    let config = (options.clone(), tls.clone(), proxy.cloned());
+    match initialized_with.as_ref() {
+        Some(current) if current == &config => return Ok(()),
+        Some(current) => {
+            return Err(anyhow!(
+                "OpenTelemetry exporters were already configured with {current:?}, \
+                 cannot reconfigure them with {config:?}"
+            ))
+        }
+        None => {}
+    }
+    if OTEL_GLOBALS.get().is_some() {
+        return Err(anyhow!(
+            "OpenTelemetry exporters were already configured by the embedder"
+        ));
+    }
+
+    init_exporters(options, tls, proxy, zinnia_version)
+        .context("cannot initialize OpenTelemetry exporters")?;
+    *initialized_with = Some(config);
+    Ok(())
+}
+
+type ExporterConfig = (TelemetryOptions, TlsConfig, Option<ProxyConfig>);
+
+// Like `deno_telemetry::init()`, except that the collector endpoint and the service name are
+// passed to the exporters directly instead of via the environment variables. The standard
+// `OTEL_*` environment variables take precedence when set.
+fn init_exporters(
+    options: &TelemetryOptions,
+    tls: &TlsConfig,
+    proxy: Option<&ProxyConfig>,
+    zinnia_version: &'static str,
+) -> Result<()> {
+    let mut resource = Resource::default().merge(&Resource::new([
+        KeyValue::new(PROCESS_RUNTIME_NAME, "zinnia"),
+        KeyValue::new(PROCESS_RUNTIME_VERSION, zinnia_version),
+    ]));
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.merge(&Resource::new([KeyValue::new(
+            SERVICE_NAME,
+            options.service_name.clone(),
+        )]));
+    }
+
+    let endpoint = options.endpoint.trim_end_matches('/');
+    let user_agent = format!("zinnia/{zinnia_version}");
+    let client = hyper_client::HyperClient::new(&user_agent, tls, proxy)?;
+
+    let span_exporter = HttpExporterBuilder::default()
+        .with_http_client(client.clone())
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(format!("{endpoint}/v1/traces"))
+        .build_span_exporter()?;
+    let mut span_processor = BatchSpanProcessor::builder(span_exporter, OtelSharedRuntime).build();
+    span_processor.set_resource(&resource);
+
+    let log_exporter = HttpExporterBuilder::default()
+        .with_http_client(client)
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(format!("{endpoint}/v1/logs"))
+        .build_log_exporter()?;
+    let log_processor = BatchLogProcessor::builder(log_exporter, OtelSharedRuntime).build();
+    log_processor.set_resource(&resource);
+
+    // Metrics are not exported, see `otel_config()`
+    let meter_provider = SdkMeterProvider::builder().with_resource(resource).build();
+
+    OTEL_GLOBALS
+        .set(OtelGlobals {
+            span_processor,
+            log_processor,
+            id_generator: DenoIdGenerator::Random(RandomIdGenerator::default()),
+            meter_provider,
+            builtin_instrumentation_scope: InstrumentationScope::builder("deno")
+                .with_version(zinnia_version)
+                .build(),
+            config: otel_config(Some(options)),
+        })
+        .map_err(|_| anyhow!("OpenTelemetry exporters are already configured"))
+}
+
+/// Export all pending spans and logs. This blocks the current thread until the collector
+/// accepts the data or the export times out.
+pub fn flush_telemetry() {
+    deno_telemetry::flush();
+}
+
+// The HTTP client used by the exporters, like the one in `deno_telemetry`. It's called from the
+// shared OpenTelemetry runtime, so that the exports don't depend on the runtime of the module.
+mod hyper_client {
+    use deno_core::anyhow::Context;
+    use deno_fetch::{create_http_client, Client, CreateHttpClientOptions, ReqBody};
+    use http_body_util::BodyExt;
+    use opentelemetry_http::{Bytes, HttpError, Request, Response, ResponseExt};
+
+    use crate::{ProxyConfig, TlsConfig};
+
+    /// Connects to the collector like `fetch` connects to servers: with the same TLS settings and
+    /// via the same proxy, unless the collector's host is excluded from the proxy.
+    #[derive(Debug, Clone)]
+    pub struct HyperClient {
+        direct: Client,
+        proxied: Option<(ProxyConfig, Client)>,
+    }
+
+    impl HyperClient {
+        pub fn new(
+            user_agent: &str,
+            tls: &TlsConfig,
+            proxy: Option<&ProxyConfig>,
+        ) -> deno_core::anyhow::Result<Self> {
+            let loaded = tls.load().context("Invalid TLS configuration")?;
+            let root_cert_store = match &loaded.root_cert_store_provider {
+                Some(provider) => Some(provider.get_or_try_init()?.clone()),
+                None => None,
+            };
+            let options = CreateHttpClientOptions {
+                root_cert_store,
+                unsafely_ignore_certificate_errors: tls.unsafely_ignore_certificate_errors.clone(),
+                client_cert_chain_and_key: loaded
+                    .client_cert_chain_and_key
+                    .try_into()
+                    .unwrap_or_default(),
+                proxy_from_env: false,
+                ..Default::default()
+            };
+            let proxied = match proxy {
+                None => None,
+                Some(proxy) => {
+                    let client = create_http_client(
+                        user_agent,
+                        CreateHttpClientOptions {
+                            proxy: Some(proxy.to_deno_proxy()),
+                            ..options.clone()
+                        },
+                    )?;
+                    Some((proxy.clone(), client))
+                }
+            };
+            Ok(Self {
+                direct: create_http_client(user_agent, options)?,
+                proxied,
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl opentelemetry_http::HttpClient for HyperClient {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+            let host = request.uri().host().unwrap_or_default();
+            let client = match &self.proxied {
+                Some((proxy, client)) if !proxy.bypasses(host) => client,
+                _ => &self.direct,
+            };
+            let (parts, body) = request.into_parts();
+            let request = Request::from_parts(parts, ReqBody::full(body.into()));
+            let mut response = client.clone().send(request).await?;
+            let headers = std::mem::take(response.headers_mut());
+
+            let mut http_response = Response::builder()
+                .status(response.status())
+                .body(response.into_body().collect().await?.to_bytes())?;
+            *http_response.headers_mut() = headers;
+            Ok(http_response.error_for_status()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn disabled_by_default() {
+        assert_eq!(otel_config(None).as_v8().as_ref(), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn enables_tracing_and_console_export() {
+        let options = TelemetryOptions {
+            export_console: true,
+            ..TelemetryOptions::new("http://localhost:4318".into(), "test".into())
+        };
+        let config = otel_config(Some(&options));
+        assert_eq!(&config.as_v8()[0..3], &[1, 0, 1]);
+    }
+
+    #[test]
+    fn exporter_client_loads_tls_config() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let tls = TlsConfig {
+            ca_cert_files: vec![dir.path().join("missing.pem")],
+            ..Default::default()
+        };
+        let err = hyper_client::HyperClient::new("test", &tls, None).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Cannot read certificate file"),
+            "{err:#}"
+        );
+
+        hyper_client::HyperClient::new("test", &TlsConfig::default(), None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exporter_client_uses_proxy() {
+        use opentelemetry_http::HttpClient;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A stand-in for an HTTP proxy, it responds to the first request it receives
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let proxy = ProxyConfig::new(format!("http://127.0.0.1:{proxy_port}"));
+        let client =
+            hyper_client::HyperClient::new("test", &TlsConfig::default(), Some(&proxy)).unwrap();
+        let request = opentelemetry_http::Request::post("http://collector.example.com/v1/traces")
+            .body(b"spans".to_vec())
+            .unwrap();
+        client.send(request).await.unwrap();
+
+        let received = received.await.unwrap();
+        assert!(
+            received.starts_with("POST http://collector.example.com/v1/traces HTTP/1.1\r\n"),
+            "{received}"
+        );
+    }
+}
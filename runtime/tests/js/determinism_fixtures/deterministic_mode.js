@@ -0,0 +1,14 @@
+// Prints values that are the same in every run when the random number generator is seeded and the
+// module runs on the virtual clock
+console.log("Math.random: %s", Math.random());
+console.log("crypto.getRandomValues: %s", crypto.getRandomValues(new Uint8Array(4)).join(","));
+console.log("Date: %s", new Date().toISOString());
+console.log("Date(): %s", Date() === new Date().toString());
+
+const started = performance.now();
+let ticks = 0;
+const interval = setInterval(() => ticks++, 10);
+await new Promise((resolve) => setTimeout(resolve, 55));
+clearInterval(interval);
+console.log("Timers: %s ticks, %s ms", ticks, performance.now() - started);
+console.log("Date: %s", new Date().toISOString());
@@ -0,0 +1,117 @@
+import { fakeTimers, mockFetch, test } from "zinnia:test";
+import { assertEquals, assertRejects, assertThrows } from "zinnia:assert";
+
+const realSetTimeout = setTimeout;
+
+test("runs timers when the clock advances", async () => {
+  fakeTimers.install({ now: new Date("2024-01-01T00:00:00Z") });
+  const calls = [];
+  setTimeout(() => calls.push("second"), 200);
+  setTimeout((name) => calls.push(name), 100, "first");
+  const id = setTimeout(() => calls.push("cancelled"), 150);
+  clearTimeout(id);
+  assertEquals(fakeTimers.pending, 2);
+
+  await fakeTimers.advance(99);
+  assertEquals(calls, []);
+  await fakeTimers.advance(1);
+  assertEquals(calls, ["first"]);
+  await fakeTimers.advance(1000);
+  assertEquals(calls, ["first", "second"]);
+  assertEquals(fakeTimers.pending, 0);
+});
+
+test("controls Date and performance.now()", async () => {
+  fakeTimers.install({ now: 1_700_000_000_000 });
+  const started = performance.now();
+  assertEquals(Date.now(), 1_700_000_000_000);
+  assertEquals(new Date().toISOString(), "2023-11-14T22:13:20.000Z");
+  assertEquals(new Date(0).getTime(), 0);
+
+  let firedAt;
+  setTimeout(() => (firedAt = Date.now()), 250);
+  await fakeTimers.advance(1_000);
+  assertEquals(firedAt, 1_700_000_000_250);
+  assertEquals(Date.now(), 1_700_000_001_000);
+  assertEquals(Math.round(performance.now() - started), 1_000);
+});
+
+test("runs intervals and loops awaiting promises", async () => {
+  fakeTimers.install();
+  mockFetch.get("https://api.example.com/tasks", Response.json({ id: 1 }));
+  const tasks = [];
+  const loop = async () => {
+    for (;;) {
+      const res = await fetch("https://api.example.com/tasks");
+      tasks.push(await res.json());
+      if (tasks.length === 3) return;
+      await new Promise((resolve) => setTimeout(resolve, 60_000));
+    }
+  };
+  const done = loop();
+
+  let ticks = 0;
+  const id = setInterval(() => ticks++, 10_000);
+  await fakeTimers.advance(120_000);
+  clearInterval(id);
+  assertEquals(ticks, 12);
+  await done;
+  assertEquals(tasks.length, 3);
+});
+
+test("runAll() runs timers scheduled by other timers", async () => {
+  fakeTimers.install();
+  const calls = [];
+  setTimeout(() => {
+    calls.push(1);
+    setTimeout(() => calls.push(2), 5_000);
+  }, 1_000);
+  await fakeTimers.runAll();
+  assertEquals(calls, [1, 2]);
+
+  setInterval(() => {}, 1_000);
+  await assertRejects(() => fakeTimers.runAll(), Error, "infinite loop of timers");
+});
+
+test("the runtime's timers follow the virtual clock", async () => {
+  fakeTimers.install();
+  const signal = AbortSignal.timeout(5_000);
+  let fired = false;
+  realSetTimeout(() => (fired = true), 1_000);
+  assertEquals(fakeTimers.pending, 2);
+
+  await fakeTimers.advance(999);
+  assertEquals(fired, false);
+  await fakeTimers.advance(1);
+  assertEquals(fired, true);
+  assertEquals(signal.aborted, false);
+  await fakeTimers.advance(4_000);
+  assertEquals(signal.aborted, true);
+  assertEquals(fakeTimers.pending, 0);
+});
+
+test("Date() without new returns the virtual time as a string", () => {
+  const RealDate = Date;
+  fakeTimers.install({ now: 1_700_000_000_000 });
+  assertEquals(typeof Date(), "string");
+  assertEquals(Date(), new RealDate(1_700_000_000_000).toString());
+  assertEquals(Date.parse("2024-01-01T00:00:00Z"), 1_704_067_200_000);
+  assertEquals(Date.UTC(2024, 0, 1), 1_704_067_200_000);
+  assertEquals(new Date() instanceof Date, true);
+  assertEquals(new Date() instanceof RealDate, true);
+  assertEquals(Date.prototype, RealDate.prototype);
+});
+
+test("rejects invalid usage", async () => {
+  await assertRejects(() => fakeTimers.advance(100), Error, "not installed");
+  assertThrows(() => fakeTimers.pending, Error, "not installed");
+  fakeTimers.install();
+  assertThrows(() => fakeTimers.install(), Error, "already installed");
+});
+
+test("restores the real clock after each test", async () => {
+  assertEquals(setTimeout, realSetTimeout);
+  const started = Date.now();
+  await new Promise((resolve) => setTimeout(resolve, 10));
+  assertEquals(Date.now() - started >= 10, true);
+});
@@ -0,0 +1,102 @@
+import { mockFetch, test } from "zinnia:test";
+import { assertEquals, assertRejects, assertThrows, AssertionError } from "zinnia:assert";
+
+const TEST_CID = "bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq";
+const TEST_CAR_BASE64 =
+  "OqJlcm9vdHOB2CpYJQABVRIg+ujQf1DbvNP91lYQrc1sPfIXGzGulwQbHiIlBueN/ZRndmVyc2lvbgGLAQFVEiD66NB/UNu80/3WVhCtzWw98hcbMa6XBBseIiUG5439lGxhcGlkYXJ5CmJyYXZvCnJlYWwKcGFyZXNpcwpoaWdoYm9ybgpob3JzZQpib3dlbAphc3Npc3QKY29ybmVhCnB5cmUKVU5JRk9STQpza2lpbmcKc3BpcmUKdXBoZWF2ZQpjcnVtcAo=";
+
+test("mocks responses by method and URL", async () => {
+  mockFetch.get("https://api.example.com/tasks", Response.json({ id: 1 }));
+  mockFetch.post("https://api.example.com/measurements", async (request) => {
+    const { id } = await request.json();
+    return new Response(null, { status: id === 1 ? 201 : 400 });
+  });
+
+  const task = await fetch("https://api.example.com/tasks");
+  assertEquals(task.url, "https://api.example.com/tasks");
+  assertEquals(await task.json(), { id: 1 });
+
+  const res = await fetch("https://api.example.com/measurements", {
+    method: "POST",
+    body: JSON.stringify({ id: 1 }),
+  });
+  assertEquals(res.status, 201);
+
+  mockFetch.assertCalled("GET", "https://api.example.com/tasks", { times: 1 });
+  mockFetch.assertNotCalled("DELETE", "https://api.example.com/tasks");
+  const [measurement] = mockFetch.callsTo("POST", "https://api.example.com/measurements");
+  assertEquals(await measurement.request.json(), { id: 1 });
+  assertEquals(
+    mockFetch.calls.map(({ method, url }) => `${method} ${url}`),
+    ["GET https://api.example.com/tasks", "POST https://api.example.com/measurements"],
+  );
+});
+
+test("returns a fresh copy of the mocked response for each call", async () => {
+  mockFetch.get("https://api.example.com/", new Response("hello"));
+  assertEquals(await (await fetch("https://api.example.com/")).text(), "hello");
+  assertEquals(await (await fetch("https://api.example.com/")).text(), "hello");
+  mockFetch.assertCalled("GET", "https://api.example.com/", { times: 2 });
+});
+
+test("rejects requests with the mocked error", async () => {
+  mockFetch.get("https://api.example.com/tasks", new TypeError("connection reset"));
+  await assertRejects(() => fetch("https://api.example.com/tasks"), TypeError, "connection reset");
+});
+
+test("rejects requests not matching any route", async () => {
+  mockFetch.get("https://api.example.com/tasks", Response.json([]));
+  await assertRejects(
+    () => fetch("https://api.example.com/tasks", { method: "DELETE" }),
+    TypeError,
+    "No fetch mock matches DELETE https://api.example.com/tasks",
+  );
+  assertThrows(
+    () => mockFetch.assertCalled("GET", "https://api.example.com/tasks"),
+    AssertionError,
+    "it was called 0 time(s)",
+  );
+});
+
+test("matches URL patterns, the route registered last wins", async () => {
+  mockFetch.route("*", /^https:\/\/api\.example\.com\//, new Response("any"));
+  mockFetch.get(new URLPattern({ pathname: "/rounds/:id" }), new Response("round"));
+
+  assertEquals(await (await fetch("https://api.example.com/rounds/1")).text(), "round");
+  assertEquals(await (await fetch("https://api.example.com/other")).text(), "any");
+  const res = await fetch("https://api.example.com/rounds/1", { method: "PUT" });
+  assertEquals(await res.text(), "any");
+});
+
+test("mocks ipfs:// retrievals", async () => {
+  const car = Uint8Array.from(atob(TEST_CAR_BASE64), (c) => c.charCodeAt(0));
+  mockFetch.get(`ipfs://${TEST_CID}`, new Response(car));
+
+  const { car: retrieved, stats } = await Zinnia.ipfs.retrieve(`ipfs://${TEST_CID}`);
+  assertEquals(retrieved, car);
+  assertEquals(stats.backend, "mock");
+  assertEquals(stats.bytesReceived, car.byteLength);
+  assertEquals(stats.success, true);
+
+  const response = await fetch(`ipfs://${TEST_CID}`);
+  const { blocks } = await Zinnia.ipfs.verifyCar(response);
+  assertEquals(blocks.map((b) => b.cid), [TEST_CID]);
+});
+
+test("limits the size of verified CAR data", async () => {
+  const car = Uint8Array.from(atob(TEST_CAR_BASE64), (c) => c.charCodeAt(0));
+  await assertRejects(
+    () => Zinnia.ipfs.verifyCar(car, { root: TEST_CID, maxBytes: 100 }),
+    Error,
+    "larger than the limit of 100 bytes",
+  );
+  await assertRejects(
+    () => Zinnia.ipfs.verifyCar(car, { root: TEST_CID, maxBytes: -1 }),
+    TypeError,
+    "maxBytes",
+  );
+});
+
+test("resets the mocks after each test", () => {
+  assertEquals(mockFetch.calls, []);
+});
@@ -1,5 +1,5 @@
 import { test } from "zinnia:test";
-import { assert, assertEquals, assertRejects } from "zinnia:assert";
+import { assert, assertArrayIncludes, assertEquals, assertRejects } from "zinnia:assert";
 
 test("fetch", async () => {
   const res = await fetch("https://google.com/");
@@ -9,6 +9,62 @@ test("fetch", async () => {
   assert(text.includes("<body"));
 });
 
+test("fetch records resource timing", async () => {
+  performance.clearResourceTimings();
+  const res = await fetch("https://google.com/");
+  await res.arrayBuffer();
+
+  const entries = performance.getEntriesByType("resource");
+  assertEquals(entries.length, 1);
+  const [entry] = entries;
+  assert(entry instanceof PerformanceResourceTiming);
+  assert(entry instanceof PerformanceEntry);
+  assertEquals(entry.name, "https://google.com/");
+  assertEquals(entry.entryType, "resource");
+  assertEquals(entry.initiatorType, "fetch");
+  assertEquals(entry.responseStatus, 200);
+  assert(entry.startTime <= entry.responseStart, "startTime <= responseStart");
+  assert(entry.responseStart <= entry.responseEnd, "responseStart <= responseEnd");
+  assertEquals(entry.duration, entry.responseEnd - entry.startTime);
+  assertArrayIncludes(["http/1.1", "h2"], [entry.nextHopProtocol]);
+  assert(entry.fetchStart <= entry.domainLookupStart, "fetchStart <= domainLookupStart");
+  assert(entry.domainLookupStart <= entry.domainLookupEnd, "domainLookupStart <= domainLookupEnd");
+  assert(entry.domainLookupEnd <= entry.connectStart, "domainLookupEnd <= connectStart");
+  assert(
+    entry.connectStart <= entry.secureConnectionStart,
+    "connectStart <= secureConnectionStart",
+  );
+  assert(entry.secureConnectionStart <= entry.connectEnd, "secureConnectionStart <= connectEnd");
+  assertEquals(entry.requestStart, entry.connectEnd);
+  assert(entry.requestStart <= entry.responseStart, "requestStart <= responseStart");
+  assert(entry.encodedBodySize > 0, "encodedBodySize > 0");
+  assert(entry.decodedBodySize > 0, "decodedBodySize > 0");
+  assertEquals(performance.getEntriesByName("https://google.com/"), [entry]);
+
+  performance.clearResourceTimings();
+  assertEquals(performance.getEntriesByType("resource"), []);
+});
+
+test("fetch records resource timing of failed requests and unread bodies", async () => {
+  performance.clearResourceTimings();
+  await assertRejects(() => fetch("https://zinnia.invalid/"));
+  const res = await fetch("https://google.com/");
+
+  const [failed, unread] = performance.getEntriesByType("resource");
+  assertEquals(failed.name, "https://zinnia.invalid/");
+  assertEquals(failed.responseStatus, 0);
+  assertEquals(failed.responseStart, 0);
+  assertEquals(unread.name, "https://google.com/");
+  assertEquals(unread.responseStatus, 200);
+  // The connection to google.com may be reused, its phases are then reported as `fetchStart`
+  assert(unread.fetchStart <= unread.connectStart, "fetchStart <= connectStart");
+  assert(unread.connectEnd <= unread.responseStart, "connectEnd <= responseStart");
+  assertEquals(unread.encodedBodySize, 0);
+
+  await res.body?.cancel();
+  performance.clearResourceTimings();
+});
+
 test("fetch from filesystem", async () => {
   const err = await assertRejects(async () => fetch("file:///etc/passwd"));
   assertEquals(err.message, "NetworkError when attempting to fetch resource");
@@ -1,5 +1,5 @@
 import { test } from "zinnia:test";
-import { assertEquals, assertMatch, assertRejects, AssertionError } from "zinnia:assert";
+import { assert, assertEquals, assertMatch, assertRejects, AssertionError } from "zinnia:assert";
 
 const TEST_CID = "bafkreih25dih6ug3xtj73vswccw423b56ilrwmnos4cbwhrceudopdp5sq";
 const FRISBII_INSTANCE_QUERY_PARAMS = "?protocol=http&providers=/dns4/frisbii.fly.dev/https";
@@ -73,13 +73,31 @@ test("does not modify original request headers - headers initialized as Headers"
   assertEquals(Array.from(request.headers.keys()), ["x-test"]);
 });
 
+test("reports retrieval metadata", async () => {
+  const requestUrl = `ipfs://${TEST_CID}?protocols=http&providers=/dns4/frisbii.fly.dev/https`;
+  const { car, stats } = await Zinnia.ipfs.retrieve(requestUrl);
+  assertEquals(car.byteLength, EXPECTED_CAR_SIZE_IN_BYTES, "CAR size in bytes");
+
+  const { startedAt, ttfbMs, durationMs, ...rest } = stats;
+  assertEquals(rest, {
+    url: requestUrl,
+    backend: "http",
+    status: 200,
+    bytesReceived: EXPECTED_CAR_SIZE_IN_BYTES,
+    success: true,
+  });
+  assert(startedAt <= Date.now(), "startedAt");
+  assert(ttfbMs <= durationMs, `ttfbMs ${ttfbMs} should not exceed durationMs ${durationMs}`);
+});
+
 test("rejects user-provided Authorization header", async () => {
   const request = new Request(`ipfs://${TEST_CID}${FRISBII_INSTANCE_QUERY_PARAMS}`, {
     headers: { Authorization: "invalid" },
   });
 
-  let error = await assertRejects(() => fetch(request));
+  let error = await assertRejects(() => fetch(request), TypeError);
   assertMatch(error.message, /authorization/i);
+  assertMatch(error.message, /lassie/i);
 });
 
 /**
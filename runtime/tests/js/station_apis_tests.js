@@ -1,5 +1,5 @@
 import { test } from "zinnia:test";
-import { assertStrictEquals } from "zinnia:assert";
+import { assertStrictEquals, assertThrows } from "zinnia:assert";
 
 test("Zinnia.walletAddress", () => {
   // Runtime JS tests are executed with the default configuration
@@ -11,3 +11,47 @@ test("Zinnia.walletAddress", () => {
 test("Zinnia.stationId", () => {
   assertStrictEquals(Zinnia.stationId, "0".repeat(88));
 });
+
+test("Zinnia.metrics rejects invalid metric names", () => {
+  assertThrows(() => Zinnia.metrics.counter("my-counter"), TypeError, "Invalid metric name");
+  assertThrows(() => Zinnia.metrics.gauge("1st"), TypeError, "Invalid metric name");
+  assertThrows(() => Zinnia.metrics.histogram(42), TypeError, "Invalid metric name");
+});
+
+test("Zinnia.metrics rejects invalid labels", () => {
+  assertThrows(
+    () => Zinnia.metrics.counter("requests", { "status-code": 200 }),
+    TypeError,
+    "Invalid label name",
+  );
+  assertThrows(() => Zinnia.metrics.counter("requests", "ok"), TypeError, "must be an object");
+  for (const label of ["module", "le", "__name__"]) {
+    assertThrows(
+      () => Zinnia.metrics.histogram("latency", { [label]: "x" }),
+      TypeError,
+      "is reserved",
+    );
+  }
+});
+
+test("Zinnia.metrics rejects invalid values", () => {
+  const counter = Zinnia.metrics.counter("requests");
+  assertThrows(() => counter.inc(-1), TypeError, "Counters cannot decrease");
+  assertThrows(() => counter.inc("1"), TypeError, "finite number");
+  assertThrows(() => Zinnia.metrics.gauge("peers").set(NaN), TypeError, "finite number");
+});
+
+test("Zinnia.telemetry is disabled by default", async () => {
+  assertStrictEquals(Zinnia.telemetry.enabled, false);
+
+  const tracer = Zinnia.telemetry.tracer("station-apis-tests");
+  const span = tracer.startSpan("disabled");
+  assertStrictEquals(span.isRecording(), false);
+  span.setAttribute("key", "value").end();
+
+  const result = await tracer.startActiveSpan("active", async (span) => {
+    span.end();
+    return "done";
+  });
+  assertStrictEquals(result, "done");
+});
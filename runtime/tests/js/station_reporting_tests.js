@@ -8,3 +8,6 @@ console.error("console.error");
 Zinnia.activity.info("activity.info");
 Zinnia.activity.error("activity.error");
 Zinnia.jobCompleted();
+Zinnia.metrics.counter("requests", { status: "ok" }).inc();
+Zinnia.metrics.gauge("peers").set(7);
+Zinnia.metrics.histogram("duration").observe(0.5);
@@ -1,15 +1,18 @@
 use std::sync::{Arc, OnceLock};
 
-use zinnia_runtime::lassie_config;
+use zinnia_runtime::{lassie_config, LassieBackend, RetrievalBackend, RetrievalConfig};
 
-pub fn lassie_daemon() -> Arc<lassie::Daemon> {
-    static LASSIE_DAEMON: OnceLock<Result<Arc<lassie::Daemon>, lassie::StartError>> =
+pub fn lassie_backend() -> Arc<dyn RetrievalBackend> {
+    static LASSIE_BACKEND: OnceLock<Result<Arc<LassieBackend>, lassie::StartError>> =
         OnceLock::new();
 
-    let result = LASSIE_DAEMON.get_or_init(|| lassie::Daemon::start(lassie_config()).map(Arc::new));
+    let result = LASSIE_BACKEND.get_or_init(|| {
+        lassie::Daemon::start(lassie_config(&RetrievalConfig::default()))
+            .map(|daemon| Arc::new(LassieBackend::new(Arc::new(daemon))))
+    });
 
     match result {
-        Ok(ptr) => Arc::clone(ptr),
+        Ok(ptr) => Arc::clone(ptr) as Arc<dyn RetrievalBackend>,
         Err(err) => panic!("could not start Lassie daemon: {err}"),
     }
 }
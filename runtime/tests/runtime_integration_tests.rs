@@ -5,6 +5,7 @@
 //   deno run runtime/tests/js/timers_tests.js
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context};
 use deno_core::ModuleSpecifier;
@@ -15,7 +16,7 @@ use zinnia_runtime::{anyhow, deno_core, run_js_module, AnyError, BootstrapOption
 use pretty_assertions::assert_eq;
 
 mod lassie_daemon;
-use lassie_daemon::lassie_daemon;
+use lassie_daemon::lassie_backend;
 
 macro_rules! js_tests(
     ( $name:ident ) => {
@@ -84,6 +85,8 @@ js_tests!(station_apis_tests);
 js_tests!(station_reporting_tests check_activity);
 js_tests!(module_loader_tests);
 js_tests!(fetch_tests);
+js_tests!(fetch_mock_tests);
+js_tests!(fake_timers_tests);
 js_tests!(ipfs_retrieval_tests);
 js_tests!(websockets_tests);
 
@@ -198,6 +201,47 @@ async fn source_code_paths_when_inside_module_root() -> Result<(), AnyError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn deterministic_mode_test() -> Result<(), AnyError> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let mut full_path = get_base_dir();
+    full_path.push("determinism_fixtures/deterministic_mode.js");
+    let main_module = deno_core::resolve_path(
+        &full_path.to_string_lossy(),
+        &std::env::current_dir().context("unable to get current working directory")?,
+    )?;
+
+    let run = |seed: u64| {
+        let main_module = main_module.clone();
+        async move {
+            let reporter = Rc::new(RecordingReporter::new());
+            let config = BootstrapOptions {
+                rng_seed: Some(seed),
+                virtual_clock_start: Some(UNIX_EPOCH + Duration::from_secs(1_704_067_200)),
+                ..BootstrapOptions::new("zinnia_runtime_tests".into(), reporter.clone(), None)
+            };
+            run_js_module(&main_module, &config).await?;
+            Ok::<_, AnyError>(reporter.events.take())
+        }
+    };
+
+    let first = run(42).await?;
+    assert_eq!(first, run(42).await?);
+    let other_seed = run(7).await?;
+    assert_ne!(&first[..2], &other_seed[..2]);
+    assert_eq!(
+        &first[2..],
+        [
+            "console.info: Date: 2024-01-01T00:00:00.000Z\n",
+            "console.info: Date(): true\n",
+            "console.info: Timers: 5 ticks, 55 ms\n",
+            "console.info: Date: 2024-01-01T00:00:00.055Z\n",
+        ]
+    );
+    Ok(())
+}
+
 // Run all tests in a single JS file
 async fn run_js_test_file(name: &str) -> Result<Vec<String>, RunFailure> {
     run_js_test_file_with_module_root(name, None).await
@@ -242,12 +286,14 @@ async fn run_js_test_file_with_module_root(
     )
     .map_err(AnyError::from)?;
     let reporter = Rc::new(RecordingReporter::new());
-    let config = BootstrapOptions::new(
-        format!("zinnia_runtime_tests/{}", env!("CARGO_PKG_VERSION")),
-        reporter.clone(),
-        lassie_daemon(),
-        module_root,
-    );
+    let config = BootstrapOptions {
+        retrieval_backend: lassie_backend(),
+        ..BootstrapOptions::new(
+            format!("zinnia_runtime_tests/{}", env!("CARGO_PKG_VERSION")),
+            reporter.clone(),
+            module_root,
+        )
+    };
     let run_result = run_js_module(&main_module, &config).await;
     let activities = reporter.events.take();
 
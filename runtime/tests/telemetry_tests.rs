@@ -0,0 +1,161 @@
+// Integration tests exporting OpenTelemetry data to a local stand-in for the OTLP collector.
+// OpenTelemetry exporters are global for the entire process, therefore these tests live in
+// their own test binary.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use assert_fs::prelude::*;
+use zinnia_runtime::{
+    anyhow, deno_core, run_js_module, BootstrapOptions, RecordingReporter, TelemetryOptions,
+};
+
+#[tokio::test]
+async fn exports_spans_and_logs() -> Result<()> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let collector = OtlpCollector::start()?;
+
+    let mod_js = assert_fs::NamedTempFile::new("telemetry-test.js")?;
+    mod_js.write_str(&format!(
+        r#"
+import {{ assert }} from "zinnia:assert";
+assert(Zinnia.telemetry.enabled, "telemetry should be enabled");
+
+const tracer = Zinnia.telemetry.tracer("telemetry-test");
+await tracer.startActiveSpan("custom-retrieval", async (span) => {{
+  const res = await fetch("http://127.0.0.1:{port}/hello");
+  await res.arrayBuffer();
+  span.end();
+}});
+
+console.log("log-from-telemetry-test");
+"#,
+        port = collector.port,
+    ))?;
+
+    let main_module = deno_core::resolve_path(
+        &mod_js.to_string_lossy(),
+        &std::env::current_dir().context("unable to get current working directory")?,
+    )?;
+    let reporter = Rc::new(RecordingReporter::new());
+    let config = BootstrapOptions {
+        telemetry: Some(TelemetryOptions {
+            export_console: true,
+            ..TelemetryOptions::new(
+                format!("http://127.0.0.1:{}", collector.port),
+                "telemetry-test".into(),
+            )
+        }),
+        ..BootstrapOptions::new("zinnia_telemetry_tests".into(), reporter.clone(), None)
+    };
+    run_js_module(&main_module, &config).await?;
+
+    let traces = collector.received("/v1/traces");
+    assert!(contains(&traces, b"custom-retrieval"), "custom span");
+    assert!(contains(&traces, b"fetch"), "fetch span");
+
+    let logs = collector.received("/v1/logs");
+    assert!(contains(&logs, b"log-from-telemetry-test"), "console log");
+
+    // The exporters are shared by all runtimes in the process, they cannot be reconfigured
+    let config = BootstrapOptions {
+        telemetry: Some(TelemetryOptions::new(
+            format!("http://127.0.0.1:{}", collector.port),
+            "another-service".into(),
+        )),
+        ..BootstrapOptions::new("zinnia_telemetry_tests".into(), reporter.clone(), None)
+    };
+    let err = run_js_module(&main_module, &config).await.unwrap_err();
+    assert!(format!("{err:#}").contains("cannot reconfigure"), "{err:#}");
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// A minimal HTTP server recording request bodies, standing in for an OTLP/HTTP collector.
+/// The server runs in a dedicated thread because the OpenTelemetry flush blocks the caller.
+struct OtlpCollector {
+    port: u16,
+    requests: Arc<Mutex<Vec<(String, Vec<u8>)>>>,
+}
+
+impl OtlpCollector {
+    fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").context("cannot listen on localhost")?;
+        let port = listener.local_addr()?.port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for socket in listener.incoming() {
+                let socket = socket.expect("cannot accept incoming connection");
+                let recorded = Arc::clone(&recorded);
+                std::thread::spawn(move || {
+                    // Errors mean the client closed the connection, there is nothing to do
+                    let _ = handle_connection(socket, &recorded);
+                });
+            }
+        });
+
+        Ok(Self { port, requests })
+    }
+
+    /// Concatenated bodies of all requests received at the given path.
+    fn received(&self, path: &str) -> Vec<u8> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(p, _)| p == path)
+            .flat_map(|(_, body)| body.clone())
+            .collect()
+    }
+}
+
+fn handle_connection(
+    socket: TcpStream,
+    recorded: &Mutex<Vec<(String, Vec<u8>)>>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(socket.try_clone()?);
+    let mut socket = socket;
+
+    // Support keep-alive connections, handle requests until the client disconnects
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or_default();
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        recorded.lock().unwrap().push((path, body));
+
+        socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+    }
+}
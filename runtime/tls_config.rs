@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use deno_core::anyhow::{anyhow, Context, Result};
+use deno_error::JsErrorBox;
+use deno_tls::rustls::{ClientConfig, RootCertStore};
+use deno_tls::{
+    create_default_root_cert_store, load_certs, load_private_keys, RootCertStoreProvider, TlsKey,
+    TlsKeys,
+};
+
+/// TLS settings for `fetch` and `WebSocket` connections and for the OpenTelemetry exporters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsConfig {
+    /// PEM files with CA certificates to trust in addition to the built-in Mozilla root
+    /// certificates, e.g. the certificate of a TLS-intercepting corporate proxy.
+    pub ca_cert_files: Vec<PathBuf>,
+
+    /// Client certificate presented to servers requiring mutual TLS. At the moment, this applies
+    /// to `fetch` and the OpenTelemetry exporters only.
+    pub client_cert: Option<ClientCertConfig>,
+
+    /// Don't verify TLS certificates of these hosts, an empty list disables the verification
+    /// for all hosts. This makes the connections vulnerable to man-in-the-middle attacks, use it
+    /// for development only.
+    pub unsafely_ignore_certificate_errors: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertConfig {
+    /// PEM file with the certificate chain.
+    pub cert_chain_file: PathBuf,
+    /// PEM file with the private key.
+    pub private_key_file: PathBuf,
+}
+
+/// Certificates and keys loaded from the files configured in [`TlsConfig`].
+pub(crate) struct LoadedTlsConfig {
+    pub root_cert_store_provider: Option<Arc<dyn RootCertStoreProvider>>,
+    pub client_cert_chain_and_key: TlsKeys,
+}
+
+impl TlsConfig {
+    /// Check that the certificate and key files can be loaded.
+    pub fn validate(&self) -> Result<()> {
+        self.load().map(|_| ())
+    }
+
+    pub(crate) fn load(&self) -> Result<LoadedTlsConfig> {
+        let mut root_cert_store = create_default_root_cert_store();
+        for path in &self.ca_cert_files {
+            for cert in read_certs(path)? {
+                root_cert_store
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+            }
+        }
+
+        let client_cert_chain_and_key = match &self.client_cert {
+            None => TlsKeys::Null,
+            Some(ClientCertConfig {
+                cert_chain_file,
+                private_key_file,
+            }) => {
+                let cert_chain = read_certs(cert_chain_file)?;
+                let key_data = std::fs::read(private_key_file).with_context(|| {
+                    format!(
+                        "Cannot read private key file {}",
+                        private_key_file.display()
+                    )
+                })?;
+                let private_key = load_private_keys(&key_data)
+                    .with_context(|| {
+                        format!("Invalid private key file {}", private_key_file.display())
+                    })?
+                    .swap_remove(0);
+                // deno_fetch panics when the key does not match the certificate, check it now
+                ClientConfig::builder()
+                    .with_root_certificates(root_cert_store.clone())
+                    .with_client_auth_cert(cert_chain.clone(), private_key.clone_key())
+                    .context("The client certificate does not match the private key")?;
+                TlsKeys::Static(TlsKey(cert_chain, private_key))
+            }
+        };
+
+        let root_cert_store_provider = if self.ca_cert_files.is_empty() {
+            None
+        } else {
+            let provider: Arc<dyn RootCertStoreProvider> =
+                Arc::new(StaticRootCertStore(root_cert_store));
+            Some(provider)
+        };
+
+        Ok(LoadedTlsConfig {
+            root_cert_store_provider,
+            client_cert_chain_and_key,
+        })
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<deno_tls::rustls::pki_types::CertificateDer<'static>>> {
+    let file = File::open(path)
+        .with_context(|| format!("Cannot read certificate file {}", path.display()))?;
+    load_certs(&mut BufReader::new(file))
+        .map_err(|err| anyhow!("Invalid certificate file {}: {err}", path.display()))
+}
+
+struct StaticRootCertStore(RootCertStore);
+
+impl RootCertStoreProvider for StaticRootCertStore {
+    fn get_or_try_init(&self) -> Result<&RootCertStore, JsErrorBox> {
+        Ok(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn loads_default_config() {
+        let loaded = TlsConfig::default().load().unwrap();
+        assert!(loaded.root_cert_store_provider.is_none());
+        assert!(matches!(loaded.client_cert_chain_and_key, TlsKeys::Null));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let not_a_cert = dir.child("not-a-cert.pem");
+        not_a_cert.write_str("hello").unwrap();
+
+        let config = TlsConfig {
+            ca_cert_files: vec![not_a_cert.to_path_buf()],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid certificate file"),
+            "{err:#}"
+        );
+
+        let config = TlsConfig {
+            ca_cert_files: vec![dir.child("missing.pem").to_path_buf()],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Cannot read certificate file"),
+            "{err:#}"
+        );
+    }
+}